@@ -0,0 +1,472 @@
+//! Tree heads as C2SP checkpoints in signed notes.
+//!
+//! A checkpoint is a short text naming the log (its *origin*), the tree
+//! size and the base64 encoded root hash, optionally followed by extension
+//! lines:
+//!
+//! ```text
+//! example.com/log
+//! 15368
+//! 2PvIu0AsYNQZqFIU5MBPHfVHF2Fnq0hs6ElIwrj0kU4=
+//! ```
+//!
+//! It is exchanged as a signed note: the text, a blank line and one
+//! signature line per signer, of the form `— <key name> <base64 signature>`.
+//! The signature is prefixed with the first four bytes of the key hash
+//! `SHA-256(<key name> || "\n" || 0x01 || <Ed25519 public key>)`, which
+//! lets verifiers skip signatures by keys they do not know. This is the
+//! format used by the Go `sumdb` and `tlog` packages and by witnesses.
+//!
+//! Witnesses add C2SP `cosignature/v1` lines. They sign
+//! `cosignature/v1\ntime <seconds>\n` followed by the checkpoint text, and
+//! their signature is prefixed with the timestamp as a big endian `u64`.
+//! The key hash uses the signature type `0x04` instead of `0x01`.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use digest::{Digest, Hash};
+use merkle::TreeHead;
+use proof::VerificationError;
+use ring::digest as ring_digest;
+use ring::signature::ED25519_PUBLIC_KEY_LEN;
+use signed_merkle::{KeyPair, PubKey};
+use std::error::Error;
+use std::{fmt, mem};
+
+/// Ed25519 signature type of the signed note format.
+const ALG_ED25519: u8 = 0x01;
+/// Ed25519 `cosignature/v1` signature type of the signed note format.
+const ALG_COSIGNATURE_V1: u8 = 0x04;
+/// Start of every signature line.
+const SIG_PREFIX: &str = "\u{2014} ";
+
+/// Reasons for a note, checkpoint or verifier key to be rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteError {
+    /// A key name is empty or contains whitespace or `+`.
+    InvalidName,
+    /// An origin or extension line is empty or spans several lines.
+    InvalidLine,
+    /// The note is not made up of text, a blank line and signatures.
+    MalformedNote,
+    /// The note text is not a checkpoint for the expected hash function.
+    MalformedCheckpoint,
+    /// A verifier key cannot be parsed or is not an Ed25519 key.
+    MalformedKey,
+}
+
+impl fmt::Display for NoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            NoteError::InvalidName => "invalid key name",
+            NoteError::InvalidLine => "invalid checkpoint line",
+            NoteError::MalformedNote => "malformed signed note",
+            NoteError::MalformedCheckpoint => "malformed checkpoint",
+            NoteError::MalformedKey => "malformed verifier key",
+        })
+    }
+}
+
+impl Error for NoteError {}
+
+/// The tree head of the log `origin`, in checkpoint form.
+pub struct Checkpoint<D: Digest> {
+    origin: String,
+    th: TreeHead<D>,
+    extensions: Vec<String>,
+}
+
+impl<D: Digest> Clone for Checkpoint<D> {
+    fn clone(&self) -> Self {
+        Checkpoint {
+            origin: self.origin.clone(),
+            th: self.th.clone(),
+            extensions: self.extensions.clone(),
+        }
+    }
+}
+
+impl<D: Digest> Checkpoint<D> {
+    pub fn new(origin: &str, th: TreeHead<D>) -> Result<Self, NoteError> {
+        check_line(origin)?;
+        Ok(Checkpoint {
+            origin: origin.to_string(),
+            th,
+            extensions: Vec::new(),
+        })
+    }
+
+    /// Append an extension line. Its meaning is up to the log.
+    pub fn add_extension(&mut self, line: &str) -> Result<(), NoteError> {
+        check_line(line)?;
+        self.extensions.push(line.to_string());
+        Ok(())
+    }
+
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    pub fn tree_head(&self) -> &TreeHead<D> {
+        &self.th
+    }
+
+    pub fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    /// The checkpoint text, ending in a newline.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{}\n{}\n{}\n",
+            self.origin,
+            self.th.size(),
+            BASE64.encode(self.th.root_hash())
+        );
+        for line in &self.extensions {
+            text.push_str(line);
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Parse a checkpoint text as produced by `to_text`.
+    pub fn from_text(text: &str) -> Result<Self, NoteError> {
+        if !text.ends_with('\n') {
+            return Err(NoteError::MalformedCheckpoint);
+        }
+        let mut lines = text[..text.len() - 1].split('\n');
+        let mut next = || lines.next().ok_or(NoteError::MalformedCheckpoint);
+
+        let origin = next()?;
+        let size = next()?;
+        let root = next()?;
+        let size = match size.parse::<u64>() {
+            Ok(n) if n.to_string() == size => n,
+            _ => return Err(NoteError::MalformedCheckpoint),
+        };
+        let root = BASE64.decode(root)
+            .map_err(|_| NoteError::MalformedCheckpoint)?;
+        if root.len() != mem::size_of::<Hash<D>>() {
+            return Err(NoteError::MalformedCheckpoint);
+        }
+
+        let th = TreeHead::new(size, Hash::<D>::clone_from_slice(&root));
+        let mut cp = Checkpoint::new(origin, th)
+            .map_err(|_| NoteError::MalformedCheckpoint)?;
+        while let Ok(line) = next() {
+            cp.add_extension(line)
+                .map_err(|_| NoteError::MalformedCheckpoint)?;
+        }
+        Ok(cp)
+    }
+}
+
+/// One signature line of a signed note.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NoteSignature {
+    name: String,
+    key_hash: u32,
+    sig: Vec<u8>,
+}
+
+impl NoteSignature {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn key_hash(&self) -> u32 {
+        self.key_hash
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        &self.sig
+    }
+
+    fn to_line(&self) -> String {
+        let mut bytes = be_u32(self.key_hash).to_vec();
+        bytes.extend_from_slice(&self.sig);
+        format!("{}{} {}\n", SIG_PREFIX, self.name, BASE64.encode(&bytes))
+    }
+
+    fn from_line(line: &str) -> Result<Self, NoteError> {
+        if !line.starts_with(SIG_PREFIX) {
+            return Err(NoteError::MalformedNote);
+        }
+        let mut parts = line[SIG_PREFIX.len()..].split(' ');
+        let (name, sig) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(sig), None) => (name, sig),
+            _ => return Err(NoteError::MalformedNote),
+        };
+        check_name(name).map_err(|_| NoteError::MalformedNote)?;
+        let bytes =
+            BASE64.decode(sig).map_err(|_| NoteError::MalformedNote)?;
+        if bytes.len() < 5 {
+            return Err(NoteError::MalformedNote);
+        }
+        Ok(NoteSignature {
+            name: name.to_string(),
+            key_hash: from_be_u32(&bytes[..4]),
+            sig: bytes[4..].to_vec(),
+        })
+    }
+}
+
+/// A checkpoint together with the signatures on its text.
+pub struct SignedCheckpoint<D: Digest> {
+    checkpoint: Checkpoint<D>,
+    signatures: Vec<NoteSignature>,
+}
+
+impl<D: Digest> Clone for SignedCheckpoint<D> {
+    fn clone(&self) -> Self {
+        SignedCheckpoint {
+            checkpoint: self.checkpoint.clone(),
+            signatures: self.signatures.clone(),
+        }
+    }
+}
+
+impl<D: Digest> SignedCheckpoint<D> {
+    /// A note for `checkpoint` without any signatures yet.
+    pub fn new(checkpoint: Checkpoint<D>) -> Self {
+        SignedCheckpoint {
+            checkpoint,
+            signatures: Vec::new(),
+        }
+    }
+
+    /// Add a signature by `keypair` under the key name `name`.
+    pub fn sign(
+        &mut self,
+        name: &str,
+        keypair: &KeyPair,
+    ) -> Result<(), NoteError> {
+        check_name(name)?;
+        let sig = keypair.sign(self.checkpoint.to_text().as_bytes());
+        self.add_signature(name, &keypair.pub_key(), sig.as_ref().to_vec());
+        Ok(())
+    }
+
+    /// Add the signature `sig` on the checkpoint text by `pubkey`, made
+    /// elsewhere. Neither the name nor the signature are checked.
+    pub(crate) fn add_signature(
+        &mut self,
+        name: &str,
+        pubkey: &PubKey,
+        sig: Vec<u8>,
+    ) {
+        self.signatures.push(NoteSignature {
+            name: name.to_string(),
+            key_hash: key_hash(name, pubkey),
+            sig,
+        });
+    }
+
+    /// Add a `cosignature/v1` by `keypair` under the key name `name`,
+    /// made at `timestamp` seconds since the UNIX epoch. Use a `Witness`
+    /// to only cosign checkpoints consistent with earlier ones.
+    pub(crate) fn cosign(
+        &mut self,
+        name: &str,
+        keypair: &KeyPair,
+        timestamp: u64,
+    ) -> Result<(), NoteError> {
+        check_name(name)?;
+        let msg = self.cosigned_text(timestamp);
+        let mut sig = be_u64(timestamp).to_vec();
+        sig.extend_from_slice(keypair.sign(msg.as_bytes()).as_ref());
+        self.signatures.push(NoteSignature {
+            name: name.to_string(),
+            key_hash: cosignature_key_hash(name, &keypair.pub_key()),
+            sig,
+        });
+        Ok(())
+    }
+
+    pub fn checkpoint(&self) -> &Checkpoint<D> {
+        &self.checkpoint
+    }
+
+    pub fn signatures(&self) -> &[NoteSignature] {
+        &self.signatures
+    }
+
+    /// Check that the key `pubkey` named `name` signed the checkpoint.
+    /// Signatures by other keys are ignored.
+    pub fn verify(
+        &self,
+        name: &str,
+        pubkey: &PubKey,
+    ) -> Result<(), VerificationError> {
+        let hash = key_hash(name, pubkey);
+        let text = self.checkpoint.to_text();
+        let valid = self
+            .signatures
+            .iter()
+            .filter(|s| s.name == name && s.key_hash == hash)
+            .any(|s| pubkey.verify_sig(text.as_bytes(), &s.sig));
+        if valid {
+            Ok(())
+        } else {
+            Err(VerificationError::BadSignature)
+        }
+    }
+
+    /// Check that the witness key `pubkey` named `name` cosigned the
+    /// checkpoint, and return the time of the cosignature in seconds since
+    /// the UNIX epoch. Other signatures are ignored.
+    pub fn verify_cosignature(
+        &self,
+        name: &str,
+        pubkey: &PubKey,
+    ) -> Result<u64, VerificationError> {
+        let hash = cosignature_key_hash(name, pubkey);
+        self.signatures
+            .iter()
+            .filter(|s| s.name == name && s.key_hash == hash)
+            .filter(|s| s.sig.len() > 8)
+            .map(|s| (from_be_u64(&s.sig[..8]), &s.sig[8..]))
+            .find(|&(timestamp, sig)| {
+                let msg = self.cosigned_text(timestamp);
+                pubkey.verify_sig(msg.as_bytes(), sig)
+            })
+            .map(|(timestamp, _)| timestamp)
+            .ok_or(VerificationError::BadSignature)
+    }
+
+    fn cosigned_text(&self, timestamp: u64) -> String {
+        format!(
+            "cosignature/v1\ntime {}\n{}",
+            timestamp,
+            self.checkpoint.to_text()
+        )
+    }
+
+    /// The signed note: checkpoint text, blank line, signature lines.
+    pub fn to_note(&self) -> String {
+        let mut note = self.checkpoint.to_text();
+        note.push('\n');
+        for s in &self.signatures {
+            note.push_str(&s.to_line());
+        }
+        note
+    }
+
+    /// Parse a signed note holding a checkpoint. The signatures are not
+    /// checked, use `verify` for that.
+    pub fn from_note(note: &str) -> Result<Self, NoteError> {
+        let split = match note.rfind("\n\n") {
+            Some(i) => i + 1,
+            None => return Err(NoteError::MalformedNote),
+        };
+        let (text, sigs) = (&note[..split], &note[split + 1..]);
+        if sigs.is_empty() || !sigs.ends_with('\n') {
+            return Err(NoteError::MalformedNote);
+        }
+        let signatures = sigs[..sigs.len() - 1]
+            .split('\n')
+            .map(NoteSignature::from_line)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SignedCheckpoint {
+            checkpoint: Checkpoint::from_text(text)?,
+            signatures,
+        })
+    }
+}
+
+/// The key hash identifying the key `pubkey` named `name` in signature
+/// lines.
+pub fn key_hash(name: &str, pubkey: &PubKey) -> u32 {
+    typed_key_hash(name, ALG_ED25519, pubkey)
+}
+
+/// The key hash identifying the witness key `pubkey` named `name` in
+/// cosignature lines.
+pub fn cosignature_key_hash(name: &str, pubkey: &PubKey) -> u32 {
+    typed_key_hash(name, ALG_COSIGNATURE_V1, pubkey)
+}
+
+fn typed_key_hash(name: &str, alg: u8, pubkey: &PubKey) -> u32 {
+    let mut ctx = ring_digest::Context::new(&ring_digest::SHA256);
+    ctx.update(name.as_bytes());
+    ctx.update(b"\n");
+    ctx.update(&[alg]);
+    ctx.update(pubkey.as_bytes());
+    from_be_u32(&ctx.finish().as_ref()[..4])
+}
+
+/// The verifier key `<name>+<key hash>+<key>` as used by the Go `note`
+/// package to configure trusted keys.
+pub fn verifier_key(
+    name: &str,
+    pubkey: &PubKey,
+) -> Result<String, NoteError> {
+    check_name(name)?;
+    let mut key = vec![ALG_ED25519];
+    key.extend_from_slice(pubkey.as_bytes());
+    Ok(format!(
+        "{}+{:08x}+{}",
+        name,
+        key_hash(name, pubkey),
+        BASE64.encode(&key)
+    ))
+}
+
+/// Parse a verifier key produced by `verifier_key`.
+pub fn parse_verifier_key(
+    vkey: &str,
+) -> Result<(String, PubKey), NoteError> {
+    // The base64 key itself may contain `+`.
+    let mut parts = vkey.splitn(3, '+');
+    let (name, hash, key) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(name), Some(hash), Some(key)) => (name, hash, key),
+        _ => return Err(NoteError::MalformedKey),
+    };
+    check_name(name).map_err(|_| NoteError::MalformedKey)?;
+    let key = BASE64.decode(key).map_err(|_| NoteError::MalformedKey)?;
+    if key.len() != 1 + ED25519_PUBLIC_KEY_LEN || key[0] != ALG_ED25519 {
+        return Err(NoteError::MalformedKey);
+    }
+    let pubkey = PubKey::new(&key[1..]);
+    if hash.len() != 8 || format!("{:08x}", key_hash(name, &pubkey)) != hash
+    {
+        return Err(NoteError::MalformedKey);
+    }
+    Ok((name.to_string(), pubkey))
+}
+
+pub(crate) fn check_name(name: &str) -> Result<(), NoteError> {
+    let invalid = |c: char| c.is_whitespace() || c == '+';
+    if name.is_empty() || name.contains(invalid) {
+        return Err(NoteError::InvalidName);
+    }
+    Ok(())
+}
+
+fn check_line(line: &str) -> Result<(), NoteError> {
+    if line.is_empty() || line.contains('\n') {
+        return Err(NoteError::InvalidLine);
+    }
+    Ok(())
+}
+
+fn be_u32(x: u32) -> [u8; 4] {
+    [(x >> 24) as u8, (x >> 16) as u8, (x >> 8) as u8, x as u8]
+}
+
+fn from_be_u32(b: &[u8]) -> u32 {
+    b[..4].iter().fold(0, |acc, &x| (acc << 8) | u32::from(x))
+}
+
+fn be_u64(x: u64) -> [u8; 8] {
+    let mut b = [0; 8];
+    for (i, byte) in b.iter_mut().enumerate() {
+        *byte = (x >> (56 - 8 * i)) as u8;
+    }
+    b
+}
+
+fn from_be_u64(b: &[u8]) -> u64 {
+    b[..8].iter().fold(0, |acc, &x| (acc << 8) | u64::from(x))
+}
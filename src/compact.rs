@@ -0,0 +1,176 @@
+use digest::{AsHash, Digest, Digestible, Hash};
+use merkle::{MerkleTree, OwningMerkleTree, TreeHead, READ_FAILED};
+use proof::AsMerkleTree;
+use std::{io, iter};
+use storage::Storage;
+
+/// An append-only Merkle tree that only remembers its right edge.
+///
+/// For every set bit in the number of elements the tree keeps the root of
+/// the corresponding perfect subtree, so memory use is logarithmic in the
+/// size of the tree. This is enough to compute the same tree heads as a
+/// `MerkleTree`, but not to produce proofs or to detect duplicates.
+///
+/// Deserializing a tree fails if its frontier does not have one hash per
+/// set bit of its size.
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CompactMerkleTree<D: Digest> {
+    count: u64,
+    /// Roots of the perfect subtrees, largest (leftmost) first. There is
+    /// one for every set bit of `count`.
+    #[cfg_attr(feature = "serde", serde(bound = ""))]
+    frontier: Vec<Hash<D>>,
+}
+
+/// The fields of a `CompactMerkleTree` as they are serialized, before they
+/// are checked.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct CompactTreeParts<D: Digest> {
+    count: u64,
+    #[serde(bound = "")]
+    frontier: Vec<Hash<D>>,
+}
+
+#[cfg(feature = "serde")]
+impl<'de, D: Digest> ::serde::Deserialize<'de> for CompactMerkleTree<D> {
+    fn deserialize<De>(deserializer: De) -> Result<Self, De::Error>
+    where
+        De: ::serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let parts = CompactTreeParts::<D>::deserialize(deserializer)?;
+        if parts.frontier.len() != parts.count.count_ones() as usize {
+            return Err(De::Error::custom(
+                "frontier does not match the tree size",
+            ));
+        }
+        Ok(CompactMerkleTree {
+            count: parts.count,
+            frontier: parts.frontier,
+        })
+    }
+}
+
+impl<D: Digest> Clone for CompactMerkleTree<D> {
+    fn clone(&self) -> Self {
+        CompactMerkleTree {
+            count: self.count,
+            frontier: self.frontier.clone(),
+        }
+    }
+}
+
+impl<D: Digest> CompactMerkleTree<D> {
+    pub fn new() -> CompactMerkleTree<D> {
+        CompactMerkleTree {
+            count: 0,
+            frontier: Vec::new(),
+        }
+    }
+
+    /// Append an element. Unlike `MerkleTree::insert` duplicates are not
+    /// detected.
+    pub fn insert<H: AsHash<D>>(&mut self, hash: H) {
+        // Every trailing one bit of the old count is a perfect subtree of
+        // the same size as the one just completed, so merge them, smallest
+        // first. They are the last ones of the frontier, which has a
+        // subtree for every one bit.
+        let merged = (!self.count).trailing_zeros() as usize;
+        let keep = self.frontier.len() - merged;
+        let leaf = D::hash_leaf(&hash.as_hash());
+        let node = self
+            .frontier
+            .drain(keep..)
+            .rev()
+            .fold(leaf, |right, left| D::hash_inner(&left, &right));
+        self.frontier.push(node);
+        self.count += 1;
+    }
+
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn head(&self) -> TreeHead<D> {
+        let mut hashes = self.frontier.iter().rev();
+        let hash = match hashes.next() {
+            Some(last) => hashes
+                .fold(last.clone(), |acc, h| D::hash_inner(h, &acc)),
+            None => D::default().fixed_result(),
+        };
+        TreeHead::new(self.count, hash)
+    }
+
+    /// The right edge of the tree, from the largest perfect subtree to the
+    /// smallest.
+    pub fn frontier(&self) -> &[Hash<D>] {
+        &self.frontier
+    }
+
+    /// The right edge of `mt`, like `From` builds it, but reporting
+    /// storage errors instead of panicking.
+    pub fn try_from_tree<S: Storage<D>>(
+        mt: &MerkleTree<D, S>,
+    ) -> io::Result<Self> {
+        Self::from_tree(mt)
+    }
+
+    fn from_tree<M: AsMerkleTree<D>>(mt: &M) -> io::Result<Self> {
+        let mt = mt.as_merkle_tree();
+        let count = mt.len();
+        let mut frontier = Vec::new();
+        let mut offset = 0;
+        for bit in (0..64).rev() {
+            let size = 1u64 << bit;
+            if count & size != 0 {
+                frontier.push(mt.hash_from_range(offset, offset + size - 1)?);
+                offset += size;
+            }
+        }
+        Ok(CompactMerkleTree { count, frontier })
+    }
+}
+
+impl<D: Digest> Default for CompactMerkleTree<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Digest, S: Storage<D>> From<&MerkleTree<D, S>>
+    for CompactMerkleTree<D>
+{
+    fn from(mt: &MerkleTree<D, S>) -> Self {
+        Self::from_tree(mt).expect(READ_FAILED)
+    }
+}
+
+impl<T: Digestible, D: Digest> From<&OwningMerkleTree<T, D>>
+    for CompactMerkleTree<D>
+{
+    fn from(omt: &OwningMerkleTree<T, D>) -> Self {
+        Self::from_tree(omt).expect(READ_FAILED)
+    }
+}
+
+impl<D: Digest, H: AsHash<D>> iter::FromIterator<H> for CompactMerkleTree<D> {
+    fn from_iter<T: IntoIterator<Item = H>>(iter: T) -> Self {
+        let mut cmt = CompactMerkleTree::new();
+        cmt.extend(iter);
+        cmt
+    }
+}
+
+impl<D: Digest, H: AsHash<D>> iter::Extend<H> for CompactMerkleTree<D> {
+    fn extend<T: IntoIterator<Item = H>>(&mut self, iter: T) {
+        for x in iter {
+            self.insert(x);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Just enough DER to find the parts of key documents that ring does not
+//! hand out, and to encode SubjectPublicKeyInfo documents.
+
+use untrusted;
+
+pub(crate) const INTEGER: u8 = 0x02;
+pub(crate) const BIT_STRING: u8 = 0x03;
+pub(crate) const OCTET_STRING: u8 = 0x04;
+pub(crate) const NULL: u8 = 0x05;
+pub(crate) const OID: u8 = 0x06;
+pub(crate) const SEQUENCE: u8 = 0x30;
+/// The context specific, constructed tags `[0]` and `[1]`.
+pub(crate) const CONTEXT_0: u8 = 0xa0;
+pub(crate) const CONTEXT_1: u8 = 0xa1;
+
+/// The input is not the expected DER encoding.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Malformed;
+
+impl From<untrusted::EndOfInput> for Malformed {
+    fn from(_: untrusted::EndOfInput) -> Self {
+        Malformed
+    }
+}
+
+/// Read an element with tag `tag` and return its contents.
+pub(crate) fn expect<'a>(
+    input: &mut untrusted::Reader<'a>,
+    tag: u8,
+) -> Result<untrusted::Input<'a>, Malformed> {
+    optional(input, tag)?.ok_or(Malformed)
+}
+
+/// Read an element if it has tag `tag`, and return its contents.
+pub(crate) fn optional<'a>(
+    input: &mut untrusted::Reader<'a>,
+    tag: u8,
+) -> Result<Option<untrusted::Input<'a>>, Malformed> {
+    if !input.peek(tag) {
+        return Ok(None);
+    }
+    input.read_byte()?;
+    // Lengths from 128 on take one or two more bytes, without leading
+    // zeros. Key documents are never longer.
+    let len = match input.read_byte()? {
+        n if n < 0x80 => usize::from(n),
+        0x81 => match input.read_byte()? {
+            n if n >= 0x80 => usize::from(n),
+            _ => return Err(Malformed),
+        },
+        0x82 => {
+            let high = usize::from(input.read_byte()?);
+            let len = (high << 8) | usize::from(input.read_byte()?);
+            if len < 0x100 {
+                return Err(Malformed);
+            }
+            len
+        }
+        _ => return Err(Malformed),
+    };
+    Ok(Some(input.skip_and_get_input(len)?))
+}
+
+/// Read an element with tag `tag` and decode its contents with `f`, which
+/// has to consume all of them.
+pub(crate) fn nested<'a, F, R>(
+    input: &mut untrusted::Reader<'a>,
+    tag: u8,
+    f: F,
+) -> Result<R, Malformed>
+where
+    F: FnOnce(&mut untrusted::Reader<'a>) -> Result<R, Malformed>,
+{
+    expect(input, tag)?.read_all(Malformed, f)
+}
+
+/// Read a BIT STRING without unused bits and return the bits.
+pub(crate) fn bit_string<'a>(
+    input: &mut untrusted::Reader<'a>,
+) -> Result<untrusted::Input<'a>, Malformed> {
+    nested(input, BIT_STRING, |bits| {
+        if bits.read_byte()? != 0 {
+            return Err(Malformed);
+        }
+        Ok(bits.skip_to_end())
+    })
+}
+
+/// Encode an element with tag `tag` around `contents`, which must not be
+/// longer than 65535 bytes.
+pub(crate) fn encode(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let len = contents.len();
+    let mut der = vec![tag];
+    if len < 0x80 {
+        der.push(len as u8);
+    } else if len < 0x100 {
+        der.extend_from_slice(&[0x81, len as u8]);
+    } else {
+        assert!(len <= 0xffff, "DER element too long");
+        der.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]);
+    }
+    der.extend_from_slice(contents);
+    der
+}
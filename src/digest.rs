@@ -11,7 +11,7 @@ pub use extern_digest::Input;
 
 /// Digestible
 pub trait Digestible: Eq + hash::Hash {
-    fn hash_bytes(&self, digest: &mut extern_digest::Input);
+    fn hash_bytes(&self, digest: &mut dyn extern_digest::Input);
 }
 
 pub trait Digest: extern_digest::Digest {
@@ -47,11 +47,12 @@ where
 }
 
 impl<T: AsRef<[u8]> + Eq + hash::Hash> Digestible for T {
-    fn hash_bytes(&self, digest: &mut extern_digest::Input) {
+    fn hash_bytes(&self, digest: &mut dyn extern_digest::Input) {
         digest.process(self.as_ref());
     }
 }
 
+#[allow(clippy::wrong_self_convention)]
 pub trait AsHash<D: Digest> {
     fn as_hash(self) -> Hash<D>;
 }
@@ -62,7 +63,7 @@ impl<D: Digest> AsHash<D> for Hash<D> {
     }
 }
 
-impl<'a, T: Digestible, D: Digest> AsHash<D> for &'a T {
+impl<T: Digestible, D: Digest> AsHash<D> for &T {
     fn as_hash(self) -> Hash<D> {
         D::hash_elem(self)
     }
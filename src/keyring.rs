@@ -0,0 +1,132 @@
+//! Log identifiers and sets of trusted log keys.
+//!
+//! As in RFC 6962, a log is identified by the SHA-256 hash of its public
+//! key as a DER encoded SubjectPublicKeyInfo. Signed tree heads carry the
+//! `LogId` of the key that signed them, so a client trusting several logs
+//! can keep their keys in a `KeyRing` and have each head checked with the
+//! right one.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ring::digest;
+use signer::Verifier;
+use std::collections::{hash_map, HashMap};
+use std::fmt;
+
+/// Length of a log ID.
+pub const LOG_ID_LEN: usize = 32;
+
+/// Identifies a log by the SHA-256 hash of its DER encoded public key.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LogId([u8; LOG_ID_LEN]);
+
+impl LogId {
+    /// The ID of the key `der`, a SubjectPublicKeyInfo document.
+    pub fn from_spki_der(der: &[u8]) -> Self {
+        let hash = digest::digest(&digest::SHA256, der);
+        let mut id = [0; LOG_ID_LEN];
+        id.copy_from_slice(hash.as_ref());
+        LogId(id)
+    }
+
+    /// The ID with the encoding `bytes`, if it has the right length.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != LOG_ID_LEN {
+            return None;
+        }
+        let mut id = [0; LOG_ID_LEN];
+        id.copy_from_slice(bytes);
+        Some(LogId(id))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Log IDs are shown in base64, as CT log lists do.
+impl fmt::Display for LogId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&BASE64.encode(self.0))
+    }
+}
+
+/// Finds the key of a log by its ID. A single `Verifier` only knows its
+/// own ID; a `KeyRing` knows all of its keys.
+pub trait KeySource {
+    type Key: Verifier;
+
+    fn key_for(&self, log_id: &LogId) -> Option<&Self::Key>;
+}
+
+impl<V: Verifier> KeySource for V {
+    type Key = V;
+
+    fn key_for(&self, log_id: &LogId) -> Option<&V> {
+        if self.log_id() == *log_id {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+/// Trusted log keys, looked up by their `LogId`.
+pub struct KeyRing<V: Verifier = ::PubKey> {
+    keys: HashMap<LogId, V>,
+}
+
+impl<V: Verifier> KeyRing<V> {
+    pub fn new() -> Self {
+        KeyRing {
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Trust `key`, returning its log ID.
+    pub fn add(&mut self, key: V) -> LogId {
+        let id = key.log_id();
+        self.keys.insert(id, key);
+        id
+    }
+
+    /// Stop trusting the key of `log_id`, returning it.
+    pub fn remove(&mut self, log_id: &LogId) -> Option<V> {
+        self.keys.remove(log_id)
+    }
+
+    pub fn get(&self, log_id: &LogId) -> Option<&V> {
+        self.keys.get(log_id)
+    }
+
+    pub fn contains(&self, log_id: &LogId) -> bool {
+        self.keys.contains_key(log_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, LogId, V> {
+        self.keys.iter()
+    }
+}
+
+impl<V: Verifier> Default for KeyRing<V> {
+    fn default() -> Self {
+        KeyRing::new()
+    }
+}
+
+impl<V: Verifier> KeySource for KeyRing<V> {
+    type Key = V;
+
+    fn key_for(&self, log_id: &LogId) -> Option<&V> {
+        self.get(log_id)
+    }
+}
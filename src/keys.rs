@@ -0,0 +1,360 @@
+//! Importing and exporting Ed25519 keys in the usual formats: PKCS#8 and
+//! SubjectPublicKeyInfo documents, as DER or PEM (RFC 8410), and raw
+//! 32 byte seeds and public keys.
+//!
+//! Private keys that have to be stored somewhere can be encrypted with a
+//! password into an `EncryptedKeyPair`. The key for AES-256-GCM is derived
+//! from the password with PBKDF2-HMAC-SHA256 and a random salt.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use der;
+use keyring::LogId;
+use ring::{aead, digest, pbkdf2};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{self, Ed25519KeyPair};
+use signed_merkle::{KeyPair, PubKey, RingError};
+use std::error::Error;
+use std::fmt;
+use untrusted;
+
+/// Length of an Ed25519 private key seed.
+pub(crate) const SEED_LEN: usize = 32;
+
+/// Everything in a PKCS#8 v2 document in front of the seed. The version is
+/// 1 since the public key follows.
+const PKCS8_V2_PREFIX: [u8; 16] = [
+    0x30, 0x53, 0x02, 0x01, 0x01, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70,
+    0x04, 0x22, 0x04, 0x20,
+];
+/// Between the seed and the public key of a PKCS#8 v2 document.
+const PKCS8_V2_MIDDLE: [u8; 5] = [0xa1, 0x23, 0x03, 0x21, 0x00];
+/// Everything in a SubjectPublicKeyInfo in front of the public key.
+pub(crate) const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+const PEM_PRIVATE_KEY: &str = "PRIVATE KEY";
+const PEM_PUBLIC_KEY: &str = "PUBLIC KEY";
+
+/// Version of the `EncryptedKeyPair` encoding.
+const ENCRYPTED_KEY_V1: u8 = 1;
+/// PBKDF2 iterations for newly encrypted keys.
+const PBKDF2_ITERATIONS: u32 = 100_000;
+/// Most PBKDF2 iterations accepted when decrypting. The count is only
+/// authenticated after the key has been derived, so without a bound a
+/// tampered container could keep `decrypt` busy for days.
+const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// Length of the encoding up to the ciphertext: version, iterations, salt
+/// and nonce. All of it is authenticated along with the ciphertext.
+const ENCRYPTED_KEY_HEADER_LEN: usize = 1 + 4 + SALT_LEN + NONCE_LEN;
+
+/// Reasons for a key to be rejected on import.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyError {
+    /// There is no PEM block with the expected label, or it is not valid
+    /// base64.
+    InvalidPem,
+    /// The DER document is not an Ed25519 PKCS#8 or SubjectPublicKeyInfo
+    /// document, or the public key in a PKCS#8 document does not belong to
+    /// the private key.
+    InvalidDer,
+    /// A raw seed or public key has the wrong length, or an encrypted key
+    /// is malformed.
+    InvalidKey,
+    /// An encrypted key could not be decrypted, either because the password
+    /// is wrong or because the key was tampered with.
+    WrongPassword,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            KeyError::InvalidPem => "invalid PEM document",
+            KeyError::InvalidDer => "invalid DER encoded Ed25519 key",
+            KeyError::InvalidKey => "invalid Ed25519 key",
+            KeyError::WrongPassword => "wrong password or corrupted key",
+        })
+    }
+}
+
+impl Error for KeyError {}
+
+impl KeyPair {
+    /// Load a key pair from a PKCS#8 v1 or v2 document. The public key of
+    /// a v2 document is checked against the private key. Documents with
+    /// attributes are rejected.
+    pub fn from_pkcs8_der(der: &[u8]) -> Result<Self, KeyError> {
+        let kp = Ed25519KeyPair::from_pkcs8_maybe_unchecked(
+            untrusted::Input::from(der),
+        ).map_err(|_| KeyError::InvalidDer)?;
+        // ring does not hand out the seed.
+        let seed = pkcs8_seed(der).map_err(|_| KeyError::InvalidDer)?;
+        Ok(KeyPair::from_parts(kp, seed))
+    }
+
+    /// Load a key pair from the first `PRIVATE KEY` block of a PEM file.
+    pub fn from_pem(pem: &str) -> Result<Self, KeyError> {
+        Self::from_pkcs8_der(&pem_decode(pem, PEM_PRIVATE_KEY)?)
+    }
+
+    /// The key pair derived from a 32 byte seed, as used by most Ed25519
+    /// implementations to store private keys.
+    pub fn from_seed(seed: &[u8]) -> Result<Self, KeyError> {
+        if seed.len() != SEED_LEN {
+            return Err(KeyError::InvalidKey);
+        }
+        let kp = Ed25519KeyPair::from_seed_unchecked(untrusted::Input::from(
+            seed,
+        )).map_err(|_| KeyError::InvalidKey)?;
+        Ok(KeyPair::from_parts(kp, seed))
+    }
+
+    /// The private key seed. Handle with care.
+    pub fn to_seed(&self) -> [u8; SEED_LEN] {
+        *self.seed()
+    }
+
+    /// The key pair as a PKCS#8 v2 document including the public key, the
+    /// format `new_from_bytes` accepts.
+    pub fn to_pkcs8_der(&self) -> Vec<u8> {
+        let mut der = PKCS8_V2_PREFIX.to_vec();
+        der.extend_from_slice(self.seed());
+        der.extend_from_slice(&PKCS8_V2_MIDDLE);
+        der.extend_from_slice(self.pub_key().as_bytes());
+        debug_assert_eq!(der.len(), signature::ED25519_PKCS8_V2_LEN);
+        der
+    }
+
+    pub fn to_pkcs8_pem(&self) -> String {
+        pem_encode(&self.to_pkcs8_der(), PEM_PRIVATE_KEY)
+    }
+
+    /// Encrypt the key pair with `password`, for storing it somewhere the
+    /// PKCS#8 document should not end up in the clear.
+    pub fn encrypt(
+        &self,
+        password: &[u8],
+    ) -> Result<EncryptedKeyPair, RingError> {
+        let rng = SystemRandom::new();
+        let mut salt = [0; SALT_LEN];
+        let mut nonce = [0; NONCE_LEN];
+        rng.fill(&mut salt)?;
+        rng.fill(&mut nonce)?;
+        let mut enc = EncryptedKeyPair {
+            iterations: PBKDF2_ITERATIONS,
+            salt: salt.to_vec(),
+            nonce: nonce.to_vec(),
+            ciphertext: Vec::new(),
+        };
+        let key = enc.derive_key(password);
+        let key = aead::SealingKey::new(&aead::AES_256_GCM, &key)?;
+        let mut in_out = self.to_pkcs8_der();
+        let tag_len = aead::AES_256_GCM.tag_len();
+        in_out.resize(in_out.len() + tag_len, 0);
+        let len = aead::seal_in_place(
+            &key,
+            &nonce,
+            &enc.header(),
+            &mut in_out,
+            tag_len,
+        )?;
+        in_out.truncate(len);
+        enc.ciphertext = in_out;
+        Ok(enc)
+    }
+}
+
+/// The seed in the `CurvePrivateKey` of a PKCS#8 document (RFC 8410).
+fn pkcs8_seed(pkcs8: &[u8]) -> Result<&[u8], der::Malformed> {
+    let seed = untrusted::Input::from(pkcs8).read_all(der::Malformed, |doc| {
+        der::nested(doc, der::SEQUENCE, |info| {
+            der::expect(info, der::INTEGER)?;
+            der::expect(info, der::SEQUENCE)?;
+            let seed = der::nested(info, der::OCTET_STRING, |key| {
+                der::expect(key, der::OCTET_STRING)
+            })?;
+            // The public key of a v2 document may follow.
+            info.skip_to_end();
+            Ok(seed)
+        })
+    })?;
+    if seed.len() != SEED_LEN {
+        return Err(der::Malformed);
+    }
+    Ok(seed.as_slice_less_safe())
+}
+
+/// A key pair encrypted with a password, see `KeyPair::encrypt`.
+///
+/// The binary encoding of `to_bytes` is a version byte, the PBKDF2
+/// iteration count as a big-endian `u32`, the salt, the nonce, and the
+/// encrypted PKCS#8 document followed by the GCM tag.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncryptedKeyPair {
+    iterations: u32,
+    salt: Vec<u8>,
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+impl EncryptedKeyPair {
+    /// Decrypt the key pair with `password`.
+    pub fn decrypt(&self, password: &[u8]) -> Result<KeyPair, KeyError> {
+        self.check()?;
+        let key = self.derive_key(password);
+        let key = aead::OpeningKey::new(&aead::AES_256_GCM, &key)
+            .map_err(|_| KeyError::InvalidKey)?;
+        let mut in_out = self.ciphertext.clone();
+        let der = aead::open_in_place(
+            &key,
+            &self.nonce,
+            &self.header(),
+            0,
+            &mut in_out,
+        ).map_err(|_| KeyError::WrongPassword)?;
+        KeyPair::from_pkcs8_der(der)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header();
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, KeyError> {
+        if bytes.len() < ENCRYPTED_KEY_HEADER_LEN
+            || bytes[0] != ENCRYPTED_KEY_V1
+        {
+            return Err(KeyError::InvalidKey);
+        }
+        let iterations = bytes[1..5]
+            .iter()
+            .fold(0, |acc, &b| (acc << 8) | u32::from(b));
+        let (salt, rest) = bytes[5..].split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let enc = EncryptedKeyPair {
+            iterations,
+            salt: salt.to_vec(),
+            nonce: nonce.to_vec(),
+            ciphertext: ciphertext.to_vec(),
+        };
+        enc.check()?;
+        Ok(enc)
+    }
+
+    /// Reject parameters that ring would panic on, which deserialized
+    /// containers may have, and iteration counts too large to derive the
+    /// key in reasonable time.
+    fn check(&self) -> Result<(), KeyError> {
+        if self.iterations == 0
+            || self.iterations > MAX_PBKDF2_ITERATIONS
+            || self.salt.len() != SALT_LEN
+            || self.nonce.len() != NONCE_LEN
+        {
+            return Err(KeyError::InvalidKey);
+        }
+        Ok(())
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(ENCRYPTED_KEY_HEADER_LEN);
+        header.push(ENCRYPTED_KEY_V1);
+        for i in (0..4).rev() {
+            header.push((self.iterations >> (i * 8)) as u8);
+        }
+        header.extend_from_slice(&self.salt);
+        header.extend_from_slice(&self.nonce);
+        header
+    }
+
+    fn derive_key(&self, password: &[u8]) -> [u8; 32] {
+        let mut key = [0; 32];
+        pbkdf2::derive(
+            &digest::SHA256,
+            self.iterations,
+            &self.salt,
+            password,
+            &mut key,
+        );
+        key
+    }
+}
+
+impl PubKey {
+    /// The key with the 32 byte encoding `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, KeyError> {
+        if bytes.len() != signature::ED25519_PUBLIC_KEY_LEN {
+            return Err(KeyError::InvalidKey);
+        }
+        Ok(PubKey::new(bytes))
+    }
+
+    /// Load a key from a SubjectPublicKeyInfo document.
+    pub fn from_spki_der(der: &[u8]) -> Result<Self, KeyError> {
+        let prefix_len = ED25519_SPKI_PREFIX.len();
+        if der.len() != prefix_len + signature::ED25519_PUBLIC_KEY_LEN
+            || der[..prefix_len] != ED25519_SPKI_PREFIX
+        {
+            return Err(KeyError::InvalidDer);
+        }
+        Ok(PubKey::new(&der[prefix_len..]))
+    }
+
+    /// Load a key from the first `PUBLIC KEY` block of a PEM file.
+    pub fn from_pem(pem: &str) -> Result<Self, KeyError> {
+        Self::from_spki_der(&pem_decode(pem, PEM_PUBLIC_KEY)?)
+    }
+
+    /// The key as a SubjectPublicKeyInfo document.
+    pub fn to_spki_der(&self) -> Vec<u8> {
+        let mut der = ED25519_SPKI_PREFIX.to_vec();
+        der.extend_from_slice(self.as_bytes());
+        der
+    }
+
+    pub fn to_pem(&self) -> String {
+        pem_encode(&self.to_spki_der(), PEM_PUBLIC_KEY)
+    }
+
+    /// The RFC 6962 log ID of the key.
+    pub fn log_id(&self) -> LogId {
+        LogId::from_spki_der(&self.to_spki_der())
+    }
+}
+
+fn pem_encode(der: &[u8], label: &str) -> String {
+    let b64 = BASE64.encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in b64.as_bytes().chunks(64) {
+        // base64 output is ASCII.
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+/// The contents of the first PEM block labeled `label`. Anything around it,
+/// like comments or other blocks, is ignored.
+fn pem_decode(pem: &str, label: &str) -> Result<Vec<u8>, KeyError> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let mut lines = pem
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| *line != begin)
+        .skip(1);
+    let mut b64 = String::new();
+    loop {
+        match lines.next() {
+            Some(line) if line == end => break,
+            Some(line) => b64.push_str(line),
+            None => return Err(KeyError::InvalidPem),
+        }
+    }
+    BASE64.decode(&b64).map_err(|_| KeyError::InvalidPem)
+}
@@ -1,24 +1,71 @@
 extern crate digest as extern_digest;
 extern crate untrusted;
 
+#[cfg(feature = "ring")]
+extern crate base64;
 #[cfg(feature = "ring")]
 extern crate ring;
 
+#[cfg(feature = "memmap")]
+extern crate memmap2;
+
+#[cfg(feature = "rayon")]
+extern crate rayon;
+
 #[cfg(feature = "serde")]
 #[macro_use]
 extern crate serde;
 
+#[cfg(feature = "ring")]
+pub mod checkpoint;
+mod compact;
+#[cfg(feature = "ring")]
+mod der;
+#[cfg(feature = "ring")]
+pub mod keyring;
+#[cfg(feature = "ring")]
+mod keys;
 mod merkle;
 #[cfg(feature = "ring")]
 mod signed_merkle;
+#[cfg(feature = "ring")]
+pub mod signer;
 pub mod digest;
+#[cfg(feature = "ring")]
+pub mod promise;
 pub mod proof;
+pub mod rfc6962;
+#[cfg(feature = "ring")]
+pub mod rotation;
+pub mod storage;
+pub mod tiles;
+#[cfg(feature = "ring")]
+pub mod witness;
 
+pub use compact::CompactMerkleTree;
+pub use merkle::{DuplicatePolicy, InsertError};
 pub use merkle::MerkleTree;
 pub use merkle::OwningMerkleTree;
+pub use merkle::TreeHead;
+#[cfg(feature = "memmap")]
+pub use storage::MappedMerkleTree;
+#[cfg(feature = "ring")]
+pub use signed_merkle::Cosignature;
+#[cfg(feature = "ring")]
+pub use keyring::{KeyRing, LogId};
+#[cfg(feature = "ring")]
+pub use keys::{EncryptedKeyPair, KeyError};
 #[cfg(feature = "ring")]
 pub use signed_merkle::KeyPair;
 #[cfg(feature = "ring")]
+pub use signed_merkle::RingError;
+#[cfg(feature = "ring")]
+pub use signer::{EcdsaKeyPair, EcdsaPubKey, RsaPssPubKey};
+#[cfg(feature = "ring")]
+pub use signed_merkle::PubKey;
+#[cfg(feature = "ring")]
 pub use signed_merkle::SignedMerkleTree;
 #[cfg(feature = "ring")]
 pub use signed_merkle::SignedOwningMerkleTree;
+#[cfg(feature = "ring")]
+pub use signed_merkle::SignedTreeHead;
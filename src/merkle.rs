@@ -3,9 +3,15 @@ use digest::Digest;
 use digest::Digestible;
 use digest::Hash;
 use proof::*;
+use rfc6962;
 use proof::AsMerkleTree;
-use std::collections::{hash_map, HashMap};
-use std::iter;
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::error::Error;
+use std::{cmp, fmt, io, iter, ops, slice};
+use storage::{self, FileStorage, MemoryStorage, Storage};
+use tiles::{Tile, TILE_HEIGHT, TILE_WIDTH};
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TreeHead<D: Digest> {
@@ -24,6 +30,15 @@ impl<D: Digest> Clone for TreeHead<D> {
 }
 
 impl<D: Digest> TreeHead<D> {
+    /// Create a tree head from a size and root hash obtained elsewhere, for
+    /// example from a previously verified signed tree head.
+    pub fn new(size: u64, root_hash: Hash<D>) -> Self {
+        TreeHead {
+            count: size,
+            hash: root_hash,
+        }
+    }
+
     pub fn size(&self) -> u64 {
         self.count
     }
@@ -33,136 +48,576 @@ impl<D: Digest> TreeHead<D> {
     }
 }
 
+/// What a tree does with an entry whose hash it contains already.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Drop the entry, so that the tree is a set. Inserting it returns
+    /// false. This is the default.
+    Reject,
+    /// Append the entry again, as an append-only log would. `index_of`
+    /// returns its first position, `indices_of` all of them.
+    Allow,
+    /// Drop the entry, but treat inserting it as a success, so that
+    /// inserts are idempotent. The entry keeps its existing position.
+    ReturnExisting,
+}
+
+// #[default] on enum variants needs Rust 1.62.
+#[allow(clippy::derivable_impls)]
+impl Default for DuplicatePolicy {
+    fn default() -> Self {
+        DuplicatePolicy::Reject
+    }
+}
+
+/// Reasons for `append` to fail.
+#[derive(Debug)]
+pub enum InsertError {
+    /// The entry is part of the tree already, at the given position, and
+    /// the duplicate policy rejects it.
+    Duplicate(u64),
+    /// The storage failed to record the entry, or a signed tree failed to
+    /// sign its new head. In the latter case the entry was removed again.
+    Io(io::Error),
+}
+
+impl fmt::Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InsertError::Duplicate(index) => {
+                write!(f, "duplicate of the entry at position {}", index)
+            }
+            InsertError::Io(ref e) => write!(f, "insert failed: {}", e),
+        }
+    }
+}
+
+impl Error for InsertError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            InsertError::Duplicate(_) => None,
+            InsertError::Io(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for InsertError {
+    fn from(e: io::Error) -> Self {
+        InsertError::Io(e)
+    }
+}
+
+/// The outcome of adding an entry to a tree.
+pub(crate) enum Inserted {
+    /// The entry was appended at this position.
+    Appended(u64),
+    /// The entry was dropped as a duplicate of the one at this position.
+    Existing(u64),
+}
+
+impl Inserted {
+    /// Whether inserting counts as a success under `policy`.
+    pub(crate) fn accepted(&self, policy: DuplicatePolicy) -> bool {
+        self.index(policy).is_ok()
+    }
+
+    /// The position of the entry, unless `policy` rejects it.
+    pub(crate) fn index(
+        &self,
+        policy: DuplicatePolicy,
+    ) -> Result<u64, InsertError> {
+        match *self {
+            Inserted::Appended(index) => Ok(index),
+            Inserted::Existing(index) => {
+                if policy == DuplicatePolicy::ReturnExisting {
+                    Ok(index)
+                } else {
+                    Err(InsertError::Duplicate(index))
+                }
+            }
+        }
+    }
+}
+
+/// A Merkle tree over entry hashes, with its nodes kept in `S`.
+///
+/// By default an entry can only be part of the tree once, see
+/// `DuplicatePolicy` for the alternatives.
+///
+/// Reading a `MemoryStorage` cannot fail. For storages that can, every
+/// method reading the tree has a `try_` variant that reports the error,
+/// while the plain one panics.
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone)]
-pub struct MerkleTree<D: Digest> {
-    #[cfg_attr(feature = "serde", serde(bound = ""))]
-    pub(crate) map: HashMap<Hash<D>, usize>,
-    #[cfg_attr(feature = "serde", serde(bound = ""))]
-    pub(crate) tree: Vec<Hash<D>>,
+pub struct MerkleTree<D: Digest, S: Storage<D> = MemoryStorage<D>> {
+    #[cfg_attr(feature = "serde", serde(
+            bound(serialize = "S: ::serde::Serialize",
+                  deserialize = "S: ::serde::Deserialize<'de>")))]
+    storage: S,
+    policy: DuplicatePolicy,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    _digest: PhantomData<D>,
 }
 
 impl<D: Digest> MerkleTree<D> {
     pub fn new() -> MerkleTree<D> {
-        let empty = D::default().fixed_result();
-        let mut m = MerkleTree::<D> {
-            map: HashMap::new(),
-            tree: Vec::new(),
-        };
-        m.tree.push(empty.clone());
-        m.tree.push(empty);
-        m
+        MerkleTree::with_storage(MemoryStorage::new())
     }
 
-    pub fn insert<H: AsHash<D>>(&mut self, hash: H) -> bool {
-        let hash = hash.as_hash();
+    /// The entry hash stored at position `index`, as it was inserted.
+    ///
+    /// Like `iter` and `entries` this is only available for trees kept in
+    /// memory, which can lend out their entries. Trees in other storages
+    /// can only be queried with `leaf_hash` and `index_of`.
+    pub fn get(&self, index: u64) -> Option<&Hash<D>> {
+        self.storage.entries().get(index as usize)
+    }
 
-        let mut tlen = self.tree.len();
-        let count = self.len();
+    /// Iterate over the entry hashes in insertion order.
+    pub fn iter(&self) -> slice::Iter<'_, Hash<D>> {
+        self.storage.entries().iter()
+    }
 
-        let leaf_hash = D::hash_leaf(&hash);
+    /// The entry hashes at positions `range`, in the spirit of the RFC
+    /// 6962 `get-entries` call: the range is cut off at the end of the
+    /// tree, so fewer entries than requested may be returned.
+    pub fn entries(&self, range: ops::Range<u64>) -> &[Hash<D>] {
+        clamped(self.storage.entries(), range)
+    }
 
-        match self.map.entry(hash) {
-            hash_map::Entry::Occupied(_) => return false,
-            e @ hash_map::Entry::Vacant(_) => e.or_insert(count),
-        };
+    /// Build a tree of `hashes` bottom-up, computing each level of nodes
+    /// in one go instead of completing subtrees entry by entry. The tree
+    /// is the same as if `hashes` were inserted one at a time. With the
+    /// `rayon` feature, the nodes of each level are hashed in parallel.
+    pub fn from_hashes(hashes: Vec<Hash<D>>) -> MerkleTree<D>
+    where
+        Hash<D>: Send + Sync,
+    {
+        let mut mt = MerkleTree::new();
+        mt.extend_from_hashes(hashes);
+        mt
+    }
 
-        if self.tree.len() / 2 <= count {
-            self.inc_height();
-            tlen = self.tree.len();
-        }
-        let mut pos = tlen / 2 + count;
+    /// Insert `hashes` like `from_hashes` builds a tree, following the
+    /// duplicate policy. Returns the number of appended hashes.
+    pub fn extend_from_hashes(&mut self, hashes: Vec<Hash<D>>) -> usize
+    where
+        Hash<D>: Send + Sync,
+    {
+        #[cfg(feature = "rayon")]
+        let map = storage::par_map_chunks::<D>;
+        #[cfg(not(feature = "rayon"))]
+        let map = storage::map_chunks::<D>;
+        let duplicates = self.policy == DuplicatePolicy::Allow;
+        self.storage.append_mapped(hashes, map, duplicates)
+    }
+
+    /// Like `extend_from_hashes`, hashing on the calling thread only, so
+    /// it works for any `D`.
+    pub(crate) fn append_batch(&mut self, hashes: Vec<Hash<D>>) -> usize {
+        let duplicates = self.policy == DuplicatePolicy::Allow;
+        self.storage
+            .append_mapped(hashes, storage::map_chunks::<D>, duplicates)
+    }
 
-        self.tree[pos] = leaf_hash;
+    /// Remove the entries from position `len` on.
+    #[cfg(feature = "ring")]
+    pub(crate) fn truncate(&mut self, len: u64) {
+        self.storage.truncate(len);
+    }
 
-        while pos > 1 {
-            if pos % 2 == 0 {
-                self.tree[pos / 2] = self.tree[pos].clone();
-            } else {
-                self.tree[pos / 2] =
-                    D::hash_inner(&self.tree[pos - 1], &self.tree[pos]);
-            }
-            pos /= 2;
+    /// Persist the tree to the empty or missing directory `dir` in the
+    /// layout used by `FileStorage`, so it can be opened again later, or
+    /// served by readers using `MappedStorage`.
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let mut target = FileStorage::open(dir)?;
+        self.storage.copy_to(&mut target)?;
+        target.sync()
+    }
+}
+
+impl<D: Digest, S: Storage<D>> MerkleTree<D, S> {
+    /// Use the tree kept in `storage`, for example one that was persisted
+    /// by a `FileStorage` earlier.
+    pub fn with_storage(storage: S) -> MerkleTree<D, S> {
+        MerkleTree {
+            storage,
+            policy: DuplicatePolicy::Reject,
+            _digest: PhantomData,
         }
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn into_storage(self) -> S {
+        self.storage
+    }
+
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.policy
+    }
 
-        true
+    /// Decide what later inserts do with entries that are part of the tree
+    /// already. Entries inserted before are kept as they are. The policy
+    /// is not persisted by `FileStorage`, set it again after opening one.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.policy = policy;
     }
 
-    fn inc_height(&mut self) {
-        let t = &mut self.tree;
-        let old_len = t.len();
-        let new_len = old_len * 2;
-        t.reserve(new_len);
-        let x = D::default().fixed_result();
-        t.extend(iter::repeat(x).take(old_len));
-        let mut rem_len = new_len;
-        while rem_len > 2 {
-            let a_len = rem_len / 2;
-            let (a, b) = t.split_at_mut(a_len);
-            b[..a_len / 2].clone_from_slice(&a[a_len / 2..]);
-            rem_len = a_len;
+    /// Append `hash`, unless it is part of the tree already and the
+    /// duplicate policy says otherwise. Returns false if `hash` was
+    /// rejected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the storage fails to record the entry, see `try_insert`.
+    pub fn insert<H: AsHash<D>>(&mut self, hash: H) -> bool {
+        self.try_insert(hash).expect("failed to write to tree storage")
+    }
+
+    /// Like `insert`, but reports storage errors instead of panicking.
+    pub fn try_insert<H: AsHash<D>>(&mut self, hash: H) -> io::Result<bool> {
+        let policy = self.policy;
+        self.push(hash).map(|x| x.accepted(policy))
+    }
+
+    /// Append `hash` following the duplicate policy, and return the
+    /// position it was assigned, or its existing position if the policy
+    /// is `ReturnExisting`.
+    pub fn append<H: AsHash<D>>(
+        &mut self,
+        hash: H,
+    ) -> Result<u64, InsertError> {
+        let policy = self.policy;
+        self.push(hash)?.index(policy)
+    }
+
+    /// Append all of `hashes` following the duplicate policy, and return
+    /// the positions of the appended ones. Hashes dropped as duplicates
+    /// are skipped instead of failing the batch, look them up with
+    /// `index_of`. The nodes of the tree are computed once for the whole
+    /// batch if the storage supports it, see `Storage::append_all`. On
+    /// error, the hashes before the failing one stay in the tree.
+    pub fn append_many<H, I>(
+        &mut self,
+        hashes: I,
+    ) -> Result<ops::Range<u64>, InsertError>
+    where
+        H: AsHash<D>,
+        I: IntoIterator<Item = H>,
+    {
+        let hashes = hashes.into_iter().map(|h| h.as_hash()).collect();
+        let duplicates = self.policy == DuplicatePolicy::Allow;
+        let start = self.storage.len();
+        self.storage.append_all(hashes, duplicates)?;
+        Ok(start..self.storage.len())
+    }
+
+    /// Append `hash` following the duplicate policy.
+    pub(crate) fn push<H: AsHash<D>>(
+        &mut self,
+        hash: H,
+    ) -> io::Result<Inserted> {
+        let hash = hash.as_hash();
+        if self.policy != DuplicatePolicy::Allow {
+            if let Some(index) = self.storage.index_of(&hash)? {
+                return Ok(Inserted::Existing(index));
+            }
         }
+
+        let index = self.storage.len();
+        let nodes = storage::completed_nodes(&self.storage, &hash)?;
+        self.storage.append(hash, &nodes)?;
+        Ok(Inserted::Appended(index))
     }
 
     pub fn head(&self) -> TreeHead<D> {
-        TreeHead {
-            count: self.len() as u64,
-            hash: self.tree[1].clone(),
+        self.try_head().expect(READ_FAILED)
+    }
+
+    /// Like `head`, but reports storage errors instead of panicking.
+    pub fn try_head(&self) -> io::Result<TreeHead<D>> {
+        let size = self.len();
+        self.try_head_at(size).map(Option::unwrap)
+    }
+
+    /// The tree head as it was when the tree contained `tree_size`
+    /// elements.
+    pub fn head_at(&self, tree_size: u64) -> Option<TreeHead<D>> {
+        self.try_head_at(tree_size).expect(READ_FAILED)
+    }
+
+    /// Like `head_at`, but reports storage errors instead of panicking.
+    pub fn try_head_at(
+        &self,
+        tree_size: u64,
+    ) -> io::Result<Option<TreeHead<D>>> {
+        if tree_size > self.len() {
+            return Ok(None);
         }
+        let hash = if tree_size == 0 {
+            D::default().fixed_result()
+        } else {
+            self.hash_from_range(0, tree_size - 1)?
+        };
+        Ok(Some(TreeHead {
+            count: tree_size,
+            hash,
+        }))
     }
 
     pub fn inclusion_proof<H: AsHash<D>>(
         &self,
         h: H,
     ) -> Option<InclusionProof<D>> {
+        self.try_inclusion_proof(h).expect(READ_FAILED)
+    }
+
+    /// Like `inclusion_proof`, but reports storage errors instead of
+    /// panicking.
+    pub fn try_inclusion_proof<H: AsHash<D>>(
+        &self,
+        h: H,
+    ) -> io::Result<Option<InclusionProof<D>>> {
+        self.try_inclusion_proof_at(h, self.len())
+    }
+
+    /// Prove that `h` was included in the tree when it had `tree_size`
+    /// elements, for clients that only trust an older tree head.
+    pub fn inclusion_proof_at<H: AsHash<D>>(
+        &self,
+        h: H,
+        tree_size: u64,
+    ) -> Option<InclusionProof<D>> {
+        self.try_inclusion_proof_at(h, tree_size).expect(READ_FAILED)
+    }
+
+    /// Like `inclusion_proof_at`, but reports storage errors instead of
+    /// panicking.
+    pub fn try_inclusion_proof_at<H: AsHash<D>>(
+        &self,
+        h: H,
+        tree_size: u64,
+    ) -> io::Result<Option<InclusionProof<D>>> {
         let h = h.as_hash();
 
-        InclusionProofBase::new(h, self)
-            .map(|x| InclusionProof::new(x, self.head()))
+        let th = match self.try_head_at(tree_size)? {
+            Some(th) => th,
+            None => return Ok(None),
+        };
+        let base = InclusionProofBase::new(h, tree_size, self)?;
+        Ok(base.map(|x| InclusionProof::new(x, th)))
+    }
+
+    /// Prove inclusion of the element at position `index`.
+    pub fn inclusion_proof_by_index(
+        &self,
+        index: u64,
+    ) -> Option<InclusionProof<D>> {
+        self.try_inclusion_proof_by_index(index).expect(READ_FAILED)
+    }
+
+    /// Like `inclusion_proof_by_index`, but reports storage errors instead
+    /// of panicking.
+    pub fn try_inclusion_proof_by_index(
+        &self,
+        index: u64,
+    ) -> io::Result<Option<InclusionProof<D>>> {
+        let size = self.len();
+        match InclusionProofBase::from_index(index, size, self)? {
+            Some(x) => Ok(Some(InclusionProof::new(x, self.try_head()?))),
+            None => Ok(None),
+        }
+    }
+
+    /// The RFC 6962 leaf hash of the element at position `index`.
+    pub fn leaf_hash(&self, index: u64) -> Option<Hash<D>> {
+        self.try_leaf_hash(index).expect(READ_FAILED)
+    }
+
+    /// Like `leaf_hash`, but reports storage errors instead of panicking.
+    pub fn try_leaf_hash(&self, index: u64) -> io::Result<Option<Hash<D>>> {
+        if index < self.len() {
+            self.hash_from_range(index, index).map(Some)
+        } else {
+            Ok(None)
+        }
     }
 
     pub fn consistency_proof(
         &self,
         old_size: u64,
     ) -> Option<ConsistencyProof<D>> {
-        ConsistencyProofBase::new(old_size, self)
-            .map(|x| ConsistencyProof::new(x, self.head()))
+        self.try_consistency_proof(old_size).expect(READ_FAILED)
     }
 
-    pub(crate) fn len(&self) -> usize {
-        self.map.len()
+    /// Like `consistency_proof`, but reports storage errors instead of
+    /// panicking.
+    pub fn try_consistency_proof(
+        &self,
+        old_size: u64,
+    ) -> io::Result<Option<ConsistencyProof<D>>> {
+        self.try_consistency_proof_between(old_size, self.len())
+    }
+
+    /// Prove that the tree at `new_size` elements is an extension of the
+    /// tree at `old_size` elements, for `old_size <= new_size <= len()`.
+    pub fn consistency_proof_between(
+        &self,
+        old_size: u64,
+        new_size: u64,
+    ) -> Option<ConsistencyProof<D>> {
+        self.try_consistency_proof_between(old_size, new_size)
+            .expect(READ_FAILED)
     }
 
-    pub(crate) fn get_offset(&self) -> u64 {
-        self.len().next_power_of_two() as u64
+    /// Like `consistency_proof_between`, but reports storage errors
+    /// instead of panicking.
+    pub fn try_consistency_proof_between(
+        &self,
+        old_size: u64,
+        new_size: u64,
+    ) -> io::Result<Option<ConsistencyProof<D>>> {
+        let th = match self.try_head_at(new_size)? {
+            Some(th) => th,
+            None => return Ok(None),
+        };
+        let base = ConsistencyProofBase::new(old_size, new_size, self)?;
+        Ok(base.map(|x| ConsistencyProof::new(x, th)))
+    }
+
+    /// The contents of `tile`: the concatenated hashes it covers. Returns
+    /// `None` if the tree is too small for the tile.
+    pub fn tile(&self, tile: &Tile) -> Option<Vec<u8>> {
+        self.try_tile(tile).expect(READ_FAILED)
+    }
+
+    /// Like `tile`, but reports storage errors instead of panicking.
+    pub fn try_tile(&self, tile: &Tile) -> io::Result<Option<Vec<u8>>> {
+        let (level, nodes) = match self.tile_nodes(tile) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let mut data = Vec::new();
+        for i in nodes {
+            data.extend_from_slice(&self.storage.node(level, i)?);
+        }
+        Ok(Some(data))
+    }
+
+    /// The level of the bottom row of `tile`, and its nodes on that level.
+    fn tile_nodes(&self, tile: &Tile) -> Option<(u32, ops::Range<u64>)> {
+        let level = tile.level().checked_mul(TILE_HEIGHT)?;
+        if level >= 64 {
+            return None;
+        }
+        let start = tile.index().checked_mul(TILE_WIDTH)?;
+        let end = start.checked_add(tile.width())?;
+        if end > self.storage.len() >> level {
+            return None;
+        }
+        Some((level, start..end))
     }
 
-    pub(crate) fn hash_from_range(&self, left: u64, right: u64) -> Hash<D> {
+    /// Number of elements in the tree.
+    pub fn len(&self) -> u64 {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+
+    pub fn contains<H: AsHash<D>>(&self, h: H) -> bool {
+        self.try_contains(h).expect(READ_FAILED)
+    }
+
+    /// Like `contains`, but reports storage errors instead of panicking.
+    pub fn try_contains<H: AsHash<D>>(&self, h: H) -> io::Result<bool> {
+        self.try_index_of(h).map(|x| x.is_some())
+    }
+
+    /// Position of the element with hash `h`, the first one if it was
+    /// appended several times.
+    pub fn index_of<H: AsHash<D>>(&self, h: H) -> Option<u64> {
+        self.try_index_of(h).expect(READ_FAILED)
+    }
+
+    /// Like `index_of`, but reports storage errors instead of panicking.
+    pub fn try_index_of<H: AsHash<D>>(&self, h: H) -> io::Result<Option<u64>> {
+        self.storage.index_of(&h.as_hash())
+    }
+
+    /// All positions of the element with hash `h`, in ascending order.
+    /// There is more than one only if duplicates are allowed.
+    pub fn indices_of<H: AsHash<D>>(&self, h: H) -> Vec<u64> {
+        self.try_indices_of(h).expect(READ_FAILED)
+    }
+
+    /// Like `indices_of`, but reports storage errors instead of panicking.
+    pub fn try_indices_of<H: AsHash<D>>(&self, h: H) -> io::Result<Vec<u64>> {
+        self.storage.indices_of(&h.as_hash())
+    }
+
+    /// The Merkle Tree Hash of the leaves `left..=right`.
+    ///
+    /// `left` has to be aligned like the subtrees in RFC 6962, that is to a
+    /// multiple of the smallest power of two not smaller than the range.
+    /// Only perfect subtrees are stored, other ranges are split up and
+    /// recomputed.
+    pub(crate) fn hash_from_range(
+        &self,
+        left: u64,
+        right: u64,
+    ) -> io::Result<Hash<D>> {
         let diff = right + 1 - left;
-        let next_pow_2 = diff.next_power_of_two();
-        self.tree[((self.get_offset() + left) / next_pow_2) as usize].clone()
+        if diff.is_power_of_two() {
+            let level = diff.trailing_zeros();
+            return self.storage.node(level, left >> level);
+        }
+        let k = rfc6962::split(diff);
+        Ok(D::hash_inner(
+            &self.hash_from_range(left, left + k - 1)?,
+            &self.hash_from_range(left + k, right)?,
+        ))
     }
 }
 
+pub(crate) const READ_FAILED: &str = "failed to read tree storage";
+
+fn clamped<T>(s: &[T], range: ops::Range<u64>) -> &[T] {
+    let end = cmp::min(range.end, s.len() as u64);
+    let start = cmp::min(range.start, end);
+    &s[start as usize..end as usize]
+}
+
 impl<D: Digest> Default for MerkleTree<D> {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Collected trees are built level by level, see `from_hashes`.
 impl<D: Digest, H: AsHash<D>> iter::FromIterator<H> for MerkleTree<D> {
     fn from_iter<T: IntoIterator<Item = H>>(iter: T) -> Self {
+        let hashes = iter.into_iter().map(AsHash::as_hash).collect();
         let mut mt = MerkleTree::new();
-        mt.extend(iter);
+        mt.append_batch(hashes);
         mt
     }
 }
 
-impl<D: Digest, H: AsHash<D>> iter::Extend<H> for MerkleTree<D> {
+/// Extending the tree appends like `append_many`, and panics if the
+/// storage fails to record an entry.
+impl<D: Digest, S: Storage<D>, H: AsHash<D>> iter::Extend<H>
+    for MerkleTree<D, S>
+{
     fn extend<T: IntoIterator<Item = H>>(&mut self, iter: T) {
-        for x in iter {
-            self.insert(x);
-        }
+        self.append_many(iter)
+            .expect("failed to write to tree storage");
     }
 }
 
@@ -181,15 +636,113 @@ impl<T: Digestible, D: Digest> OwningMerkleTree<T, D> {
         }
     }
 
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.mt.duplicate_policy()
+    }
+
+    /// See `MerkleTree::set_duplicate_policy`. With `Allow`, equal elements
+    /// are stored once per insert.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.mt.set_duplicate_policy(policy);
+    }
+
+    /// Append `elem`, following the duplicate policy like
+    /// `MerkleTree::insert`.
     pub fn insert(&mut self, elem: T) -> bool {
-        let hash = D::hash_elem(&elem);
+        let policy = self.duplicate_policy();
+        self.push(elem)
+            .expect("failed to write to tree storage")
+            .accepted(policy)
+    }
+
+    /// See `MerkleTree::append`.
+    pub fn append(&mut self, elem: T) -> Result<u64, InsertError> {
+        let policy = self.duplicate_policy();
+        self.push(elem)?.index(policy)
+    }
+
+    /// See `MerkleTree::append_many`.
+    pub fn append_many<I: IntoIterator<Item = T>>(
+        &mut self,
+        elems: I,
+    ) -> Result<ops::Range<u64>, InsertError> {
+        let start = self.len();
+        self.append_batch(elems.into_iter().collect());
+        Ok(start..self.len())
+    }
 
-        if self.mt.insert(hash) {
+    /// Append `elems` following the duplicate policy, computing the nodes
+    /// of the tree once for the whole batch like `append_batch` does.
+    /// Returns the number of appended elements.
+    pub(crate) fn append_batch(&mut self, elems: Vec<T>) -> usize {
+        let allow = self.duplicate_policy() == DuplicatePolicy::Allow;
+        let mut seen = HashSet::new();
+        let mut hashes = Vec::with_capacity(elems.len());
+        for elem in elems {
+            let hash = D::hash_elem(&elem);
+            // Later copies of an element are duplicates of the first one.
+            if allow
+                || !self.mt.contains(hash.clone()) && seen.insert(hash.clone())
+            {
+                hashes.push(hash);
+                self.objs.push(elem);
+            }
+        }
+        let added = self.mt.append_batch(hashes);
+        debug_assert_eq!(self.objs.len() as u64, self.mt.len());
+        added
+    }
+
+    /// Remove the elements from position `len` on.
+    #[cfg(feature = "ring")]
+    pub(crate) fn truncate(&mut self, len: u64) {
+        self.mt.truncate(len);
+        self.objs.truncate(len as usize);
+    }
+
+    /// Append `elem` following the duplicate policy.
+    pub(crate) fn push(&mut self, elem: T) -> io::Result<Inserted> {
+        let hash = D::hash_elem(&elem);
+        let inserted = self.mt.push(hash)?;
+        if let Inserted::Appended(_) = inserted {
             self.objs.push(elem);
-            true
-        } else {
-            false
         }
+        Ok(inserted)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.mt.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mt.is_empty()
+    }
+
+    pub fn contains<H: AsHash<D>>(&self, h: H) -> bool {
+        self.mt.contains(h)
+    }
+
+    pub fn index_of<H: AsHash<D>>(&self, h: H) -> Option<u64> {
+        self.mt.index_of(h)
+    }
+
+    pub fn indices_of<H: AsHash<D>>(&self, h: H) -> Vec<u64> {
+        self.mt.indices_of(h)
+    }
+
+    /// The element stored at position `index`.
+    pub fn get(&self, index: u64) -> Option<&T> {
+        self.objs.get(index as usize)
+    }
+
+    /// Iterate over the elements in insertion order.
+    pub fn iter(&self) -> slice::Iter<'_, T> {
+        self.objs.iter()
+    }
+
+    /// The elements at positions `range`, cut off at the end of the tree.
+    pub fn entries(&self, range: ops::Range<u64>) -> &[T] {
+        clamped(&self.objs, range)
     }
 
     pub fn head(&self) -> TreeHead<D> {
@@ -203,12 +756,43 @@ impl<T: Digestible, D: Digest> OwningMerkleTree<T, D> {
         self.mt.inclusion_proof(h)
     }
 
+    pub fn head_at(&self, tree_size: u64) -> Option<TreeHead<D>> {
+        self.mt.head_at(tree_size)
+    }
+
+    pub fn inclusion_proof_at<H: AsHash<D>>(
+        &self,
+        h: H,
+        tree_size: u64,
+    ) -> Option<InclusionProof<D>> {
+        self.mt.inclusion_proof_at(h, tree_size)
+    }
+
+    pub fn inclusion_proof_by_index(
+        &self,
+        index: u64,
+    ) -> Option<InclusionProof<D>> {
+        self.mt.inclusion_proof_by_index(index)
+    }
+
+    pub fn leaf_hash(&self, index: u64) -> Option<Hash<D>> {
+        self.mt.leaf_hash(index)
+    }
+
     pub fn consistency_proof(
         &self,
         old_size: u64,
     ) -> Option<ConsistencyProof<D>> {
         self.mt.consistency_proof(old_size)
     }
+
+    pub fn consistency_proof_between(
+        &self,
+        old_size: u64,
+        new_size: u64,
+    ) -> Option<ConsistencyProof<D>> {
+        self.mt.consistency_proof_between(old_size, new_size)
+    }
 }
 
 impl<T: Digestible, D: Digest> Default for OwningMerkleTree<T, D> {
@@ -217,21 +801,20 @@ impl<T: Digestible, D: Digest> Default for OwningMerkleTree<T, D> {
     }
 }
 
+/// Collected trees are built level by level, like `MerkleTree`s.
 impl<T: Digestible, D: Digest> iter::FromIterator<T>
     for OwningMerkleTree<T, D>
 {
     fn from_iter<S: IntoIterator<Item = T>>(iter: S) -> Self {
         let mut mt = OwningMerkleTree::new();
-        mt.extend(iter);
+        mt.append_batch(iter.into_iter().collect());
         mt
     }
 }
 
 impl<T: Digestible, D: Digest> iter::Extend<T> for OwningMerkleTree<T, D> {
     fn extend<S: IntoIterator<Item = T>>(&mut self, iter: S) {
-        for x in iter {
-            self.insert(x);
-        }
+        self.append_batch(iter.into_iter().collect());
     }
 }
 
@@ -241,13 +824,17 @@ impl<T: Digestible, D: Digest> From<OwningMerkleTree<T, D>> for MerkleTree<D> {
     }
 }
 
-impl<D: Digest> AsMerkleTree<D> for MerkleTree<D> {
-    fn as_merkle_tree(&self) -> &MerkleTree<D> {
+impl<D: Digest, S: Storage<D>> AsMerkleTree<D> for MerkleTree<D, S> {
+    type Storage = S;
+
+    fn as_merkle_tree(&self) -> &MerkleTree<D, S> {
         self
     }
 }
 
 impl<T: Digestible, D: Digest> AsMerkleTree<D> for OwningMerkleTree<T, D> {
+    type Storage = MemoryStorage<D>;
+
     fn as_merkle_tree(&self) -> &MerkleTree<D> {
         &self.mt
     }
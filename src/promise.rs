@@ -0,0 +1,204 @@
+//! Signed promises to include entries, like the Signed Certificate
+//! Timestamps of Certificate Transparency.
+//!
+//! A signed tree can accept entries into a queue and immediately return an
+//! `InclusionPromise`: a statement signed by the log that the entry will be
+//! part of every head signed at or after the promise's deadline, its
+//! timestamp plus the maximum merge delay of the log. Submitters keep the
+//! promise and ask the log for inclusion proofs. A proof from any head
+//! shows the entry was merged, while a head signed at or after the
+//! deadline for which the log has no proof shows the promise was broken.
+//!
+//! Promised entries are staged like those passed to `stage` and become part
+//! of the tree with the next `commit`.
+
+use digest::{Digest, Hash};
+use keyring::{KeySource, LogId};
+use proof::{SignedInclusionProof, VerificationError};
+use signed_merkle::{self, SignedTreeHead, SIGNATURE_TYPE_PROMISE};
+use signer::{SignatureAlgorithm, TreeHeadSigner, Verifier};
+use std::io;
+
+/// Version of the signing input of inclusion promises.
+const PROMISE_VERSION: u8 = 0;
+
+/// Merge delay of new signed trees: 24 hours, as for most CT logs.
+pub const DEFAULT_MERGE_DELAY: u64 = 24 * 60 * 60 * 1000;
+
+/// A log's signed promise to include an entry within its maximum merge
+/// delay.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct InclusionPromise<D: Digest> {
+    version: u8,
+    timestamp: u64,
+    merge_delay: u64,
+    log_id: LogId,
+    sig_alg: SignatureAlgorithm,
+    #[cfg_attr(feature = "serde", serde(bound = ""))]
+    entry: Hash<D>,
+    sig: Vec<u8>,
+}
+
+impl<D: Digest> Clone for InclusionPromise<D> {
+    fn clone(&self) -> Self {
+        InclusionPromise {
+            version: self.version,
+            timestamp: self.timestamp,
+            merge_delay: self.merge_delay,
+            log_id: self.log_id,
+            sig_alg: self.sig_alg,
+            entry: self.entry.clone(),
+            sig: self.sig.clone(),
+        }
+    }
+}
+
+impl<D: Digest> InclusionPromise<D> {
+    /// Have `signer` promise to include `entry` within `merge_delay`
+    /// milliseconds, waiting for the signature if needed.
+    pub(crate) fn signed<K: TreeHeadSigner>(
+        signer: &K,
+        entry: Hash<D>,
+        merge_delay: u64,
+    ) -> io::Result<Self> {
+        let pk = signer.verifier();
+        let mut promise = InclusionPromise {
+            version: PROMISE_VERSION,
+            timestamp: signed_merkle::now_millis(),
+            merge_delay,
+            log_id: pk.log_id(),
+            sig_alg: pk.algorithm(),
+            entry,
+            sig: Vec::new(),
+        };
+        let pending = signer.sign_tree_head(&promise.signing_input());
+        promise.sig = pending.wait()?;
+        promise.verify(&pk).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "signer returned an invalid promise signature",
+            )
+        })?;
+        Ok(promise)
+    }
+
+    /// The data covered by the signature:
+    ///
+    /// ```text
+    /// u8 version; u8 signature_type; u64 timestamp; u64 merge_delay;
+    /// opaque log_id<0..255>; opaque entry_hash<0..255>;
+    /// ```
+    ///
+    /// Encoded like the signing input of a `SignedTreeHead`.
+    fn signing_input(&self) -> Vec<u8> {
+        let mut input = vec![self.version, SIGNATURE_TYPE_PROMISE];
+        signed_merkle::push_u64(&mut input, self.timestamp);
+        signed_merkle::push_u64(&mut input, self.merge_delay);
+        signed_merkle::push_opaque(&mut input, self.log_id.as_bytes());
+        signed_merkle::push_opaque(&mut input, &self.entry);
+        input
+    }
+
+    /// Check the signature with the key of the log that made the promise,
+    /// taken from `keys`.
+    pub fn verify<K: KeySource>(
+        &self,
+        keys: &K,
+    ) -> Result<(), VerificationError> {
+        if self.version != PROMISE_VERSION {
+            return Err(VerificationError::MalformedProof);
+        }
+        let pubkey = match keys.key_for(&self.log_id) {
+            Some(pubkey) => pubkey,
+            None => return Err(VerificationError::BadSignature),
+        };
+        if self.sig_alg != pubkey.algorithm() {
+            return Err(VerificationError::BadSignature);
+        }
+        if pubkey.verify_message(&self.signing_input(), &self.sig) {
+            Ok(())
+        } else {
+            Err(VerificationError::BadSignature)
+        }
+    }
+
+    /// Check that the entry was merged: `proof` shows it to be part of a
+    /// head signed by the same log. Any head does, no matter when it was
+    /// signed; use `verify_head` to find heads missing the entry.
+    pub fn verify_honored<K: KeySource>(
+        &self,
+        proof: &SignedInclusionProof<D>,
+        keys: &K,
+    ) -> Result<(), VerificationError> {
+        self.verify(keys)?;
+        if proof.log_id() != self.log_id
+            || *proof.leaf_hash() != D::hash_leaf(&self.entry)
+        {
+            return Err(VerificationError::MalformedProof);
+        }
+        proof.verify(keys)
+    }
+
+    /// Check the promise against `sth`, a head signed by the same log, and
+    /// the inclusion proof for the entry in it the log answered with, if
+    /// any. Heads signed before the deadline need not include the entry
+    /// yet. A head signed at or after it that the log has no proof for
+    /// breaks the promise and fails with `DeadlineMissed`.
+    pub fn verify_head<K: KeySource>(
+        &self,
+        sth: &SignedTreeHead<D>,
+        proof: Option<&SignedInclusionProof<D>>,
+        keys: &K,
+    ) -> Result<(), VerificationError> {
+        self.verify(keys)?;
+        if sth.log_id() != self.log_id {
+            return Err(VerificationError::MalformedProof);
+        }
+        sth.verify(keys)?;
+        match proof {
+            Some(proof) => {
+                let head = proof.signed_tree_head();
+                if head.size() != sth.size()
+                    || head.root_hash() != sth.root_hash()
+                {
+                    return Err(VerificationError::MalformedProof);
+                }
+                self.verify_honored(proof, keys)
+            }
+            None if sth.timestamp() >= self.deadline() => {
+                Err(VerificationError::DeadlineMissed)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Milliseconds since the UNIX epoch at which the promise was made.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// The maximum merge delay of the log in milliseconds.
+    pub fn merge_delay(&self) -> u64 {
+        self.merge_delay
+    }
+
+    /// Milliseconds since the UNIX epoch from which on every head of the
+    /// log includes the entry.
+    pub fn deadline(&self) -> u64 {
+        self.timestamp.saturating_add(self.merge_delay)
+    }
+
+    /// Identifier of the log that made the promise.
+    pub fn log_id(&self) -> LogId {
+        self.log_id
+    }
+
+    pub fn algorithm(&self) -> SignatureAlgorithm {
+        self.sig_alg
+    }
+
+    /// The hash of the promised entry, as passed to `contains`.
+    pub fn entry_hash(&self) -> &Hash<D> {
+        &self.entry
+    }
+}
@@ -1,71 +1,124 @@
 use digest::{Digest, Hash};
 use merkle::{MerkleTree, TreeHead};
+use rfc6962;
 #[cfg(feature = "ring")]
-use signed_merkle::{PubKey, SignedTreeHead};
+use signed_merkle::SignedTreeHead;
+#[cfg(feature = "ring")]
+use keyring::{KeySource, LogId};
+use std::error::Error;
+use std::{fmt, io};
+use storage::Storage;
+
+/// Reasons for a proof or signed tree head to be rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationError {
+    /// The tree head signature does not verify under the given key, or no
+    /// key of the log that signed it was given.
+    BadSignature,
+    /// The proof has too few or too many hashes for the tree sizes.
+    WrongPathLength,
+    /// The leaf index or old tree size lies outside of the tree.
+    IndexOutOfRange,
+    /// The root computed from the proof does not match the tree head.
+    RootMismatch,
+    /// The old root computed from a consistency proof does not match.
+    OldRootMismatch,
+    /// The proof does not fit the tree heads it is checked against, or
+    /// uses an unsupported format.
+    MalformedProof,
+    /// Fewer witnesses than required by the policy cosigned the head.
+    MissingCosignatures,
+    /// A head signed at or after the deadline of an inclusion promise
+    /// does not include the promised entry.
+    DeadlineMissed,
+    /// A key transition takes effect at a smaller tree size than the one
+    /// before it.
+    TransitionOutOfOrder,
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            VerificationError::BadSignature => "bad tree head signature",
+            VerificationError::WrongPathLength => "proof has wrong length",
+            VerificationError::IndexOutOfRange => "index out of range",
+            VerificationError::RootMismatch => "root hash mismatch",
+            VerificationError::OldRootMismatch => "old root hash mismatch",
+            VerificationError::MalformedProof => "malformed proof",
+            VerificationError::MissingCosignatures => {
+                "not enough witness cosignatures"
+            }
+            VerificationError::DeadlineMissed => {
+                "entry missing from a head after the promised deadline"
+            }
+            VerificationError::TransitionOutOfOrder => {
+                "key transition goes back in tree size"
+            }
+        })
+    }
+}
+
+impl Error for VerificationError {}
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) struct InclusionProofBase<D: Digest> {
     #[cfg_attr(feature = "serde", serde(bound = ""))]
-    obj: Hash<D>,
+    leaf: Hash<D>,
     pos: u64,
     #[cfg_attr(feature = "serde", serde(bound = ""))]
     hashes: Vec<Hash<D>>,
 }
 
 impl<D: Digest> InclusionProofBase<D> {
-    pub(crate) fn new<M: AsMerkleTree<D>>(h: Hash<D>, mt: &M) -> Option<Self> {
-        let mt = mt.as_merkle_tree();
-        if let Some(&i) = mt.map.get(&h) {
-            let mut hashes = Vec::new();
-            let offset = mt.get_offset();
-            let mut pos = offset as usize + i;
-            while pos > 1 {
-                let parent = pos / 2;
-                // Check parent for equality (unbalanced (sub)tree)
-                if mt.tree[pos] != mt.tree[parent] {
-                    if pos % 2 == 0 {
-                        hashes.push(mt.tree[pos + 1].clone());
-                    } else {
-                        hashes.push(mt.tree[pos - 1].clone());
-                    }
-                }
-                pos = parent;
-            }
-            Some(Self {
-                obj: h,
-                pos: i as u64,
-                hashes,
-            })
-        } else {
-            None
+    pub(crate) fn new<M: AsMerkleTree<D>>(
+        h: Hash<D>,
+        tree_size: u64,
+        mt: &M,
+    ) -> io::Result<Option<Self>> {
+        match mt.as_merkle_tree().try_index_of(h)? {
+            Some(i) => Self::from_index(i, tree_size, mt),
+            None => Ok(None),
         }
     }
 
-    fn calc(&self, mut n: u64) -> Hash<D> {
-        let mut hash = D::hash_leaf(&self.obj);
-
-        let mut order = Vec::new();
+    pub(crate) fn from_index<M: AsMerkleTree<D>>(
+        i: u64,
+        tree_size: u64,
+        mt: &M,
+    ) -> io::Result<Option<Self>> {
+        let mt = mt.as_merkle_tree();
+        if i >= tree_size || tree_size > mt.len() {
+            return Ok(None);
+        }
 
-        let mut m = self.pos;
-        for _ in 0..self.hashes.len() {
-            let k = n.next_power_of_two() / 2;
+        // Iterative version of PATH from RFC 6962, section 2.1.1.
+        let mut hashes = Vec::new();
+        let mut m = i;
+        let mut n = tree_size;
+        let mut offset = 0;
+        while n > 1 {
+            let k = rfc6962::split(n);
             if m < k {
+                hashes.push(mt.hash_from_range(offset + k, offset + n - 1)?);
                 n = k;
-                order.push(Order::Left);
             } else {
-                n -= k;
+                hashes.push(mt.hash_from_range(offset, offset + k - 1)?);
                 m -= k;
-                order.push(Order::Right);
+                offset += k;
+                n -= k;
             }
         }
+        hashes.reverse();
 
-        for (h, o) in self.hashes.iter().zip(order.iter().rev()) {
-            hash = match *o {
-                Order::Left => D::hash_inner(&hash, h),
-                Order::Right => D::hash_inner(h, &hash),
-            };
-        }
-        hash
+        Ok(Some(Self {
+            leaf: mt.hash_from_range(i, i)?,
+            pos: i,
+            hashes,
+        }))
+    }
+
+    fn verify(&self, th: &TreeHead<D>) -> Result<(), VerificationError> {
+        verify_inclusion(&self.leaf, self.pos, &self.hashes, th)
     }
 }
 
@@ -79,26 +132,29 @@ pub(crate) struct ConsistencyProofBase<D: Digest> {
 impl<D: Digest> ConsistencyProofBase<D> {
     pub(crate) fn new<M: AsMerkleTree<D>>(
         old_size: u64,
+        new_size: u64,
         mt: &M,
-    ) -> Option<Self> {
+    ) -> io::Result<Option<Self>> {
         let mt = mt.as_merkle_tree();
-        let mut n = mt.len() as u64;
+        let mut n = new_size;
         let mut m = old_size;
         let mut hashes = Vec::new();
-        if m > n {
-            return None;
-        } else if m == n {
-            return Some(ConsistencyProofBase { old_size, hashes });
+        if m > n || n > mt.len() {
+            return Ok(None);
+        } else if m == 0 || m == n {
+            return Ok(Some(ConsistencyProofBase { old_size, hashes }));
         }
+        // Iterative version of SUBPROOF from RFC 6962, section 2.1.2. The
+        // sibling hashes are collected top-down and reversed at the end.
         let mut b = true;
         let mut offset = 0;
         while m < n {
-            let k = n.next_power_of_two() / 2;
+            let k = rfc6962::split(n);
             if m <= k {
-                hashes.push(mt.hash_from_range(offset + k, offset + n - 1));
+                hashes.push(mt.hash_from_range(offset + k, offset + n - 1)?);
                 n = k;
             } else {
-                hashes.push(mt.hash_from_range(offset, offset + k - 1));
+                hashes.push(mt.hash_from_range(offset, offset + k - 1)?);
                 b = false;
                 m -= k;
                 offset += k;
@@ -106,86 +162,36 @@ impl<D: Digest> ConsistencyProofBase<D> {
             }
         }
         if !b {
-            hashes.push(mt.hash_from_range(offset, offset + m - 1))
-        }
-
-        if old_size.is_power_of_two() {
-            hashes.push(mt.hash_from_range(0, m - 1));
+            hashes.push(mt.hash_from_range(offset, offset + m - 1)?)
         }
+        hashes.reverse();
 
-        Some(ConsistencyProofBase {
-            old_size: old_size,
-            hashes,
-        })
+        Ok(Some(ConsistencyProofBase { old_size, hashes }))
     }
 
-    fn calc_old(&self, mut n1: u64, old_treehead: &Hash<D>) -> Hash<D> {
-        if self.hashes.is_empty() {
-            return old_treehead.clone();
-        }
-
-        let mut n0 = self.old_size;
-        let mut hashes: Vec<&Hash<D>> = Vec::new();
-        let mut flag = false;
-        for h in &self.hashes {
-            if flag {
-                hashes.push(h);
-                break;
-            }
-            let k = n1.next_power_of_two() / 2;
-            if n0 < k {
-                n1 = k;
-            } else if n0 == k {
-                flag = true;
-            } else {
-                hashes.push(h);
-                n0 -= k;
-                n1 -= k;
-            }
-        }
-        /* XXXif !flag {
-            return false;
-        }*/
-
-        let mut hashcalc = hashes[hashes.len() - 1].clone();
-        for h in hashes.iter().rev().skip(1) {
-            hashcalc = D::hash_inner(h, &hashcalc);
-        }
-        hashcalc
+    fn verify(
+        &self,
+        old_treehead: &Hash<D>,
+        th: &TreeHead<D>,
+    ) -> Result<(), VerificationError> {
+        rfc6962::verify_consistency::<D>(
+            self.old_size,
+            th.size(),
+            old_treehead,
+            th.root_hash(),
+            &self.hashes,
+        )
     }
 
-    fn calc_new(&self, mut n1: u64, old_treehead: &Hash<D>) -> Hash<D> {
-        if self.hashes.is_empty() {
-            return old_treehead.clone();
+    fn verify_against(
+        &self,
+        old: &TreeHead<D>,
+        new: &TreeHead<D>,
+    ) -> Result<(), VerificationError> {
+        if old.size() != self.old_size {
+            return Err(VerificationError::MalformedProof);
         }
-
-        let mut order = Vec::new();
-        let mut n0 = self.old_size;
-        for _ in 0..self.hashes.len() - 2 {
-            let k = n1.next_power_of_two() / 2;
-            if n0 < k {
-                order.push(Order::Right);
-                n1 = k;
-            } else {
-                order.push(Order::Left);
-                n0 -= k;
-                n1 -= k;
-            }
-        }
-        order.push(Order::Right);
-
-        let mut hashcalc = self.hashes[self.hashes.len() - 1].clone();
-
-        assert!(self.hashes.len() == order.len() + 1);
-
-        for (h, o) in self.hashes.iter().rev().skip(1).zip(order.iter().rev()) {
-            hashcalc = match *o {
-                Order::Left => D::hash_inner(h, &hashcalc),
-                Order::Right => D::hash_inner(&hashcalc, h),
-            };
-        }
-
-        hashcalc
+        self.verify(old.root_hash(), new)
     }
 }
 
@@ -202,8 +208,46 @@ impl<D: Digest> InclusionProof<D> {
         Self { base, th }
     }
 
-    pub fn verify(&self) -> bool {
-        self.base.calc(self.th.size()) == *self.th.root_hash()
+    /// Verify the proof against the tree head bundled with it.
+    ///
+    /// This only shows that the proof is internally consistent. Use
+    /// `verify_against` with a tree head obtained from a trusted source to
+    /// make sure the entry is part of the log you know about.
+    pub fn verify(&self) -> Result<(), VerificationError> {
+        self.base.verify(&self.th)
+    }
+
+    /// Verify the proof against an explicitly trusted tree head, ignoring
+    /// the head bundled with the proof.
+    pub fn verify_against(
+        &self,
+        trusted: &TreeHead<D>,
+    ) -> Result<(), VerificationError> {
+        self.base.verify(trusted)
+    }
+
+    /// The RFC 6962 leaf hash, `HASH(0x00 || entry hash)`, of the entry.
+    pub fn leaf_hash(&self) -> &Hash<D> {
+        &self.base.leaf
+    }
+
+    /// Position of the entry in the tree.
+    pub fn index(&self) -> u64 {
+        self.base.pos
+    }
+
+    /// Size of the tree the audit path was computed for.
+    pub fn tree_size(&self) -> u64 {
+        self.th.size()
+    }
+
+    /// The audit path, ordered from the leaf towards the root.
+    pub fn audit_path(&self) -> &[Hash<D>] {
+        &self.base.hashes
+    }
+
+    pub fn tree_head(&self) -> &TreeHead<D> {
+        &self.th
     }
 }
 
@@ -223,12 +267,40 @@ impl<D: Digest> ConsistencyProof<D> {
         ConsistencyProof { base, th }
     }
 
-    pub fn verify(&self, old_treehead: &Hash<D>) -> bool {
-        if self.base.calc_old(self.th.size(), old_treehead) != *old_treehead {
-            return false;
-        }
+    /// Verify the proof against the old root hash and the tree head bundled
+    /// with the proof.
+    pub fn verify(
+        &self,
+        old_treehead: &Hash<D>,
+    ) -> Result<(), VerificationError> {
+        self.base.verify(old_treehead, &self.th)
+    }
+
+    /// Verify the proof between two explicitly trusted tree heads, ignoring
+    /// the head bundled with the proof.
+    pub fn verify_against(
+        &self,
+        old: &TreeHead<D>,
+        new: &TreeHead<D>,
+    ) -> Result<(), VerificationError> {
+        self.base.verify_against(old, new)
+    }
 
-        self.base.calc_new(self.th.size(), old_treehead) == *self.th.root_hash()
+    pub fn old_size(&self) -> u64 {
+        self.base.old_size
+    }
+
+    pub fn new_size(&self) -> u64 {
+        self.th.size()
+    }
+
+    /// The consistency path as defined in RFC 6962.
+    pub fn consistency_path(&self) -> &[Hash<D>] {
+        &self.base.hashes
+    }
+
+    pub fn tree_head(&self) -> &TreeHead<D> {
+        &self.th
     }
 }
 
@@ -250,12 +322,47 @@ impl<D: Digest> SignedInclusionProof<D> {
         Self { base, sth }
     }
 
-    pub fn verify(&self, pk: &PubKey) -> bool {
-        if self.sth.verify(pk) {
-            self.base.calc(self.sth.size()) == *self.sth.root_hash()
-        } else {
-            false
-        }
+    /// Check the proof against the signed head, which is checked with the
+    /// key of its log from `keys`.
+    pub fn verify<K: KeySource>(
+        &self,
+        keys: &K,
+    ) -> Result<(), VerificationError> {
+        self.sth.verify(keys)?;
+        self.base.verify(self.sth.tree_head())
+    }
+
+    /// See `InclusionProof::verify_against`.
+    pub fn verify_against(
+        &self,
+        trusted: &TreeHead<D>,
+    ) -> Result<(), VerificationError> {
+        self.base.verify(trusted)
+    }
+
+    pub fn leaf_hash(&self) -> &Hash<D> {
+        &self.base.leaf
+    }
+
+    pub fn index(&self) -> u64 {
+        self.base.pos
+    }
+
+    pub fn tree_size(&self) -> u64 {
+        self.sth.size()
+    }
+
+    pub fn audit_path(&self) -> &[Hash<D>] {
+        &self.base.hashes
+    }
+
+    pub fn signed_tree_head(&self) -> &SignedTreeHead<D> {
+        &self.sth
+    }
+
+    /// Identifier of the log that signed the head.
+    pub fn log_id(&self) -> LogId {
+        self.sth.log_id()
     }
 }
 
@@ -277,27 +384,81 @@ impl<D: Digest> SignedConsistencyProof<D> {
         Self { base, sth }
     }
 
-    pub fn verify(&self, old_treehead: &Hash<D>, pk: &PubKey) -> bool {
-        if self.sth.verify(pk) {
-            if self.base.calc_old(self.sth.size(), old_treehead)
-                != *old_treehead
-            {
-                return false;
-            }
-            self.base.calc_new(self.sth.size(), old_treehead)
-                == *self.sth.root_hash()
-        } else {
-            false
-        }
+    pub fn verify<K: KeySource>(
+        &self,
+        old_treehead: &Hash<D>,
+        keys: &K,
+    ) -> Result<(), VerificationError> {
+        self.sth.verify(keys)?;
+        self.base.verify(old_treehead, self.sth.tree_head())
+    }
+
+    /// See `ConsistencyProof::verify_against`.
+    pub fn verify_against(
+        &self,
+        old: &TreeHead<D>,
+        new: &TreeHead<D>,
+    ) -> Result<(), VerificationError> {
+        self.base.verify_against(old, new)
+    }
+
+    pub fn old_size(&self) -> u64 {
+        self.base.old_size
+    }
+
+    pub fn new_size(&self) -> u64 {
+        self.sth.size()
+    }
+
+    pub fn consistency_path(&self) -> &[Hash<D>] {
+        &self.base.hashes
+    }
+
+    pub fn signed_tree_head(&self) -> &SignedTreeHead<D> {
+        &self.sth
+    }
+
+    /// Identifier of the log that signed the head.
+    pub fn log_id(&self) -> LogId {
+        self.sth.log_id()
     }
 }
 
-#[derive(PartialEq, Eq)]
-enum Order {
-    Left,
-    Right,
+/// Verify that `leaf_hash` is at position `index` of the tree described by
+/// the trusted head `th`, using an RFC 6962 audit path.
+pub fn verify_inclusion<D: Digest>(
+    leaf_hash: &Hash<D>,
+    index: u64,
+    audit_path: &[Hash<D>],
+    th: &TreeHead<D>,
+) -> Result<(), VerificationError> {
+    rfc6962::verify_inclusion::<D>(
+        leaf_hash,
+        index,
+        th.size(),
+        audit_path,
+        th.root_hash(),
+    )
+}
+
+/// Verify that the tree described by the trusted head `new` is an extension
+/// of the one described by `old`, using an RFC 6962 consistency path.
+pub fn verify_consistency<D: Digest>(
+    old: &TreeHead<D>,
+    new: &TreeHead<D>,
+    consistency_path: &[Hash<D>],
+) -> Result<(), VerificationError> {
+    rfc6962::verify_consistency::<D>(
+        old.size(),
+        new.size(),
+        old.root_hash(),
+        new.root_hash(),
+        consistency_path,
+    )
 }
 
 pub(crate) trait AsMerkleTree<D: Digest> {
-    fn as_merkle_tree(&self) -> &MerkleTree<D>;
+    type Storage: Storage<D>;
+
+    fn as_merkle_tree(&self) -> &MerkleTree<D, Self::Storage>;
 }
@@ -0,0 +1,225 @@
+//! Merkle tree algorithms as specified in RFC 6962 (Certificate
+//! Transparency) and its successor RFC 9162.
+//!
+//! The functions in this module operate on *leaf hashes*, that is values
+//! that already carry the `0x00` leaf prefix (see [`leaf_hash`] and
+//! `Digest::hash_leaf`). This makes them usable both with trees built by
+//! this crate and with audit paths and consistency proofs obtained from
+//! other RFC 6962 logs.
+//!
+//! [`leaf_hash`]: fn.leaf_hash.html
+
+use digest::{Digest, Hash};
+use proof::VerificationError;
+
+/// Hash raw leaf data as `HASH(0x00 || data)`.
+pub fn leaf_hash<D: Digest>(data: &[u8]) -> Hash<D> {
+    let mut hasher = D::default();
+    hasher.process(&[0x00]);
+    hasher.process(data);
+    hasher.fixed_result()
+}
+
+/// The Merkle Tree Hash `MTH(D[n])` of a list of leaf hashes.
+pub fn root<D: Digest>(leaves: &[Hash<D>]) -> Hash<D> {
+    match leaves.len() {
+        0 => D::default().fixed_result(),
+        1 => leaves[0].clone(),
+        n => {
+            let k = split(n as u64) as usize;
+            D::hash_inner(&root::<D>(&leaves[..k]), &root::<D>(&leaves[k..]))
+        }
+    }
+}
+
+/// The audit path `PATH(m, D[n])` for the leaf at `index`.
+///
+/// Returns `None` if `index` is not smaller than the number of leaves.
+pub fn inclusion_path<D: Digest>(
+    index: u64,
+    leaves: &[Hash<D>],
+) -> Option<Vec<Hash<D>>> {
+    if index >= leaves.len() as u64 {
+        return None;
+    }
+    let mut path = Vec::new();
+    path_rec::<D>(index as usize, leaves, &mut path);
+    Some(path)
+}
+
+fn path_rec<D: Digest>(m: usize, leaves: &[Hash<D>], path: &mut Vec<Hash<D>>) {
+    let n = leaves.len();
+    if n <= 1 {
+        return;
+    }
+    let k = split(n as u64) as usize;
+    if m < k {
+        path_rec::<D>(m, &leaves[..k], path);
+        path.push(root::<D>(&leaves[k..]));
+    } else {
+        path_rec::<D>(m - k, &leaves[k..], path);
+        path.push(root::<D>(&leaves[..k]));
+    }
+}
+
+/// The consistency proof `PROOF(m, D[n])` between the first `old_size`
+/// leaves and all of `leaves`.
+///
+/// Proofs from the empty tree and between equal sizes are empty. Returns
+/// `None` if `old_size` exceeds the number of leaves.
+pub fn consistency_proof<D: Digest>(
+    old_size: u64,
+    leaves: &[Hash<D>],
+) -> Option<Vec<Hash<D>>> {
+    if old_size > leaves.len() as u64 {
+        return None;
+    }
+    let mut proof = Vec::new();
+    if old_size > 0 {
+        subproof_rec::<D>(old_size as usize, leaves, true, &mut proof);
+    }
+    Some(proof)
+}
+
+fn subproof_rec<D: Digest>(
+    m: usize,
+    leaves: &[Hash<D>],
+    b: bool,
+    proof: &mut Vec<Hash<D>>,
+) {
+    let n = leaves.len();
+    if m == n {
+        if !b {
+            proof.push(root::<D>(leaves));
+        }
+        return;
+    }
+    let k = split(n as u64) as usize;
+    if m <= k {
+        subproof_rec::<D>(m, &leaves[..k], b, proof);
+        proof.push(root::<D>(&leaves[k..]));
+    } else {
+        subproof_rec::<D>(m - k, &leaves[k..], false, proof);
+        proof.push(root::<D>(&leaves[..k]));
+    }
+}
+
+/// Verify an audit path as described in RFC 9162, section 2.1.3.2.
+pub fn verify_inclusion<D: Digest>(
+    leaf_hash: &Hash<D>,
+    index: u64,
+    tree_size: u64,
+    path: &[Hash<D>],
+    root: &Hash<D>,
+) -> Result<(), VerificationError> {
+    if index >= tree_size {
+        return Err(VerificationError::IndexOutOfRange);
+    }
+
+    let mut fnode = index;
+    let mut snode = tree_size - 1;
+    let mut r = leaf_hash.clone();
+
+    for p in path {
+        if snode == 0 {
+            return Err(VerificationError::WrongPathLength);
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            r = D::hash_inner(p, &r);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            r = D::hash_inner(&r, p);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+
+    if snode != 0 {
+        Err(VerificationError::WrongPathLength)
+    } else if r != *root {
+        Err(VerificationError::RootMismatch)
+    } else {
+        Ok(())
+    }
+}
+
+/// Verify a consistency proof as described in RFC 9162, section 2.1.4.2.
+///
+/// Every tree is consistent with the empty tree; a proof from size zero or
+/// between equal sizes must be empty.
+pub fn verify_consistency<D: Digest>(
+    old_size: u64,
+    new_size: u64,
+    old_root: &Hash<D>,
+    new_root: &Hash<D>,
+    proof: &[Hash<D>],
+) -> Result<(), VerificationError> {
+    if old_size > new_size {
+        return Err(VerificationError::IndexOutOfRange);
+    }
+    if old_size == new_size || old_size == 0 {
+        if !proof.is_empty() {
+            return Err(VerificationError::WrongPathLength);
+        }
+        if old_size != 0 && old_root != new_root {
+            return Err(VerificationError::RootMismatch);
+        }
+        return Ok(());
+    }
+
+    let mut path = proof.iter();
+    let seed = if old_size.is_power_of_two() {
+        Some(old_root)
+    } else {
+        path.next()
+    };
+    let seed = match seed {
+        Some(seed) => seed,
+        None => return Err(VerificationError::WrongPathLength),
+    };
+
+    let mut fnode = old_size - 1;
+    let mut snode = new_size - 1;
+    while fnode & 1 == 1 {
+        fnode >>= 1;
+        snode >>= 1;
+    }
+
+    let mut fr = seed.clone();
+    let mut sr = seed.clone();
+    for c in path {
+        if snode == 0 {
+            return Err(VerificationError::WrongPathLength);
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            fr = D::hash_inner(c, &fr);
+            sr = D::hash_inner(c, &sr);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            sr = D::hash_inner(&sr, c);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+
+    if snode != 0 {
+        Err(VerificationError::WrongPathLength)
+    } else if fr != *old_root {
+        Err(VerificationError::OldRootMismatch)
+    } else if sr != *new_root {
+        Err(VerificationError::RootMismatch)
+    } else {
+        Ok(())
+    }
+}
+
+/// The largest power of two smaller than `n`, for `n > 1`.
+pub(crate) fn split(n: u64) -> u64 {
+    n.next_power_of_two() / 2
+}
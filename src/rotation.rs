@@ -0,0 +1,219 @@
+//! Rotating the key of a log.
+//!
+//! A log switching keys signs a `KeyTransition` with its old key, naming
+//! the new key and the tree size from which on it is used. The old key
+//! signs heads up to that size and the new key signs heads from that size
+//! on, so the head at the size of the transition may carry either
+//! signature. A `KeyHistory` starts at the key a client trusted first and
+//! follows the transitions of the log to check heads signed by any of its
+//! keys.
+
+use digest::Digest;
+use keyring::LogId;
+use proof::VerificationError;
+use signed_merkle::{self, SignedTreeHead, SIGNATURE_TYPE_KEY_TRANSITION};
+use signer::{LogKey, TreeHeadSigner, Verifier};
+use std::io;
+
+/// Version of the signing input of key transitions.
+const KEY_TRANSITION_VERSION: u8 = 0;
+
+/// Longest key that fits the two byte length in the signing input.
+const MAX_KEY_LEN: usize = 0xffff;
+
+/// A statement by the old key of a log that the log continues with a new
+/// key from `tree_size` on.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyTransition {
+    version: u8,
+    timestamp: u64,
+    tree_size: u64,
+    old_key: LogKey,
+    new_key: LogKey,
+    sig: Vec<u8>,
+}
+
+impl KeyTransition {
+    /// Have `old` sign the transition to `new` at `tree_size`, waiting for
+    /// the signature if needed.
+    pub(crate) fn signed<K: TreeHeadSigner, V: Verifier>(
+        old: &K,
+        new: &V,
+        tree_size: u64,
+    ) -> io::Result<Self> {
+        let old_key = old.verifier();
+        let mut transition = KeyTransition {
+            version: KEY_TRANSITION_VERSION,
+            timestamp: signed_merkle::now_millis(),
+            tree_size,
+            old_key: LogKey::from_verifier(&old_key),
+            new_key: LogKey::from_verifier(new),
+            sig: Vec::new(),
+        };
+        if transition.old_key.key_bytes().len() > MAX_KEY_LEN
+            || transition.new_key.key_bytes().len() > MAX_KEY_LEN
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "key too long for a key transition",
+            ));
+        }
+        let pending = old.sign_tree_head(&transition.signing_input());
+        transition.sig = pending.wait()?;
+        transition.verify(&old_key).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "signer returned an invalid key transition signature",
+            )
+        })?;
+        Ok(transition)
+    }
+
+    /// The data covered by the signature:
+    ///
+    /// ```text
+    /// u8 version; u8 signature_type; u64 timestamp; u64 tree_size;
+    /// u16 old_algorithm; opaque old_key<0..2^16-1>;
+    /// u16 new_algorithm; opaque new_key<0..2^16-1>;
+    /// ```
+    ///
+    /// Encoded like the signing input of a `SignedTreeHead`, whose
+    /// signature type keeps the two apart, except that keys get a two byte
+    /// length as they may be longer than 255 bytes.
+    fn signing_input(&self) -> Vec<u8> {
+        let mut input = vec![self.version, SIGNATURE_TYPE_KEY_TRANSITION];
+        signed_merkle::push_u64(&mut input, self.timestamp);
+        signed_merkle::push_u64(&mut input, self.tree_size);
+        for key in &[&self.old_key, &self.new_key] {
+            let alg = key.algorithm().0;
+            input.push((alg >> 8) as u8);
+            input.push(alg as u8);
+            let len = key.key_bytes().len();
+            assert!(len <= MAX_KEY_LEN, "key longer than 65535 bytes");
+            input.push((len >> 8) as u8);
+            input.push(len as u8);
+            input.extend_from_slice(key.key_bytes());
+        }
+        input
+    }
+
+    /// Check that `old` signed the transition.
+    pub fn verify<V: Verifier>(
+        &self,
+        old: &V,
+    ) -> Result<(), VerificationError> {
+        if self.version != KEY_TRANSITION_VERSION
+            || self.old_key.key_bytes().len() > MAX_KEY_LEN
+            || self.new_key.key_bytes().len() > MAX_KEY_LEN
+        {
+            return Err(VerificationError::MalformedProof);
+        }
+        if self.old_key != LogKey::from_verifier(old) {
+            return Err(VerificationError::BadSignature);
+        }
+        if old.verify_message(&self.signing_input(), &self.sig) {
+            Ok(())
+        } else {
+            Err(VerificationError::BadSignature)
+        }
+    }
+
+    /// Milliseconds since the UNIX epoch at which the transition was
+    /// signed.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// The size of the first head signed by the new key.
+    pub fn tree_size(&self) -> u64 {
+        self.tree_size
+    }
+
+    pub fn old_key(&self) -> &LogKey {
+        &self.old_key
+    }
+
+    pub fn new_key(&self) -> &LogKey {
+        &self.new_key
+    }
+}
+
+/// The keys a log signed its heads with, starting at a trusted key and
+/// following verified transitions.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Clone, Debug)]
+pub struct KeyHistory {
+    first: LogKey,
+    transitions: Vec<KeyTransition>,
+}
+
+impl KeyHistory {
+    /// A history starting at `key`, which signed all heads so far.
+    pub fn new<V: Verifier>(key: &V) -> Self {
+        KeyHistory {
+            first: LogKey::from_verifier(key),
+            transitions: Vec::new(),
+        }
+    }
+
+    /// Follow `transition` if it is signed by the current key and does not
+    /// go back in time.
+    pub fn add_transition(
+        &mut self,
+        transition: KeyTransition,
+    ) -> Result<(), VerificationError> {
+        let from = self.transitions.last().map_or(0, |t| t.tree_size);
+        if transition.tree_size < from {
+            return Err(VerificationError::TransitionOutOfOrder);
+        }
+        transition.verify(self.current())?;
+        self.transitions.push(transition);
+        Ok(())
+    }
+
+    /// The key signing new heads.
+    pub fn current(&self) -> &LogKey {
+        self.transitions.last().map_or(&self.first, |t| &t.new_key)
+    }
+
+    pub fn transitions(&self) -> &[KeyTransition] {
+        &self.transitions
+    }
+
+    /// The key that signs heads of `tree_size` with the log ID `log_id`,
+    /// if there is one.
+    pub fn key_for(
+        &self,
+        log_id: &LogId,
+        tree_size: u64,
+    ) -> Option<&LogKey> {
+        let mut from = 0;
+        let mut key = &self.first;
+        for t in &self.transitions {
+            if key.log_id() == *log_id
+                && from <= tree_size
+                && tree_size <= t.tree_size
+            {
+                return Some(key);
+            }
+            from = t.tree_size;
+            key = &t.new_key;
+        }
+        if key.log_id() == *log_id && tree_size >= from {
+            return Some(key);
+        }
+        None
+    }
+
+    /// Check that `sth` is signed by the key the log used at its size.
+    pub fn verify<D: Digest>(
+        &self,
+        sth: &SignedTreeHead<D>,
+    ) -> Result<(), VerificationError> {
+        match self.key_for(&sth.log_id(), sth.size()) {
+            Some(key) => sth.verify(key),
+            None => Err(VerificationError::BadSignature),
+        }
+    }
+}
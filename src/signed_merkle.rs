@@ -1,49 +1,241 @@
+use checkpoint::{self, Checkpoint, NoteError, SignedCheckpoint};
 use digest::{Digest, Digestible};
+use keys::SEED_LEN;
 use digest::AsHash;
 use digest::Hash;
-use merkle::{MerkleTree, OwningMerkleTree, TreeHead};
+use merkle::{DuplicatePolicy, InsertError, Inserted, MerkleTree,
+             OwningMerkleTree, TreeHead, READ_FAILED};
 use proof::*;
 use proof::SignedInclusionProof;
+use promise::{InclusionPromise, DEFAULT_MERGE_DELAY};
+use rotation::KeyTransition;
 use ring::{rand, signature};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Deserializer, Serialize};
 #[cfg(feature = "serde")]
 use serde::de::Error as SerdeError;
-use std::{fmt, iter};
+use keyring::{KeySource, LogId};
+use signer::{PendingSignature, SignatureAlgorithm, Signer, TreeHeadSigner,
+             Verifier};
+use std::{fmt, io, iter, mem, ops, slice};
+use std::collections::HashSet;
 use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
 use untrusted;
 
+/// Version of the signing input of tree heads.
+const TREE_HEAD_VERSION: u8 = 1;
+/// Version of the signing input of witness cosignatures.
+const COSIGNATURE_VERSION: u8 = 0;
+/// Distinguishes signed tree heads from other data signed with the log key.
+const SIGNATURE_TYPE_TREE_HASH: u8 = 1;
+/// Distinguishes witness cosignatures from signatures by the log itself.
+const SIGNATURE_TYPE_COSIGNATURE: u8 = 2;
+/// Distinguishes key transitions from tree heads signed with the old key.
+pub(crate) const SIGNATURE_TYPE_KEY_TRANSITION: u8 = 3;
+/// Distinguishes inclusion promises from tree heads.
+pub(crate) const SIGNATURE_TYPE_PROMISE: u8 = 4;
+
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct SignedTreeHead<D: Digest> {
     #[cfg_attr(feature = "serde", serde(bound = ""))]
     th: TreeHead<D>,
+    version: u8,
+    timestamp: u64,
+    log_id: LogId,
     sig: Vec<u8>,
+    sig_alg: SignatureAlgorithm,
+    cosigs: Vec<Cosignature>,
 }
 
 impl<D: Digest> Clone for SignedTreeHead<D> {
     fn clone(&self) -> Self {
         SignedTreeHead {
             th: self.th.clone(),
+            version: self.version,
+            timestamp: self.timestamp,
+            log_id: self.log_id,
             sig: self.sig.clone(),
+            sig_alg: self.sig_alg,
+            cosigs: self.cosigs.clone(),
         }
     }
 }
 
+/// A witness signature on a signed tree head, stating that the witness
+/// checked the head to be consistent with all heads it saw before.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cosignature {
+    witness: Vec<u8>,
+    timestamp: u64,
+    sig: Vec<u8>,
+}
+
+impl Cosignature {
+    /// Public key of the witness.
+    pub fn witness(&self) -> &[u8] {
+        &self.witness
+    }
+
+    /// Milliseconds since the UNIX epoch at which the head was cosigned.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        &self.sig
+    }
+}
+
 impl<D: Digest> SignedTreeHead<D> {
-    fn new(kp: &KeyPair, th: TreeHead<D>) -> Self {
-        Self {
-            sig: Vec::from(kp.sign(th.root_hash().as_slice()).as_ref()),
+    /// Ask `signer` to sign `th`. The head is only valid once the
+    /// signature is passed to `complete`.
+    fn request<K: TreeHeadSigner>(
+        signer: &K,
+        th: TreeHead<D>,
+    ) -> (Self, PendingSignature) {
+        let pk = signer.verifier();
+        let sth = Self {
             th,
+            version: TREE_HEAD_VERSION,
+            timestamp: now_millis(),
+            log_id: pk.log_id(),
+            sig: Vec::new(),
+            sig_alg: pk.algorithm(),
+            cosigs: Vec::new(),
+        };
+        let pending = signer.sign_tree_head(&sth.signing_input());
+        (sth, pending)
+    }
+
+    /// Sign `th` with `signer`, waiting for the signature if needed.
+    fn signed<K: TreeHeadSigner>(
+        signer: &K,
+        th: TreeHead<D>,
+    ) -> io::Result<Self> {
+        let (mut sth, pending) = Self::request(signer, th);
+        sth.complete(pending.wait()?, &signer.verifier())?;
+        Ok(sth)
+    }
+
+    /// Fill in the signature returned by a signer, which is not trusted to
+    /// return valid signatures.
+    fn complete<V: Verifier>(
+        &mut self,
+        sig: Vec<u8>,
+        pubkey: &V,
+    ) -> io::Result<()> {
+        self.sig = sig;
+        self.verify(pubkey).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "signer returned an invalid tree head signature",
+            )
+        })
+    }
+
+    /// The data covered by the signature, modeled after the RFC 6962
+    /// `TreeHeadSignature`:
+    ///
+    /// ```text
+    /// u8 version; u8 signature_type; u64 timestamp; u64 tree_size;
+    /// opaque log_id<0..255>; opaque root_hash<0..255>;
+    /// ```
+    ///
+    /// All integers are big endian, variable length fields are prefixed
+    /// with a single length byte.
+    fn signing_input(&self) -> Vec<u8> {
+        let root = self.th.root_hash();
+        let log_id = self.log_id.as_bytes();
+        let mut input = Vec::with_capacity(20 + log_id.len() + root.len());
+        input.push(self.version);
+        input.push(SIGNATURE_TYPE_TREE_HASH);
+        push_u64(&mut input, self.timestamp);
+        push_u64(&mut input, self.th.size());
+        push_opaque(&mut input, log_id);
+        push_opaque(&mut input, root);
+        input
+    }
+
+    /// Check the signature with the key of the log that signed the head,
+    /// taken from `keys`: a single `Verifier` or a `KeyRing`.
+    pub fn verify<K: KeySource>(
+        &self,
+        keys: &K,
+    ) -> Result<(), VerificationError> {
+        if self.version != TREE_HEAD_VERSION {
+            return Err(VerificationError::MalformedProof);
+        }
+        let pubkey = match keys.key_for(&self.log_id) {
+            Some(pubkey) => pubkey,
+            None => return Err(VerificationError::BadSignature),
+        };
+        if self.sig_alg != pubkey.algorithm() {
+            return Err(VerificationError::BadSignature);
+        }
+        if pubkey.verify_message(&self.signing_input(), &self.sig) {
+            Ok(())
+        } else {
+            Err(VerificationError::BadSignature)
         }
     }
 
-    pub fn verify(&self, pubkey: &PubKey) -> bool {
-        signature::verify(
-            &signature::ED25519,
-            untrusted::Input::from(&pubkey.0[..]),
-            untrusted::Input::from(self.th.root_hash()),
-            untrusted::Input::from(self.sig.as_slice()),
-        ).is_ok()
+    /// Cosign the head with the witness key `signer`. Use a `Witness` to
+    /// only cosign heads consistent with earlier ones.
+    pub(crate) fn cosign<S: Signer>(
+        &self,
+        signer: &S,
+    ) -> Result<Cosignature, RingError> {
+        let mut cosig = Cosignature {
+            witness: signer.public_key().key_bytes().to_vec(),
+            timestamp: now_millis(),
+            sig: Vec::new(),
+        };
+        let input = self.cosigning_input(cosig.timestamp);
+        cosig.sig = signer.sign_message(&input)?;
+        Ok(cosig)
+    }
+
+    /// The data covered by a cosignature made at `timestamp`: the version,
+    /// the cosignature type and the timestamp, followed by the input of
+    /// the log signature.
+    fn cosigning_input(&self, timestamp: u64) -> Vec<u8> {
+        let mut input = vec![COSIGNATURE_VERSION, SIGNATURE_TYPE_COSIGNATURE];
+        push_u64(&mut input, timestamp);
+        input.extend_from_slice(&self.signing_input());
+        input
+    }
+
+    /// Attach a cosignature, for example one returned by a remote witness.
+    /// It is not checked, use `verify_cosignature` or a `Policy` for that.
+    pub fn add_cosignature(&mut self, cosig: Cosignature) {
+        self.cosigs.push(cosig);
+    }
+
+    pub fn cosignatures(&self) -> &[Cosignature] {
+        &self.cosigs
+    }
+
+    /// Check that the witness key `witness` cosigned the head, and return
+    /// the time of the cosignature. Other cosignatures are ignored.
+    pub fn verify_cosignature<V: Verifier>(
+        &self,
+        witness: &V,
+    ) -> Result<u64, VerificationError> {
+        self.cosigs
+            .iter()
+            .filter(|c| c.witness[..] == *witness.key_bytes())
+            .find(|c| {
+                let input = self.cosigning_input(c.timestamp);
+                witness.verify_message(&input, &c.sig)
+            })
+            .map(|c| c.timestamp)
+            .ok_or(VerificationError::BadSignature)
+    }
+
+    pub fn tree_head(&self) -> &TreeHead<D> {
+        &self.th
     }
 
     pub fn size(&self) -> u64 {
@@ -53,167 +245,797 @@ impl<D: Digest> SignedTreeHead<D> {
     pub fn root_hash(&self) -> &Hash<D> {
         self.th.root_hash()
     }
+
+    /// Milliseconds since the UNIX epoch at which the head was signed.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Identifier of the log that signed this head.
+    pub fn log_id(&self) -> LogId {
+        self.log_id
+    }
+
+    /// The algorithm of the log signature.
+    pub fn algorithm(&self) -> SignatureAlgorithm {
+        self.sig_alg
+    }
 }
 
+pub(crate) fn push_u64(buf: &mut Vec<u8>, x: u64) {
+    for i in (0..8).rev() {
+        buf.push((x >> (i * 8)) as u8);
+    }
+}
+
+/// Append `bytes` with a single length byte. Only used for log IDs and
+/// hashes, which are much shorter.
+pub(crate) fn push_opaque(buf: &mut Vec<u8>, bytes: &[u8]) {
+    assert!(bytes.len() <= 0xff, "opaque field longer than 255 bytes");
+    buf.push(bytes.len() as u8);
+    buf.extend_from_slice(bytes);
+}
+
+pub(crate) fn now_millis() -> u64 {
+    let d = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    d.as_secs() * 1000 + u64::from(d.subsec_millis())
+}
+
+/// A Merkle tree whose heads are signed by `K`.
+///
+/// Every change to the tree asks the signer for a new signed head. Until a
+/// deferred signer answers, `head` and the proofs stay at the last signed
+/// head; `poll_head` and `wait_for_head` pick up the signature. To add many
+/// entries under a single signature, `stage` them and `commit` the batch.
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
-pub struct SignedMerkleTree<D: Digest> {
+pub struct SignedMerkleTree<D: Digest, K: TreeHeadSigner = KeyPair> {
     #[cfg_attr(feature = "serde", serde(bound = ""))]
     mt: MerkleTree<D>,
-    keypair: KeyPair,
+    #[cfg_attr(feature = "serde", serde(
+            bound(serialize = "K: Serialize",
+                  deserialize = "K: Deserialize<'de>")))]
+    signer: K,
     #[cfg_attr(feature = "serde", serde(bound = ""))]
     sth: SignedTreeHead<D>,
+    transitions: Vec<KeyTransition>,
+    #[cfg_attr(feature = "serde", serde(bound = ""))]
+    queue: Vec<Hash<D>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    staged: HashSet<Hash<D>>,
+    merge_delay: u64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pending: Option<(SignedTreeHead<D>, PendingSignature)>,
 }
 
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
-pub struct SignedOwningMerkleTree<T: Digestible, D: Digest> {
+pub struct SignedOwningMerkleTree<
+    T: Digestible,
+    D: Digest,
+    K: TreeHeadSigner = KeyPair,
+> {
     #[cfg_attr(feature = "serde", serde(
             bound(serialize = "OwningMerkleTree<T, D>: Serialize",
                   deserialize = "OwningMerkleTree<T, D>: Deserialize<'de>")))]
     mt: OwningMerkleTree<T, D>,
-    keypair: KeyPair,
+    #[cfg_attr(feature = "serde", serde(
+            bound(serialize = "K: Serialize",
+                  deserialize = "K: Deserialize<'de>")))]
+    signer: K,
     #[cfg_attr(feature = "serde", serde(bound = ""))]
     sth: SignedTreeHead<D>,
+    transitions: Vec<KeyTransition>,
+    #[cfg_attr(feature = "serde", serde(
+            bound(serialize = "T: Serialize",
+                  deserialize = "T: Deserialize<'de>")))]
+    queue: Vec<T>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    staged: HashSet<Hash<D>>,
+    merge_delay: u64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pending: Option<(SignedTreeHead<D>, PendingSignature)>,
+}
+
+/// The parts of a signed tree stored by `serialize_without_signer`.
+#[cfg(feature = "serde")]
+#[derive(Deserialize, Serialize)]
+struct DetachedTree<M, H, T, Q> {
+    mt: M,
+    sth: H,
+    transitions: T,
+    queue: Q,
+    merge_delay: u64,
 }
 
 macro_rules! impl_signed_tree {
     ( $name:ident, $base: ident, ($( $par:ident : $bound:ident, )*),
-    $elt:ident, ($( $et_bound:ident, )*) ) => {
-        impl<$( $par: $bound, )* D: Digest> $name<$( $par, )* D> {
-            pub fn new(keypair: KeyPair) -> Self {
-                let mt = $base::new();
-                let sth = SignedTreeHead::new(&keypair, mt.head());
+    $elt:ident, ($( $et_bound:ident, )*), $queued:ty, $hash_queued:expr ) => {
+        impl<$( $par: $bound, )* D: Digest, K: TreeHeadSigner>
+                $name<$( $par, )* D, K> {
+            /// An empty tree, waiting for `signer` to sign its head.
+            /// Panics if signing fails, see `try_new`.
+            pub fn new(signer: K) -> Self {
+                Self::try_new(signer).expect("failed to sign tree head")
+            }
 
-                Self { mt, keypair, sth }
+            pub fn try_new(signer: K) -> io::Result<Self> {
+                Self::try_from_unsigned(signer, $base::new())
             }
 
-            pub fn from_unsigned(keypair: KeyPair,
+            /// Panics if signing fails, see `try_from_unsigned`.
+            pub fn from_unsigned(signer: K,
                                  mt: $base<$( $par, )* D>) -> Self {
-                let sth = SignedTreeHead::new(&keypair, mt.head());
-                Self { mt, keypair, sth }
+                Self::try_from_unsigned(signer, mt)
+                    .expect("failed to sign tree head")
+            }
+
+            pub fn try_from_unsigned(
+                signer: K,
+                mt: $base<$( $par, )* D>,
+            ) -> io::Result<Self> {
+                let sth = SignedTreeHead::signed(&signer, mt.head())?;
+                Ok(Self {
+                    mt,
+                    signer,
+                    sth,
+                    transitions: Vec::new(),
+                    queue: Vec::new(),
+                    staged: HashSet::new(),
+                    merge_delay: DEFAULT_MERGE_DELAY,
+                    pending: None,
+                })
+            }
+
+            /// Serialize the tree and its signed head, but not the signer,
+            /// so that no private key ends up next to the tree. Load the
+            /// result with `deserialize_with_signer`.
+            #[cfg(feature = "serde")]
+            pub fn serialize_without_signer<S>(
+                &self,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error>
+            where
+                S: ::serde::Serializer,
+                $base<$( $par, )* D>: Serialize,
+                $queued: Serialize,
+            {
+                DetachedTree {
+                    mt: &self.mt,
+                    sth: &self.sth,
+                    transitions: &self.transitions,
+                    queue: &self.queue,
+                    merge_delay: self.merge_delay,
+                }.serialize(serializer)
+            }
+
+            /// Load a tree stored by `serialize_without_signer` and sign
+            /// its heads with `signer` from now on. The stored head has to
+            /// be signed by the same key and match the tree.
+            #[cfg(feature = "serde")]
+            pub fn deserialize_with_signer<'de, De>(
+                deserializer: De,
+                signer: K,
+            ) -> Result<Self, De::Error>
+            where
+                De: Deserializer<'de>,
+                $base<$( $par, )* D>: Deserialize<'de>,
+                $queued: Deserialize<'de>,
+            {
+                let detached: DetachedTree<
+                    $base<$( $par, )* D>,
+                    SignedTreeHead<D>,
+                    Vec<KeyTransition>,
+                    Vec<$queued>,
+                > = DetachedTree::deserialize(deserializer)?;
+                let DetachedTree { mt, sth, .. } = detached;
+                sth.verify(&signer.verifier()).map_err(SerdeError::custom)?;
+                match mt.head_at(sth.size()) {
+                    Some(ref th) if th.root_hash() == sth.root_hash() => {}
+                    _ => {
+                        return Err(SerdeError::custom(
+                            "signed tree head does not match the tree",
+                        ))
+                    }
+                }
+                let mut smt = Self {
+                    mt,
+                    signer,
+                    sth,
+                    transitions: detached.transitions,
+                    queue: detached.queue,
+                    staged: HashSet::new(),
+                    merge_delay: detached.merge_delay,
+                    pending: None,
+                };
+                if smt.sth.size() != smt.mt.len() {
+                    // Stored while a signature was pending.
+                    smt.request_head().map_err(SerdeError::custom)?;
+                }
+                Ok(smt)
             }
 
+            /// Panics if signing fails, see `try_insert`.
             pub fn insert<$( $elt: AsHash<$et_bound> )*>(&mut self,
                                                          elem: $elt) -> bool {
-                if self.mt.insert(elem) {
-                    self.sth = SignedTreeHead::new(&self.keypair,
-                                                   self.mt.head());
-                    true
-                } else {
-                    false
+                self.try_insert(elem).expect("failed to sign tree head")
+            }
+
+            /// Insert `elem` following the duplicate policy, and ask for
+            /// the new head to be signed if it was appended. If signing
+            /// fails the element is removed again, so that it can be
+            /// inserted once more later.
+            pub fn try_insert<$( $elt: AsHash<$et_bound> )*>(
+                &mut self,
+                elem: $elt,
+            ) -> io::Result<bool> {
+                let inserted = self.mt.push(elem)?;
+                if let Inserted::Appended(index) = inserted {
+                    self.request_head_from(index)?;
                 }
+                Ok(inserted.accepted(self.mt.duplicate_policy()))
+            }
+
+            /// Like `try_insert`, but return the position `elem` was
+            /// assigned, see `MerkleTree::append`. If signing fails the
+            /// element is removed again, like with `try_insert`.
+            pub fn append<$( $elt: AsHash<$et_bound> )*>(
+                &mut self,
+                elem: $elt,
+            ) -> Result<u64, InsertError> {
+                let inserted = self.mt.push(elem)?;
+                if let Inserted::Appended(index) = inserted {
+                    self.request_head_from(index)?;
+                }
+                inserted.index(self.mt.duplicate_policy())
+            }
+
+            /// Append all of `elems` like `MerkleTree::append_many`, and
+            /// ask for one signed head covering all of them. If signing
+            /// fails all of them are removed again.
+            pub fn append_many<$( $elt: AsHash<$et_bound>, )* I>(
+                &mut self,
+                elems: I,
+            ) -> Result<ops::Range<u64>, InsertError>
+            where
+                I: IntoIterator<Item = $elt>,
+            {
+                let range = self.mt.append_many(elems)?;
+                if range.start < range.end {
+                    self.request_head_from(range.start)?;
+                }
+                Ok(range)
+            }
+
+            /// Ask for the head covering the entries appended from
+            /// position `start` on to be signed, and remove them again if
+            /// that fails.
+            fn request_head_from(&mut self, start: u64) -> io::Result<()> {
+                let result = self.request_head();
+                if result.is_err() {
+                    self.mt.truncate(start);
+                }
+                result
+            }
+
+            pub fn duplicate_policy(&self) -> DuplicatePolicy {
+                self.mt.duplicate_policy()
             }
 
+            /// See `MerkleTree::set_duplicate_policy`. Staged entries are
+            /// checked against the policy when they are committed.
+            pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+                self.mt.set_duplicate_policy(policy);
+            }
+
+            /// The latest signed tree head. It lags behind the tree while
+            /// a signature is pending.
             pub fn head(&self) -> SignedTreeHead<D> {
                 self.sth.clone()
             }
 
+            /// Pick up a pending signature if it is available. Returns
+            /// whether the signed head covers the whole tree.
+            pub fn poll_head(&mut self) -> io::Result<bool> {
+                let result = match self.pending {
+                    Some((_, ref mut pending)) => match pending.try_take() {
+                        Some(result) => result,
+                        None => return Ok(false),
+                    },
+                    None => return Ok(self.sth.size() == self.mt.len()),
+                };
+                let (mut sth, _) = self.pending.take().unwrap();
+                sth.complete(result?, &self.signer.verifier())?;
+                self.sth = sth;
+                Ok(true)
+            }
+
+            /// Wait until the signed head covers the whole tree, asking
+            /// for a signature again if an earlier attempt failed. Signers
+            /// answering later have to do so from another thread, see
+            /// `PendingSignature::wait`.
+            pub fn wait_for_head(&mut self) -> io::Result<()> {
+                if self.pending.is_none()
+                    && self.sth.size() != self.mt.len()
+                {
+                    self.request_head()?;
+                }
+                if let Some((mut sth, pending)) = self.pending.take() {
+                    sth.complete(pending.wait()?, &self.signer.verifier())?;
+                    self.sth = sth;
+                }
+                Ok(())
+            }
+
+            /// Insert all staged entries following the duplicate policy,
+            /// computing the nodes of the tree once for the whole batch,
+            /// and ask for one signed head covering all of them. Returns
+            /// the number of appended entries; if there are none, no new
+            /// head is signed.
+            pub fn commit(&mut self) -> io::Result<usize> {
+                let mut queue = Vec::new();
+                mem::swap(&mut queue, &mut self.queue);
+                self.staged.clear();
+                let added = self.mt.append_batch(queue);
+                if added > 0 {
+                    self.request_head()?;
+                }
+                Ok(added)
+            }
+
+            /// The number of entries waiting for `commit`.
+            pub fn queued(&self) -> usize {
+                self.queue.len()
+            }
+
+            /// The maximum merge delay in milliseconds promised by
+            /// `submit`. Call `commit` often enough to keep it.
+            pub fn merge_delay(&self) -> u64 {
+                self.merge_delay
+            }
+
+            pub fn set_merge_delay(&mut self, merge_delay: u64) {
+                self.merge_delay = merge_delay;
+            }
+
+            /// What the duplicate policy says about staging the entry
+            /// with hash `hash`: `None` if it is rejected, otherwise
+            /// whether it has to be staged, which it need not if it is
+            /// kept at its existing position. Staged entries count as
+            /// part of the tree.
+            fn admits(&mut self, hash: &Hash<D>) -> Option<bool> {
+                let policy = self.mt.duplicate_policy();
+                if policy == DuplicatePolicy::Allow
+                    || !self.mt.contains(hash.clone())
+                        && !self.staged().contains(hash)
+                {
+                    return Some(true);
+                }
+                match policy {
+                    DuplicatePolicy::ReturnExisting => Some(false),
+                    _ => None,
+                }
+            }
+
+            /// Stage `elem`, whose hash is `hash`, unless it is part of
+            /// the tree or staged already and the duplicate policy says
+            /// otherwise. Returns false if `elem` was rejected.
+            fn enqueue(&mut self, hash: Hash<D>, elem: $queued) -> bool {
+                match self.admits(&hash) {
+                    Some(stage) => {
+                        if stage {
+                            self.push_staged(hash, elem);
+                        }
+                        true
+                    }
+                    None => false,
+                }
+            }
+
+            fn push_staged(&mut self, hash: Hash<D>, elem: $queued) {
+                self.staged().insert(hash);
+                self.queue.push(elem);
+            }
+
+            /// The hashes of the staged entries. Deserialized trees only
+            /// bring the staged entries, their hashes are computed when
+            /// first needed.
+            fn staged(&mut self) -> &mut HashSet<Hash<D>> {
+                if self.staged.is_empty() && !self.queue.is_empty() {
+                    self.staged =
+                        self.queue.iter().map($hash_queued).collect();
+                }
+                &mut self.staged
+            }
+
+            /// Promise to include the entry with hash `hash`, and stage
+            /// `elem`. No promise is made for entries the duplicate policy
+            /// rejects.
+            fn promise(
+                &mut self,
+                hash: Hash<D>,
+                elem: $queued,
+            ) -> io::Result<InclusionPromise<D>> {
+                let stage = self.admits(&hash).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        "entry is part of the tree or staged already",
+                    )
+                })?;
+                let promise = InclusionPromise::signed(
+                    &self.signer,
+                    hash.clone(),
+                    self.merge_delay,
+                )?;
+                if stage {
+                    self.push_staged(hash, elem);
+                }
+                Ok(promise)
+            }
+
+            /// Switch to signing heads with `signer`. Staged entries are
+            /// committed first, so that heads signed with the current key
+            /// keep its promises. The current signer then signs a
+            /// transition to the new key, taking effect at the current
+            /// size, and the current head is signed again with the new
+            /// key. Waits for all signatures involved, like
+            /// `wait_for_head`.
+            ///
+            /// On failure the tree keeps its current signer.
+            pub fn rotate_key(
+                &mut self,
+                signer: K,
+            ) -> io::Result<&KeyTransition> {
+                self.commit()?;
+                self.wait_for_head()?;
+                let transition = KeyTransition::signed(
+                    &self.signer,
+                    &signer.verifier(),
+                    self.mt.len(),
+                )?;
+                self.sth = SignedTreeHead::signed(&signer, self.mt.head())?;
+                self.signer = signer;
+                self.transitions.push(transition);
+                Ok(self.transitions.last().unwrap())
+            }
+
+            /// The key transitions of the tree, oldest first. Clients
+            /// following them with a `KeyHistory` accept heads signed
+            /// before and after each rotation.
+            pub fn key_transitions(&self) -> &[KeyTransition] {
+                &self.transitions
+            }
+
+            /// Ask for the current head to be signed, superseding any
+            /// pending request.
+            fn request_head(&mut self) -> io::Result<()> {
+                let th = self.mt.head();
+                self.pending = Some(SignedTreeHead::request(&self.signer, th));
+                self.poll_head().map(|_| ())
+            }
+
+            pub fn len(&self) -> u64 {
+                self.mt.len()
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.mt.is_empty()
+            }
+
+            pub fn contains<H: AsHash<D>>(&self, h: H) -> bool {
+                self.mt.contains(h)
+            }
+
+            pub fn index_of<H: AsHash<D>>(&self, h: H) -> Option<u64> {
+                self.mt.index_of(h)
+            }
+
+            pub fn indices_of<H: AsHash<D>>(&self, h: H) -> Vec<u64> {
+                self.mt.indices_of(h)
+            }
+
             pub fn inclusion_proof<H: AsHash<D>>(
                 &self,
                 h: H,
             ) -> Option<SignedInclusionProof<D>> {
                 let h = h.as_hash();
-                InclusionProofBase::new(h, &self.mt).map(|x| {
-                    SignedInclusionProof::new(x, self.head())
-                })
+                let size = self.sth.size();
+                InclusionProofBase::new(h, size, &self.mt)
+                    .expect(READ_FAILED)
+                    .map(|x| SignedInclusionProof::new(x, self.head()))
+            }
+
+            pub fn inclusion_proof_by_index(
+                &self,
+                index: u64,
+            ) -> Option<SignedInclusionProof<D>> {
+                let size = self.sth.size();
+                InclusionProofBase::from_index(index, size, &self.mt)
+                    .expect(READ_FAILED)
+                    .map(|x| SignedInclusionProof::new(x, self.head()))
+            }
+
+            pub fn leaf_hash(&self, index: u64) -> Option<Hash<D>> {
+                self.mt.leaf_hash(index)
+            }
+
+            /// Prove inclusion in an older version of the tree. The proof
+            /// carries an unsigned tree head and is meant to be checked
+            /// with `verify_against` using a head the client already
+            /// trusts.
+            pub fn inclusion_proof_at<H: AsHash<D>>(
+                &self,
+                h: H,
+                tree_size: u64,
+            ) -> Option<InclusionProof<D>> {
+                self.mt.inclusion_proof_at(h, tree_size)
             }
 
             pub fn consistency_proof(
                 &self,
                 old_size: u64,
             ) -> Option<SignedConsistencyProof<D>> {
-                ConsistencyProofBase::new(old_size, &self.mt).map(|x| {
-                    SignedConsistencyProof::new(x, self.head())
-                })
+                let size = self.sth.size();
+                ConsistencyProofBase::new(old_size, size, &self.mt)
+                    .expect(READ_FAILED)
+                    .map(|x| SignedConsistencyProof::new(x, self.head()))
+            }
+
+            /// Prove consistency between two older versions of the tree.
+            /// Like `inclusion_proof_at` the result carries an unsigned
+            /// tree head and is meant for `verify_against`.
+            pub fn consistency_proof_between(
+                &self,
+                old_size: u64,
+                new_size: u64,
+            ) -> Option<ConsistencyProof<D>> {
+                self.mt.consistency_proof_between(old_size, new_size)
             }
         }
 
-        impl<$( $par: $bound, )* $( $elt: AsHash<$et_bound>, )* D: Digest>
-                iter::Extend<$elt> for $name<$( $par, )* D> {
+        impl<$( $par: $bound, )* D: Digest, K: TreeHeadSigner>
+                $name<$( $par, )* D, K> {
+            /// The current tree head as a checkpoint of the log `origin`,
+            /// signed with the tree's key under the key name `origin`,
+            /// waiting for the signature like `wait_for_head`. Checkpoints
+            /// are Ed25519 signed notes, trees signing with other keys fail
+            /// with `InvalidInput`, as do invalid origins.
+            pub fn checkpoint(
+                &self,
+                origin: &str,
+            ) -> io::Result<SignedCheckpoint<D>> {
+                let invalid = |e: NoteError| {
+                    io::Error::new(io::ErrorKind::InvalidInput, e)
+                };
+                let key = self.signer.verifier();
+                let pubkey = match PubKey::from_verifier(&key) {
+                    Some(pubkey) => pubkey,
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "checkpoints need an Ed25519 key",
+                        ))
+                    }
+                };
+                checkpoint::check_name(origin).map_err(invalid)?;
+                let cp = Checkpoint::new(origin, self.sth.th.clone())
+                    .map_err(invalid)?;
+                let mut signed = SignedCheckpoint::new(cp);
+                let text = signed.checkpoint().to_text();
+                let pending = self.signer.sign_tree_head(text.as_bytes());
+                signed.add_signature(origin, &pubkey, pending.wait()?);
+                signed.verify(origin, &pubkey).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "signer returned an invalid checkpoint signature",
+                    )
+                })?;
+                Ok(signed)
+            }
+        }
+
+        /// Extending the tree asks for one signed head covering all new
+        /// entries. Signing errors cannot be returned from `extend`: the
+        /// head then lags behind the tree, and `wait_for_head` asks for a
+        /// signature again and reports the error if it fails once more.
+        impl<$( $par: $bound, )* $( $elt: AsHash<$et_bound>, )* D: Digest,
+             K: TreeHeadSigner> iter::Extend<$elt>
+                for $name<$( $par, )* D, K> {
             fn extend<S: IntoIterator<Item = $elt>>(&mut
             self, iter: S) {
-                self.mt.extend(iter);
-                self.sth = SignedTreeHead::new(&self.keypair, self.mt.head())
+                let len = self.mt.len();
+                // Trees in memory cannot fail to record entries.
+                let _ = self.mt.append_many(iter);
+                if self.mt.len() != len {
+                    let _ = self.request_head();
+                }
             }
         }
 
-        impl<$( $par: $bound, )* D: Digest> From<$name<$( $par, )* D>> for
-        $base<$( $par, )* D> {
-            fn from(mt: $name<$( $par, )* D>) -> Self {
+        impl<$( $par: $bound, )* D: Digest, K: TreeHeadSigner>
+                From<$name<$( $par, )* D, K>> for $base<$( $par, )* D> {
+            fn from(mt: $name<$( $par, )* D, K>) -> Self {
                 mt.mt
             }
         }
     }
 }
 
-impl_signed_tree!(SignedMerkleTree, MerkleTree, (), H, (D,));
+impl_signed_tree!(
+    SignedMerkleTree,
+    MerkleTree,
+    (),
+    H,
+    (D,),
+    Hash<D>,
+    Clone::clone
+);
 impl_signed_tree!(
     SignedOwningMerkleTree,
     OwningMerkleTree,
     (T: Digestible,),
     T,
-    ()
+    (),
+    T,
+    D::hash_elem
 );
 
-impl<T: Digestible, D: Digest> From<SignedOwningMerkleTree<T, D>>
-    for MerkleTree<D>
+impl<D: Digest, K: TreeHeadSigner> SignedMerkleTree<D, K> {
+    /// Stage `elem` for the next `commit`, which signs one head for all
+    /// staged entries instead of one per entry like `insert`. Returns
+    /// false if `elem` is part of the tree or staged already and the
+    /// duplicate policy rejects it.
+    pub fn stage<H: AsHash<D>>(&mut self, elem: H) -> bool {
+        let hash = elem.as_hash();
+        self.enqueue(hash.clone(), hash)
+    }
+
+    /// Stage all of `elems`, returning how many of them were staged.
+    /// Entries that are part of the tree or staged already, including
+    /// earlier copies in `elems`, are only counted if the duplicate policy
+    /// allows duplicates.
+    pub fn stage_many<H, I>(&mut self, elems: I) -> usize
+    where
+        H: AsHash<D>,
+        I: IntoIterator<Item = H>,
+    {
+        let queued = self.queue.len();
+        for elem in elems {
+            self.stage(elem);
+        }
+        self.queue.len() - queued
+    }
+
+    /// Stage `elem` like `stage` and return a signed promise to include
+    /// it within the merge delay. Waits for the signature like
+    /// `wait_for_head`, and stages nothing if signing fails. Fails with
+    /// `AlreadyExists` if the duplicate policy rejects `elem`.
+    pub fn submit<H: AsHash<D>>(
+        &mut self,
+        elem: H,
+    ) -> io::Result<InclusionPromise<D>> {
+        let hash = elem.as_hash();
+        self.promise(hash.clone(), hash)
+    }
+
+    /// The entry hash stored at position `index`, as it was inserted.
+    /// Staged entries are not part of the tree yet.
+    pub fn get(&self, index: u64) -> Option<&Hash<D>> {
+        self.mt.get(index)
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, Hash<D>> {
+        self.mt.iter()
+    }
+
+    pub fn entries(&self, range: ops::Range<u64>) -> &[Hash<D>] {
+        self.mt.entries(range)
+    }
+}
+
+impl<T: Digestible, D: Digest, K: TreeHeadSigner>
+    SignedOwningMerkleTree<T, D, K>
 {
-    fn from(somt: SignedOwningMerkleTree<T, D>) -> Self {
+    /// See `SignedMerkleTree::stage`.
+    pub fn stage(&mut self, elem: T) -> bool {
+        let hash = D::hash_elem(&elem);
+        self.enqueue(hash, elem)
+    }
+
+    /// See `SignedMerkleTree::stage_many`.
+    pub fn stage_many<I: IntoIterator<Item = T>>(&mut self, elems: I) -> usize {
+        let queued = self.queue.len();
+        for elem in elems {
+            self.stage(elem);
+        }
+        self.queue.len() - queued
+    }
+
+    /// See `SignedMerkleTree::submit`.
+    pub fn submit(&mut self, elem: T) -> io::Result<InclusionPromise<D>> {
+        let hash = D::hash_elem(&elem);
+        self.promise(hash, elem)
+    }
+
+    /// See `OwningMerkleTree::get`.
+    pub fn get(&self, index: u64) -> Option<&T> {
+        self.mt.get(index)
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, T> {
+        self.mt.iter()
+    }
+
+    pub fn entries(&self, range: ops::Range<u64>) -> &[T] {
+        self.mt.entries(range)
+    }
+}
+
+impl<T: Digestible, D: Digest, K: TreeHeadSigner>
+    From<SignedOwningMerkleTree<T, D, K>> for MerkleTree<D>
+{
+    fn from(somt: SignedOwningMerkleTree<T, D, K>) -> Self {
         let omt: OwningMerkleTree<T, D> = From::from(somt);
         From::from(omt)
     }
 }
 
-impl<T: Digestible, D: Digest> From<SignedOwningMerkleTree<T, D>>
-    for SignedMerkleTree<D>
+impl<T: Digestible, D: Digest, K: TreeHeadSigner>
+    From<SignedOwningMerkleTree<T, D, K>> for SignedMerkleTree<D, K>
 {
-    fn from(somt: SignedOwningMerkleTree<T, D>) -> Self {
+    fn from(somt: SignedOwningMerkleTree<T, D, K>) -> Self {
         SignedMerkleTree {
             mt: somt.mt.into(),
-            keypair: somt.keypair,
+            signer: somt.signer,
             sth: somt.sth,
+            transitions: somt.transitions,
+            queue: somt.queue.iter().map(D::hash_elem).collect(),
+            staged: somt.staged,
+            merge_delay: somt.merge_delay,
+            pending: somt.pending,
         }
     }
 }
 
-#[cfg_attr(feature = "serde", derive(Serialize))]
+/// An Ed25519 key pair.
 pub struct KeyPair {
-    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     kp: signature::Ed25519KeyPair,
-    #[cfg(feature = "serde")]
-    bytes: [[u8; 17]; 5], // Same size as signature::ED25519_PKCS8_V2_LEN
+    seed: [u8; SEED_LEN],
 }
 
 impl KeyPair {
     pub fn new() -> Result<Self, RingError> {
         let rng = rand::SystemRandom::new();
         let k_bytes = signature::Ed25519KeyPair::generate_pkcs8(&rng)?;
-        let kp = signature::Ed25519KeyPair::from_pkcs8(
-            untrusted::Input::from(&k_bytes),
-        )?;
-        Ok(Self {
-            kp,
-            #[cfg(feature = "serde")]
-            bytes: unsafe { ::std::mem::transmute(k_bytes) },
-        })
+        KeyPair::from_pkcs8_der(&k_bytes).map_err(|_| RingError)
     }
 
+    /// Load a PKCS#8 v2 document. `from_pkcs8_der` accepts any length and
+    /// v1 documents as well.
     pub fn new_from_bytes(
         bytes: [u8; signature::ED25519_PKCS8_V2_LEN],
     ) -> Result<Self, RingError> {
-        Ok(Self {
-            kp: signature::Ed25519KeyPair::from_pkcs8(
-                untrusted::Input::from(&bytes),
-            )?,
-            #[cfg(feature = "serde")]
-            bytes: unsafe { ::std::mem::transmute(bytes) },
-        })
+        KeyPair::from_pkcs8_der(&bytes).map_err(|_| RingError)
+    }
+
+    pub(crate) fn from_parts(
+        kp: signature::Ed25519KeyPair,
+        seed: &[u8],
+    ) -> Self {
+        let mut arr = [0; SEED_LEN];
+        arr.copy_from_slice(seed);
+        KeyPair { kp, seed: arr }
+    }
+
+    pub(crate) fn seed(&self) -> &[u8; SEED_LEN] {
+        &self.seed
     }
 
     pub fn pub_key(&self) -> PubKey {
         PubKey::new(self.kp.public_key_bytes())
     }
 
-    fn sign(&self, d: &[u8]) -> signature::Signature {
+    pub(crate) fn sign(&self, d: &[u8]) -> signature::Signature {
         self.kp.sign(d)
     }
 }
@@ -223,11 +1045,59 @@ impl KeyPair {
 pub struct PubKey([u8; signature::ED25519_PUBLIC_KEY_LEN]);
 
 impl PubKey {
-    fn new(bytes: &[u8]) -> Self {
+    pub(crate) fn new(bytes: &[u8]) -> Self {
         let mut arr = [0; signature::ED25519_PUBLIC_KEY_LEN];
         arr.copy_from_slice(bytes);
         PubKey(arr)
     }
+
+    /// The key behind `key` if it is an Ed25519 key, as needed for signed
+    /// notes.
+    pub(crate) fn from_verifier<V: Verifier>(key: &V) -> Option<Self> {
+        if key.algorithm() != SignatureAlgorithm::ED25519
+            || key.key_bytes().len() != signature::ED25519_PUBLIC_KEY_LEN
+        {
+            return None;
+        }
+        Some(PubKey::new(key.key_bytes()))
+    }
+
+    /// The 32 byte encoding of the key.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Check an Ed25519 signature made by this key.
+    pub(crate) fn verify_sig(&self, msg: &[u8], sig: &[u8]) -> bool {
+        signature::verify(
+            &signature::ED25519,
+            untrusted::Input::from(&self.0[..]),
+            untrusted::Input::from(msg),
+            untrusted::Input::from(sig),
+        ).is_ok()
+    }
+}
+
+/// Key pairs are serialized as their PKCS#8 v2 document, split into five
+/// chunks of 17 bytes.
+#[cfg(feature = "serde")]
+impl Serialize for KeyPair {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        #[derive(Serialize)]
+        struct KeyPair {
+            bytes: [[u8; 17]; 5],
+        }
+
+        let mut bytes = [[0; 17]; 5];
+        let der = self.to_pkcs8_der();
+        for (chunk, src) in bytes.iter_mut().zip(der.chunks(17)) {
+            chunk.copy_from_slice(src);
+        }
+        KeyPair { bytes }.serialize(serializer)
+    }
 }
 
 #[cfg(feature = "serde")]
@@ -241,10 +1111,8 @@ impl<'de> Deserialize<'de> for KeyPair {
         struct Bytes([[u8; 17]; 5]);
 
         let bytes = Bytes::deserialize(deserializer)?;
-
-        let cbytes: [u8; signature::ED25519_PKCS8_V2_LEN] =
-            unsafe { ::std::mem::transmute(bytes.0) };
-        KeyPair::new_from_bytes(cbytes).map_err(SerdeError::custom)
+        let der: Vec<u8> = bytes.0.iter().flat_map(|c| c.to_vec()).collect();
+        KeyPair::from_pkcs8_der(&der).map_err(SerdeError::custom)
     }
 }
 
@@ -253,18 +1121,22 @@ pub struct RingError;
 
 impl fmt::Display for RingError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.description())
+        f.write_str("An unspecified error occurred during key generation")
     }
 }
 
-impl Error for RingError {
-    fn description(&self) -> &str {
-        "An unspecified error occurred during key generation"
-    }
-}
+impl Error for RingError {}
 
 impl From<::ring::error::Unspecified> for RingError {
     fn from(_: ::ring::error::Unspecified) -> Self {
         RingError
     }
 }
+
+// io::Error::other needs Rust 1.74.
+#[allow(clippy::io_other_error)]
+impl From<RingError> for io::Error {
+    fn from(e: RingError) -> Self {
+        io::Error::new(io::ErrorKind::Other, e)
+    }
+}
@@ -0,0 +1,609 @@
+//! Signature algorithms for signed tree heads.
+//!
+//! Signed trees are generic over a `Signer`, and signed tree heads are
+//! checked with the matching `Verifier`. Ed25519 (`KeyPair` and `PubKey`)
+//! and ECDSA with P-256 and SHA-256 (`EcdsaKeyPair` and `EcdsaPubKey`, as
+//! used by CT logs) are provided; other algorithms can be plugged in by
+//! implementing both traits.
+//!
+//! Keys that must not be held in process memory, such as keys in an HSM or
+//! a remote signing service, implement `TreeHeadSigner` instead of
+//! `Signer`. Such signers may answer later: the tree keeps serving its last
+//! signed head until the signature for the new one arrives.
+
+use der;
+use keyring::LogId;
+use keys::ED25519_SPKI_PREFIX;
+use ring::{rand, signature};
+use signed_merkle::{KeyPair, PubKey, RingError};
+use std::{io, mem};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use untrusted;
+
+/// Identifies the signature algorithm of a signed tree head, using the
+/// TLS `SignatureScheme` code points as RFC 9162 does.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SignatureAlgorithm(pub u16);
+
+impl SignatureAlgorithm {
+    pub const ED25519: SignatureAlgorithm = SignatureAlgorithm(0x0807);
+    pub const ECDSA_P256_SHA256: SignatureAlgorithm =
+        SignatureAlgorithm(0x0403);
+    pub const RSA_PSS_RSAE_SHA256: SignatureAlgorithm =
+        SignatureAlgorithm(0x0804);
+}
+
+/// A private key signing tree heads.
+pub trait Signer {
+    /// The public key checking the signatures.
+    type PublicKey: Verifier;
+
+    fn public_key(&self) -> Self::PublicKey;
+
+    /// Sign `msg`, returning the signature in the encoding expected by the
+    /// verifier.
+    fn sign_message(&self, msg: &[u8]) -> Result<Vec<u8>, RingError>;
+}
+
+/// A public key checking signatures on tree heads.
+pub trait Verifier {
+    fn algorithm(&self) -> SignatureAlgorithm;
+
+    /// The encoded public key.
+    fn key_bytes(&self) -> &[u8];
+
+    /// The key as a DER encoded SubjectPublicKeyInfo document.
+    fn to_spki_der(&self) -> Vec<u8>;
+
+    /// The RFC 6962 log ID of the key, which signed tree heads carry.
+    fn log_id(&self) -> LogId {
+        LogId::from_spki_der(&self.to_spki_der())
+    }
+
+    fn verify_message(&self, msg: &[u8], sig: &[u8]) -> bool;
+}
+
+/// Signs tree heads on behalf of a signed tree, for example by handing them
+/// to a PKCS#11 device or a remote signer. Every `Signer` is one.
+pub trait TreeHeadSigner {
+    /// The public key checking the signatures.
+    type Key: Verifier;
+
+    fn verifier(&self) -> Self::Key;
+
+    /// Start signing the signing input of a tree head, or of another
+    /// statement by the log: a `KeyTransition` when the tree rotates its
+    /// key, an `InclusionPromise` or the text of a checkpoint. Signers that
+    /// cannot answer right away return a pending signature from
+    /// `PendingSignature::channel` and complete it later.
+    fn sign_tree_head(&self, input: &[u8]) -> PendingSignature;
+}
+
+impl<S: Signer> TreeHeadSigner for S {
+    type Key = S::PublicKey;
+
+    fn verifier(&self) -> S::PublicKey {
+        self.public_key()
+    }
+
+    fn sign_tree_head(&self, input: &[u8]) -> PendingSignature {
+        PendingSignature::ready(
+            self.sign_message(input).map_err(io::Error::from),
+        )
+    }
+}
+
+/// A signature that may not have been made yet.
+pub struct PendingSignature(Pending);
+
+enum Pending {
+    Ready(io::Result<Vec<u8>>),
+    Waiting(Mutex<Receiver<io::Result<Vec<u8>>>>),
+    /// Queued by a held `LocalSigner`, which only answers when told to.
+    Held(Mutex<Receiver<io::Result<Vec<u8>>>>),
+}
+
+impl PendingSignature {
+    /// A signature that is already known.
+    pub fn ready(sig: io::Result<Vec<u8>>) -> PendingSignature {
+        PendingSignature(Pending::Ready(sig))
+    }
+
+    /// A signature completed by sending it through the returned sender,
+    /// possibly from another thread.
+    pub fn channel() -> (SignatureSender, PendingSignature) {
+        let (tx, rx) = mpsc::channel();
+        let pending = PendingSignature(Pending::Waiting(Mutex::new(rx)));
+        (SignatureSender(tx), pending)
+    }
+
+    /// The signature if it is available, `None` if it is still pending.
+    /// Once taken, it is gone.
+    pub fn try_take(&mut self) -> Option<io::Result<Vec<u8>>> {
+        let result = match self.0 {
+            Pending::Ready(_) => None,
+            Pending::Waiting(ref rx) | Pending::Held(ref rx) => {
+                match rx.lock().unwrap().try_recv() {
+                    Ok(result) => Some(result),
+                    Err(TryRecvError::Empty) => return None,
+                    Err(TryRecvError::Disconnected) => Some(Err(gone())),
+                }
+            }
+        };
+        if let Some(result) = result {
+            self.0 = Pending::Ready(result);
+        }
+        match mem::replace(&mut self.0, Pending::Ready(Err(gone()))) {
+            Pending::Ready(result) => Some(result),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Block until the signature is available. Signatures that only come
+    /// once the waiting thread does something can never arrive: those of a
+    /// held `LocalSigner` fail with `WouldBlock` if they have not been
+    /// signed yet, and other signers must answer from another thread.
+    pub fn wait(mut self) -> io::Result<Vec<u8>> {
+        match self.0 {
+            Pending::Ready(result) => result,
+            Pending::Waiting(rx) => {
+                let rx = rx.into_inner().unwrap();
+                rx.recv().unwrap_or_else(|_| Err(gone()))
+            }
+            Pending::Held(_) => self.try_take().unwrap_or_else(|| {
+                Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "signer is on hold",
+                ))
+            }),
+        }
+    }
+}
+
+fn gone() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "signer went away")
+}
+
+/// Completes a `PendingSignature`.
+pub struct SignatureSender(Sender<io::Result<Vec<u8>>>);
+
+impl SignatureSender {
+    /// Hand over the signature, or the reason signing failed. Signatures
+    /// nobody waits for anymore are dropped.
+    pub fn send(self, sig: io::Result<Vec<u8>>) {
+        let _ = self.0.send(sig);
+    }
+}
+
+/// A `TreeHeadSigner` standing in for an HSM or remote signer in tests,
+/// signing with the in-process key `S`. While on hold it queues requests
+/// and only answers them when told to. Clones share the key and the queue.
+pub struct LocalSigner<S: Signer> {
+    key: Arc<S>,
+    queue: Arc<Mutex<SigningQueue>>,
+}
+
+struct SigningQueue {
+    hold: bool,
+    requests: Vec<(Vec<u8>, SignatureSender)>,
+}
+
+impl<S: Signer> Clone for LocalSigner<S> {
+    fn clone(&self) -> Self {
+        LocalSigner {
+            key: self.key.clone(),
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<S: Signer> LocalSigner<S> {
+    pub fn new(key: S) -> Self {
+        LocalSigner {
+            key: Arc::new(key),
+            queue: Arc::new(Mutex::new(SigningQueue {
+                hold: false,
+                requests: Vec::new(),
+            })),
+        }
+    }
+
+    /// Start or stop queuing requests. Requests queued so far stay queued.
+    ///
+    /// Queued requests are only answered by `sign_queued` and
+    /// `fail_queued`, so nothing waits for them: methods of a tree waiting
+    /// for a signature fail with `WouldBlock` instead of blocking forever.
+    /// Use `poll_head` to pick up heads signed later.
+    pub fn hold(&self, hold: bool) {
+        self.queue.lock().unwrap().hold = hold;
+    }
+
+    /// Number of requests waiting for a signature.
+    pub fn queued(&self) -> usize {
+        self.queue.lock().unwrap().requests.len()
+    }
+
+    /// Sign all queued requests and return their number.
+    pub fn sign_queued(&self) -> usize {
+        let requests = self.take_queued();
+        let n = requests.len();
+        for (input, tx) in requests {
+            tx.send(self.sign(&input));
+        }
+        n
+    }
+
+    /// Fail all queued requests with `kind` and return their number.
+    pub fn fail_queued(&self, kind: io::ErrorKind) -> usize {
+        let requests = self.take_queued();
+        let n = requests.len();
+        for (_, tx) in requests {
+            tx.send(Err(io::Error::new(kind, "signing failed")));
+        }
+        n
+    }
+
+    fn take_queued(&self) -> Vec<(Vec<u8>, SignatureSender)> {
+        let mut queue = self.queue.lock().unwrap();
+        let mut requests = Vec::new();
+        mem::swap(&mut requests, &mut queue.requests);
+        requests
+    }
+
+    fn sign(&self, input: &[u8]) -> io::Result<Vec<u8>> {
+        self.key.sign_message(input).map_err(io::Error::from)
+    }
+}
+
+impl<S: Signer> TreeHeadSigner for LocalSigner<S> {
+    type Key = S::PublicKey;
+
+    fn verifier(&self) -> S::PublicKey {
+        self.key.public_key()
+    }
+
+    fn sign_tree_head(&self, input: &[u8]) -> PendingSignature {
+        let mut queue = self.queue.lock().unwrap();
+        if !queue.hold {
+            return PendingSignature::ready(self.sign(input));
+        }
+        let (tx, rx) = mpsc::channel();
+        queue.requests.push((input.to_vec(), SignatureSender(tx)));
+        PendingSignature(Pending::Held(Mutex::new(rx)))
+    }
+}
+
+impl Signer for KeyPair {
+    type PublicKey = PubKey;
+
+    fn public_key(&self) -> PubKey {
+        self.pub_key()
+    }
+
+    fn sign_message(&self, msg: &[u8]) -> Result<Vec<u8>, RingError> {
+        Ok(self.sign(msg).as_ref().to_vec())
+    }
+}
+
+impl Verifier for PubKey {
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::ED25519
+    }
+
+    fn key_bytes(&self) -> &[u8] {
+        self.as_bytes()
+    }
+
+    fn to_spki_der(&self) -> Vec<u8> {
+        PubKey::to_spki_der(self)
+    }
+
+    fn verify_message(&self, msg: &[u8], sig: &[u8]) -> bool {
+        self.verify_sig(msg, sig)
+    }
+}
+
+/// Length of an uncompressed P-256 point.
+const P256_PUBLIC_KEY_LEN: usize = 65;
+/// Everything in a SubjectPublicKeyInfo in front of a P-256 point: the
+/// `id-ecPublicKey` and `prime256v1` OIDs and the BIT STRING header.
+const P256_SPKI_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02,
+    0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03,
+    0x42, 0x00,
+];
+
+/// An ECDSA P-256 key pair, producing ASN.1 DER encoded signatures.
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct EcdsaKeyPair {
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    kp: signature::ECDSAKeyPair,
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    public: EcdsaPubKey,
+    #[cfg(feature = "serde")]
+    pkcs8: Vec<u8>,
+}
+
+impl EcdsaKeyPair {
+    pub fn new() -> Result<Self, RingError> {
+        let rng = rand::SystemRandom::new();
+        let doc = signature::ECDSAKeyPair::generate_pkcs8(
+            &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+            &rng,
+        )?;
+        Self::from_pkcs8(doc.as_ref())
+    }
+
+    /// Load an unencrypted PKCS#8 document holding a P-256 private key and
+    /// its public key.
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Self, RingError> {
+        let kp = signature::ECDSAKeyPair::from_pkcs8(
+            &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+            untrusted::Input::from(pkcs8),
+        )?;
+        // ring checked the public key against the private key but does not
+        // hand it out.
+        let public = p256_public_key(pkcs8)
+            .ok()
+            .and_then(EcdsaPubKey::from_point)
+            .ok_or(RingError)?;
+        Ok(EcdsaKeyPair {
+            kp,
+            public,
+            #[cfg(feature = "serde")]
+            pkcs8: pkcs8.to_vec(),
+        })
+    }
+
+    pub fn pub_key(&self) -> EcdsaPubKey {
+        self.public.clone()
+    }
+}
+
+impl Signer for EcdsaKeyPair {
+    type PublicKey = EcdsaPubKey;
+
+    fn public_key(&self) -> EcdsaPubKey {
+        self.pub_key()
+    }
+
+    fn sign_message(&self, msg: &[u8]) -> Result<Vec<u8>, RingError> {
+        let rng = rand::SystemRandom::new();
+        let sig = self.kp.sign(untrusted::Input::from(msg), &rng)?;
+        Ok(sig.as_ref().to_vec())
+    }
+}
+
+/// The public key in the `ECPrivateKey` (RFC 5915) of a PKCS#8 document.
+fn p256_public_key(pkcs8: &[u8]) -> Result<&[u8], der::Malformed> {
+    let point = untrusted::Input::from(pkcs8).read_all(der::Malformed, |doc| {
+        der::nested(doc, der::SEQUENCE, |info| {
+            der::expect(info, der::INTEGER)?;
+            der::expect(info, der::SEQUENCE)?;
+            let point = der::nested(info, der::OCTET_STRING, |key| {
+                der::nested(key, der::SEQUENCE, |ec| {
+                    der::expect(ec, der::INTEGER)?;
+                    der::expect(ec, der::OCTET_STRING)?;
+                    der::optional(ec, der::CONTEXT_0)?;
+                    der::nested(ec, der::CONTEXT_1, der::bit_string)
+                })
+            })?;
+            // Attributes and the public key of a PKCS#8 v2 document may
+            // follow.
+            info.skip_to_end();
+            Ok(point)
+        })
+    })?;
+    Ok(point.as_slice_less_safe())
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for EcdsaKeyPair {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        #[derive(Deserialize)]
+        struct Pkcs8 {
+            pkcs8: Vec<u8>,
+        }
+
+        let doc = Pkcs8::deserialize(deserializer)?;
+        EcdsaKeyPair::from_pkcs8(&doc.pkcs8).map_err(D::Error::custom)
+    }
+}
+
+/// An ECDSA P-256 public key, as an uncompressed point.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EcdsaPubKey(Vec<u8>);
+
+impl EcdsaPubKey {
+    /// The key for the uncompressed point `0x04 || x || y`.
+    pub fn from_point(point: &[u8]) -> Option<Self> {
+        if point.len() != P256_PUBLIC_KEY_LEN || point[0] != 4 {
+            return None;
+        }
+        Some(EcdsaPubKey(point.to_vec()))
+    }
+}
+
+impl Verifier for EcdsaPubKey {
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::ECDSA_P256_SHA256
+    }
+
+    fn key_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn to_spki_der(&self) -> Vec<u8> {
+        spki(&P256_SPKI_PREFIX, &self.0)
+    }
+
+    fn verify_message(&self, msg: &[u8], sig: &[u8]) -> bool {
+        signature::verify(
+            &signature::ECDSA_P256_SHA256_ASN1,
+            untrusted::Input::from(&self.0),
+            untrusted::Input::from(msg),
+            untrusted::Input::from(sig),
+        ).is_ok()
+    }
+}
+
+/// The `rsaEncryption` OID, which RSA public keys are published under.
+const RSA_ENCRYPTION_OID: [u8; 9] =
+    [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+
+/// An RSA public key checking RSA-PSS signatures with SHA-256 and a salt as
+/// long as the hash, as DER encoded PKCS#1 `RSAPublicKey`. Keys of 2048 to
+/// 8192 bits are supported. There is no matching signer.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RsaPssPubKey(Vec<u8>);
+
+impl RsaPssPubKey {
+    /// The key in the DER encoded PKCS#1 `RSAPublicKey` `der`.
+    pub fn from_pkcs1_der(der: &[u8]) -> Option<Self> {
+        untrusted::Input::from(der)
+            .read_all(der::Malformed, |doc| {
+                der::nested(doc, der::SEQUENCE, |key| {
+                    der::expect(key, der::INTEGER)?;
+                    der::expect(key, der::INTEGER)
+                })
+            })
+            .ok()
+            .map(|_| RsaPssPubKey(der.to_vec()))
+    }
+
+    /// The key in the SubjectPublicKeyInfo `der`, which has to name the
+    /// key `rsaEncryption`.
+    pub fn from_spki_der(der: &[u8]) -> Option<Self> {
+        untrusted::Input::from(der)
+            .read_all(der::Malformed, |doc| {
+                der::nested(doc, der::SEQUENCE, |spki| {
+                    der::nested(spki, der::SEQUENCE, |alg| {
+                        let oid = der::expect(alg, der::OID)?;
+                        if oid.as_slice_less_safe() != RSA_ENCRYPTION_OID {
+                            return Err(der::Malformed);
+                        }
+                        der::expect(alg, der::NULL)
+                    })?;
+                    der::bit_string(spki)
+                })
+            })
+            .ok()
+            .and_then(|key| Self::from_pkcs1_der(key.as_slice_less_safe()))
+    }
+}
+
+impl Verifier for RsaPssPubKey {
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::RSA_PSS_RSAE_SHA256
+    }
+
+    fn key_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn to_spki_der(&self) -> Vec<u8> {
+        rsa_spki(&self.0)
+    }
+
+    fn verify_message(&self, msg: &[u8], sig: &[u8]) -> bool {
+        signature::verify(
+            &signature::RSA_PSS_2048_8192_SHA256,
+            untrusted::Input::from(&self.0),
+            untrusted::Input::from(msg),
+            untrusted::Input::from(sig),
+        ).is_ok()
+    }
+}
+
+/// A public key of any supported algorithm, for keys only known from the
+/// data naming them, like the new key of a `KeyTransition`. RSA keys are
+/// DER encoded PKCS#1 `RSAPublicKey`s, the other keys are stored as their
+/// verifiers hold them. Signatures of unsupported algorithms never verify.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogKey {
+    alg: SignatureAlgorithm,
+    key: Vec<u8>,
+}
+
+impl LogKey {
+    pub fn new(alg: SignatureAlgorithm, key: &[u8]) -> Self {
+        LogKey {
+            alg,
+            key: key.to_vec(),
+        }
+    }
+
+    pub fn from_verifier<V: Verifier>(key: &V) -> Self {
+        LogKey::new(key.algorithm(), key.key_bytes())
+    }
+}
+
+impl Verifier for LogKey {
+    fn algorithm(&self) -> SignatureAlgorithm {
+        self.alg
+    }
+
+    fn key_bytes(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Keys of unsupported algorithms are taken to be SubjectPublicKeyInfo
+    /// documents already.
+    fn to_spki_der(&self) -> Vec<u8> {
+        match self.alg {
+            SignatureAlgorithm::ED25519 => {
+                spki(&ED25519_SPKI_PREFIX, &self.key)
+            }
+            SignatureAlgorithm::ECDSA_P256_SHA256 => {
+                spki(&P256_SPKI_PREFIX, &self.key)
+            }
+            SignatureAlgorithm::RSA_PSS_RSAE_SHA256 => rsa_spki(&self.key),
+            _ => self.key.clone(),
+        }
+    }
+
+    fn verify_message(&self, msg: &[u8], sig: &[u8]) -> bool {
+        let alg: &dyn signature::VerificationAlgorithm = match self.alg {
+            SignatureAlgorithm::ED25519 => &signature::ED25519,
+            SignatureAlgorithm::ECDSA_P256_SHA256 => {
+                &signature::ECDSA_P256_SHA256_ASN1
+            }
+            SignatureAlgorithm::RSA_PSS_RSAE_SHA256 => {
+                &signature::RSA_PSS_2048_8192_SHA256
+            }
+            _ => return false,
+        };
+        signature::verify(
+            alg,
+            untrusted::Input::from(&self.key),
+            untrusted::Input::from(msg),
+            untrusted::Input::from(sig),
+        ).is_ok()
+    }
+}
+
+fn spki(prefix: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut der = prefix.to_vec();
+    der.extend_from_slice(key);
+    der
+}
+
+fn rsa_spki(key: &[u8]) -> Vec<u8> {
+    let mut alg = der::encode(der::OID, &RSA_ENCRYPTION_OID);
+    alg.extend_from_slice(&der::encode(der::NULL, &[]));
+    let mut spki = der::encode(der::SEQUENCE, &alg);
+    let mut bits = vec![0];
+    bits.extend_from_slice(key);
+    spki.extend_from_slice(&der::encode(der::BIT_STRING, &bits));
+    der::encode(der::SEQUENCE, &spki)
+}
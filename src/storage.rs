@@ -0,0 +1,997 @@
+//! Backends holding the nodes of a `MerkleTree`.
+//!
+//! Trees only store the roots of *perfect* subtrees, organized by level:
+//! level 0 holds the leaf hashes, and node `i` on level `l` is the root of
+//! the leaves `i << l .. (i + 1) << l`. Such a node never changes once all
+//! its leaves are present, so every level is an append-only list. Nodes on
+//! the right edge of the tree that are not complete yet are recomputed from
+//! the stored ones when they are needed.
+
+use digest::{Digest, Hash};
+#[cfg(feature = "memmap")]
+use memmap2::Mmap;
+#[cfg(feature = "memmap")]
+use merkle::MerkleTree;
+use std::cmp;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// Node and index storage behind a `MerkleTree`.
+pub trait Storage<D: Digest> {
+    /// Number of entries in the tree.
+    fn len(&self) -> u64;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The root of the perfect subtree `index` on `level`. Only called for
+    /// `index < len() >> level`.
+    fn node(&self, level: u32, index: u64) -> io::Result<Hash<D>>;
+
+    /// Position of the entry with hash `entry`, the first one if it was
+    /// appended several times.
+    fn index_of(&self, entry: &Hash<D>) -> io::Result<Option<u64>>;
+
+    /// All positions of the entry with hash `entry`, in ascending order.
+    /// Storages that do not keep track of repeated entries only return
+    /// the one found by `index_of`.
+    fn indices_of(&self, entry: &Hash<D>) -> io::Result<Vec<u64>> {
+        self.index_of(entry).map(|x| x.into_iter().collect())
+    }
+
+    /// Append an entry at position `len()`. `nodes` holds its leaf hash
+    /// followed by the roots of the perfect subtrees it completes, from
+    /// level 0 upwards. The entry may be part of the tree already.
+    fn append(&mut self, entry: Hash<D>, nodes: &[Hash<D>])
+        -> io::Result<()>;
+
+    /// Append `entries` like `append` would one at a time, skipping those
+    /// that are part of the tree already unless `duplicates` is set.
+    /// Returns the number of appended entries. On error, the entries
+    /// before the failing one stay in the tree. Storages keeping all
+    /// levels at hand compute the nodes of the batch once per level.
+    fn append_all(
+        &mut self,
+        entries: Vec<Hash<D>>,
+        duplicates: bool,
+    ) -> io::Result<u64> {
+        let mut appended = 0;
+        for entry in entries {
+            if duplicates || self.index_of(&entry)?.is_none() {
+                let nodes = completed_nodes(self, &entry)?;
+                self.append(entry, &nodes)?;
+                appended += 1;
+            }
+        }
+        Ok(appended)
+    }
+}
+
+/// The nodes `Storage::append` takes for `entry` at the next position of
+/// `storage`: its leaf hash, followed by the roots of the perfect subtrees
+/// it completes, one for every trailing one bit of its index.
+pub(crate) fn completed_nodes<D: Digest, S: Storage<D> + ?Sized>(
+    storage: &S,
+    entry: &Hash<D>,
+) -> io::Result<Vec<Hash<D>>> {
+    let mut nodes = vec![D::hash_leaf(entry)];
+    let mut i = storage.len();
+    let mut level = 0;
+    while i & 1 == 1 {
+        let left = storage.node(level, i - 1)?;
+        let node = D::hash_inner(&left, &nodes[level as usize]);
+        nodes.push(node);
+        i >>= 1;
+        level += 1;
+    }
+    Ok(nodes)
+}
+
+/// Keeps all nodes and the entry index in memory. This is the default
+/// storage of `MerkleTree`.
+///
+/// Deserializing a storage fails if its nodes or its index do not match
+/// its entries. Trees serialized before storages existed, as a `map` and
+/// a `tree` of nodes in another order, cannot be read any more; build
+/// them again from their entries.
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct MemoryStorage<D: Digest> {
+    /// The entry hashes in insertion order.
+    #[cfg_attr(feature = "serde", serde(bound = ""))]
+    entries: Vec<Hash<D>>,
+    #[cfg_attr(feature = "serde", serde(bound = ""))]
+    map: HashMap<Hash<D>, u64>,
+    #[cfg_attr(feature = "serde", serde(bound = ""))]
+    levels: Vec<Vec<Hash<D>>>,
+    /// The positions of entries appended more than once, except for the
+    /// first one, which is in `map`.
+    #[cfg_attr(feature = "serde", serde(bound = ""))]
+    duplicates: HashMap<Hash<D>, Vec<u64>>,
+}
+
+/// The fields of a `MemoryStorage` as they are serialized, before they
+/// are checked.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct MemoryStorageParts<D: Digest> {
+    #[serde(bound = "")]
+    entries: Vec<Hash<D>>,
+    #[serde(bound = "")]
+    map: HashMap<Hash<D>, u64>,
+    #[serde(bound = "")]
+    levels: Vec<Vec<Hash<D>>>,
+    #[serde(bound = "")]
+    duplicates: HashMap<Hash<D>, Vec<u64>>,
+}
+
+#[cfg(feature = "serde")]
+impl<'de, D: Digest> ::serde::Deserialize<'de> for MemoryStorage<D> {
+    fn deserialize<De>(deserializer: De) -> Result<Self, De::Error>
+    where
+        De: ::serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let parts = MemoryStorageParts::<D>::deserialize(deserializer)?;
+        let len = parts.entries.len() as u64;
+        let height = 64 - len.leading_zeros() as usize;
+        if parts.levels.len() != height
+            || parts
+                .levels
+                .iter()
+                .enumerate()
+                .any(|(level, nodes)| nodes.len() as u64 != len >> level)
+        {
+            return Err(De::Error::custom(
+                "nodes do not match the number of entries",
+            ));
+        }
+
+        // The index has to be the one the entries get when appended.
+        let mut storage = MemoryStorage::new();
+        for entry in parts.entries {
+            storage.push_entry(entry);
+        }
+        if storage.map != parts.map || storage.duplicates != parts.duplicates
+        {
+            return Err(De::Error::custom("index does not match the entries"));
+        }
+        storage.levels = parts.levels;
+        Ok(storage)
+    }
+}
+
+impl<D: Digest> Clone for MemoryStorage<D> {
+    fn clone(&self) -> Self {
+        MemoryStorage {
+            entries: self.entries.clone(),
+            map: self.map.clone(),
+            levels: self.levels.clone(),
+            duplicates: self.duplicates.clone(),
+        }
+    }
+}
+
+impl<D: Digest> MemoryStorage<D> {
+    pub fn new() -> MemoryStorage<D> {
+        MemoryStorage {
+            entries: Vec::new(),
+            map: HashMap::new(),
+            levels: Vec::new(),
+            duplicates: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn entries(&self) -> &[Hash<D>] {
+        &self.entries
+    }
+
+    /// Write the tree to `target`, which has to be empty.
+    pub(crate) fn copy_to<S: Storage<D>>(
+        &self,
+        target: &mut S,
+    ) -> io::Result<()> {
+        if !target.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "target storage is not empty",
+            ));
+        }
+        for (i, entry) in self.entries.iter().enumerate() {
+            // Entry i completes the subtrees ending at position i + 1.
+            let end = i as u64 + 1;
+            let nodes: Vec<_> = (0..end.trailing_zeros() + 1)
+                .map(|level| {
+                    let i = (end >> level) - 1;
+                    self.levels[level as usize][i as usize].clone()
+                })
+                .collect();
+            target.append(entry.clone(), &nodes)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the entries from position `len` on, and the nodes they
+    /// completed.
+    #[cfg(feature = "ring")]
+    pub(crate) fn truncate(&mut self, len: u64) {
+        while self.len() > len {
+            let entry = self.entries.pop().unwrap();
+            match self.duplicates.entry(entry) {
+                // Later copies are removed first.
+                Entry::Occupied(mut more) => {
+                    more.get_mut().pop();
+                    if more.get().is_empty() {
+                        more.remove();
+                    }
+                }
+                Entry::Vacant(e) => {
+                    self.map.remove(e.key());
+                }
+            }
+        }
+        self.levels.truncate(64 - len.leading_zeros() as usize);
+        for (level, nodes) in self.levels.iter_mut().enumerate() {
+            nodes.truncate((len >> level) as usize);
+        }
+    }
+
+    /// Record `entry` at the next position.
+    fn push_entry(&mut self, entry: Hash<D>) {
+        let index = self.len();
+        self.entries.push(entry.clone());
+        match self.map.entry(entry) {
+            Entry::Occupied(e) => {
+                let more = self.duplicates.entry(e.key().clone());
+                more.or_default().push(index);
+            }
+            Entry::Vacant(e) => {
+                e.insert(index);
+            }
+        }
+    }
+}
+
+/// Applies a function to consecutive chunks of hashes of the given size,
+/// sequentially or on several threads.
+pub(crate) type ChunkMap<D> =
+    fn(&[Hash<D>], usize, fn(&[Hash<D>]) -> Hash<D>) -> Vec<Hash<D>>;
+
+pub(crate) fn map_chunks<D: Digest>(
+    hashes: &[Hash<D>],
+    size: usize,
+    f: fn(&[Hash<D>]) -> Hash<D>,
+) -> Vec<Hash<D>> {
+    hashes.chunks(size).map(f).collect()
+}
+
+#[cfg(feature = "rayon")]
+pub(crate) fn par_map_chunks<D: Digest>(
+    hashes: &[Hash<D>],
+    size: usize,
+    f: fn(&[Hash<D>]) -> Hash<D>,
+) -> Vec<Hash<D>>
+where
+    Hash<D>: Send + Sync,
+{
+    use rayon::prelude::*;
+
+    hashes.par_chunks(size).map(f).collect()
+}
+
+impl<D: Digest> MemoryStorage<D> {
+    /// Append `entries` like `append_all`, hashing with `map`. Instead of
+    /// completing the subtrees of every entry, each level is extended
+    /// once.
+    pub(crate) fn append_mapped(
+        &mut self,
+        entries: Vec<Hash<D>>,
+        map: ChunkMap<D>,
+        duplicates: bool,
+    ) -> usize {
+        let mut fresh = Vec::with_capacity(entries.len());
+        for entry in entries {
+            if duplicates || !self.map.contains_key(&entry) {
+                self.push_entry(entry.clone());
+                fresh.push(entry);
+            }
+        }
+        if fresh.is_empty() {
+            return 0;
+        }
+
+        if self.levels.is_empty() {
+            self.levels.push(Vec::new());
+        }
+        let leaves = map(&fresh, 1, |entry| D::hash_leaf(&entry[0]));
+        self.levels[0].extend(leaves);
+        // Every complete pair of nodes on a level that has no parent yet
+        // gets one on the level above.
+        for level in 0.. {
+            let below = self.levels[level].len() / 2;
+            let done = self.levels.get(level + 1).map_or(0, Vec::len);
+            if below == done {
+                break;
+            }
+            if level + 1 == self.levels.len() {
+                self.levels.push(Vec::new());
+            }
+            let (lower, upper) = self.levels.split_at_mut(level + 1);
+            let pairs = &lower[level][2 * done..2 * below];
+            upper[0].extend(map(pairs, 2, |pair| {
+                D::hash_inner(&pair[0], &pair[1])
+            }));
+        }
+        fresh.len()
+    }
+}
+
+impl<D: Digest> Default for MemoryStorage<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Digest> Storage<D> for MemoryStorage<D> {
+    fn len(&self) -> u64 {
+        self.entries.len() as u64
+    }
+
+    fn node(&self, level: u32, index: u64) -> io::Result<Hash<D>> {
+        Ok(self.levels[level as usize][index as usize].clone())
+    }
+
+    fn index_of(&self, entry: &Hash<D>) -> io::Result<Option<u64>> {
+        Ok(self.map.get(entry).cloned())
+    }
+
+    fn indices_of(&self, entry: &Hash<D>) -> io::Result<Vec<u64>> {
+        let mut indices: Vec<_> =
+            self.map.get(entry).cloned().into_iter().collect();
+        if let Some(more) = self.duplicates.get(entry) {
+            indices.extend(more);
+        }
+        Ok(indices)
+    }
+
+    fn append(
+        &mut self,
+        entry: Hash<D>,
+        nodes: &[Hash<D>],
+    ) -> io::Result<()> {
+        for (level, node) in nodes.iter().enumerate() {
+            if level == self.levels.len() {
+                self.levels.push(Vec::new());
+            }
+            self.levels[level].push(node.clone());
+        }
+        self.push_entry(entry);
+        Ok(())
+    }
+
+    fn append_all(
+        &mut self,
+        entries: Vec<Hash<D>>,
+        duplicates: bool,
+    ) -> io::Result<u64> {
+        let appended =
+            self.append_mapped(entries, map_chunks::<D>, duplicates);
+        Ok(appended as u64)
+    }
+}
+
+const ENTRIES_FILE: &str = "entries";
+const INDEX_FILE: &str = "index";
+const MIN_INDEX_SLOTS: u64 = 1024;
+
+/// Keeps a tree in a directory so that it survives restarts and may grow
+/// larger than memory.
+///
+/// The directory contains
+///
+/// * `entries`: the entry hashes in insertion order,
+/// * `nodes-<level>`: the perfect subtree roots of each level,
+/// * `index`: an open addressing hash table from entries to positions.
+///
+/// All hashes are stored as raw bytes, integers as big endian `u64`. The
+/// `entries` file is authoritative: a node or index write that was cut
+/// short, for example by a crash, is repaired from it by `open`.
+///
+/// The index starts with the number of indexed entries, followed by a
+/// power of two number of slots. A slot holds one plus the position of an
+/// entry, or zero if it is empty. Entries are placed by linear probing,
+/// starting at the slot given by the first eight bytes of their hash. An
+/// entry appended several times has a slot for every position, in
+/// ascending order along the probe sequence.
+///
+/// This layout is stable, so that trees can be shared with readers using
+/// `MappedStorage`.
+///
+/// A failed append is rolled back, so that the tree stays as it was. If
+/// that fails as well, further appends are refused until the directory is
+/// opened again, which repairs it. Failed reads are reported as errors by
+/// the `try_` methods of `MerkleTree`.
+pub struct FileStorage<D: Digest> {
+    dir: PathBuf,
+    len: u64,
+    entries: File,
+    levels: Vec<File>,
+    index: File,
+    slots: u64,
+    poisoned: bool,
+    _digest: PhantomData<D>,
+}
+
+impl<D: Digest> FileStorage<D> {
+    /// Open the tree stored in `dir`, creating an empty one if the
+    /// directory does not contain a tree yet.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<FileStorage<D>> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let entries = open_append(&dir.join(ENTRIES_FILE))?;
+        let len = entries.metadata()?.len() / hash_len::<D>();
+        entries.set_len(len * hash_len::<D>())?;
+
+        let index = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(INDEX_FILE))?;
+
+        let mut storage = FileStorage {
+            dir,
+            len,
+            entries,
+            levels: Vec::new(),
+            index,
+            slots: 0,
+            poisoned: false,
+            _digest: PhantomData,
+        };
+        storage.repair_levels()?;
+        storage.repair_index()?;
+        Ok(storage)
+    }
+
+    /// The directory holding the tree.
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Flush all files to disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.entries.sync_data()?;
+        for f in &self.levels {
+            f.sync_data()?;
+        }
+        self.index.sync_data()
+    }
+
+    fn level_path(&self, level: usize) -> PathBuf {
+        self.dir.join(format!("nodes-{}", level))
+    }
+
+    /// Bring every level to the number of nodes implied by the entries.
+    fn repair_levels(&mut self) -> io::Result<()> {
+        let mut level = 0;
+        while level < 64 && self.len >> level > 0 {
+            let expected = self.len >> level;
+            let f = open_append(&self.level_path(level))?;
+            let present =
+                cmp::min(f.metadata()?.len() / hash_len::<D>(), expected);
+            f.set_len(present * hash_len::<D>())?;
+            self.levels.push(f);
+
+            for i in present..expected {
+                let node = if level == 0 {
+                    D::hash_leaf(&self.entry(i)?)
+                } else {
+                    let l = level as u32 - 1;
+                    let left = self.node(l, 2 * i)?;
+                    D::hash_inner(&left, &self.node(l, 2 * i + 1)?)
+                };
+                self.levels[level].write_all(&node)?;
+            }
+            level += 1;
+        }
+
+        // Levels above the top one have to be empty, or later appends
+        // would end up behind stale nodes.
+        while self.level_path(level).exists() {
+            File::create(self.level_path(level))?;
+            level += 1;
+        }
+        Ok(())
+    }
+
+    /// Make sure every entry can be found through the index.
+    fn repair_index(&mut self) -> io::Result<()> {
+        let size = self.index.metadata()?.len();
+        let slots = size.saturating_sub(8) / 8;
+        let indexed = if size >= 8 {
+            read_u64(&self.index, 0)?
+        } else {
+            0
+        };
+        if slots < MIN_INDEX_SLOTS
+            || !slots.is_power_of_two()
+            || indexed > self.len
+            || self.len * 2 > slots
+        {
+            let mut slots = MIN_INDEX_SLOTS;
+            while self.len * 2 > slots {
+                slots *= 2;
+            }
+            return self.rebuild_index(slots);
+        }
+
+        self.slots = slots;
+        for i in indexed..self.len {
+            let entry = self.entry(i)?;
+            if !self.indices_of(&entry)?.contains(&i) {
+                self.index_insert(&entry, i)?;
+            }
+        }
+        write_u64(&self.index, 0, self.len)
+    }
+
+    /// Replace the index with a fresh one of `slots` slots.
+    fn rebuild_index(&mut self, slots: u64) -> io::Result<()> {
+        let tmp = self.dir.join("index.new");
+        let index = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)?;
+        clear_index(&index, slots)?;
+        // Keep the current index if the new one cannot be completed.
+        let old_index = mem::replace(&mut self.index, index);
+        let old_slots = mem::replace(&mut self.slots, slots);
+        let result = self
+            .fill_index()
+            .and_then(|_| fs::rename(&tmp, self.dir.join(INDEX_FILE)));
+        if result.is_err() {
+            self.index = old_index;
+            self.slots = old_slots;
+        }
+        result
+    }
+
+    fn fill_index(&self) -> io::Result<()> {
+        for i in 0..self.len {
+            let entry = self.entry(i)?;
+            self.index_insert(&entry, i)?;
+        }
+        write_u64(&self.index, 0, self.len)
+    }
+
+    fn entry(&self, index: u64) -> io::Result<Hash<D>> {
+        read_hash::<D>(&self.entries, index * hash_len::<D>())
+    }
+
+    fn probe<F>(&self, entry: &Hash<D>, found: F) -> io::Result<u64>
+    where
+        F: FnMut(u64) -> bool,
+    {
+        probe::<D, _, _, _>(
+            entry,
+            self.slots,
+            self.len,
+            |slot| read_u64(&self.index, 8 + 8 * slot),
+            |i| self.entry(i),
+            found,
+        )
+    }
+
+    fn index_insert(&self, entry: &Hash<D>, index: u64) -> io::Result<()> {
+        let slot = self.probe(entry, |_| false)?;
+        write_u64(&self.index, 8 + 8 * slot, index + 1)
+    }
+
+    /// Write the entry at position `len` to all files, recording the index
+    /// slot it takes in `slot` before writing to it.
+    fn write_entry(
+        &mut self,
+        entry: &Hash<D>,
+        nodes: &[Hash<D>],
+        slot: &mut Option<u64>,
+    ) -> io::Result<()> {
+        self.entries.write_all(entry)?;
+        for (level, node) in nodes.iter().enumerate() {
+            if level == self.levels.len() {
+                let f = open_append(&self.level_path(level))?;
+                self.levels.push(f);
+            }
+            self.levels[level].write_all(node)?;
+        }
+        let free = self.probe(entry, |_| false)?;
+        *slot = Some(free);
+        write_u64(&self.index, 8 + 8 * free, self.len + 1)?;
+        write_u64(&self.index, 0, self.len + 1)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Undo a failed `write_entry`: cut all files back to `len` entries,
+    /// so that the next append does not land behind partial writes, and
+    /// clear the index slot of the entry. It was the last one filled, so
+    /// no other entry is placed behind it.
+    fn roll_back(&mut self, slot: Option<u64>) -> io::Result<()> {
+        self.entries.set_len(self.len * hash_len::<D>())?;
+        for (level, f) in self.levels.iter().enumerate() {
+            f.set_len((self.len >> level) * hash_len::<D>())?;
+        }
+        if let Some(slot) = slot {
+            write_u64(&self.index, 8 + 8 * slot, 0)?;
+        }
+        write_u64(&self.index, 0, self.len)
+    }
+}
+
+impl<D: Digest> Storage<D> for FileStorage<D> {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn node(&self, level: u32, index: u64) -> io::Result<Hash<D>> {
+        read_hash::<D>(&self.levels[level as usize], index * hash_len::<D>())
+    }
+
+    fn index_of(&self, entry: &Hash<D>) -> io::Result<Option<u64>> {
+        let mut index = None;
+        self.probe(entry, |i| {
+            index = Some(i);
+            true
+        })?;
+        Ok(index)
+    }
+
+    fn indices_of(&self, entry: &Hash<D>) -> io::Result<Vec<u64>> {
+        let mut indices = Vec::new();
+        self.probe(entry, |i| {
+            indices.push(i);
+            false
+        })?;
+        Ok(indices)
+    }
+
+    // io::Error::other needs Rust 1.74.
+    #[allow(clippy::io_other_error)]
+    fn append(
+        &mut self,
+        entry: Hash<D>,
+        nodes: &[Hash<D>],
+    ) -> io::Result<()> {
+        if self.poisoned {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "an earlier append failed, open the storage again to repair it",
+            ));
+        }
+        if (self.len + 1) * 2 > self.slots {
+            let slots = self.slots * 2;
+            self.rebuild_index(slots)?;
+        }
+
+        let mut slot = None;
+        let result = self.write_entry(&entry, nodes, &mut slot);
+        if result.is_err() && self.roll_back(slot).is_err() {
+            self.poisoned = true;
+        }
+        result
+    }
+}
+
+/// A read-only view of a tree written by `FileStorage`, for example by
+/// `MerkleTree::save`, that maps the files into memory instead of reading
+/// them.
+///
+/// The view shows the tree at the size it had when it was opened, even if
+/// a `FileStorage` keeps appending to the directory. The files must not be
+/// truncated or otherwise modified while they are mapped, so only trees
+/// that were closed cleanly are accepted. Needs the `memmap` feature.
+#[cfg(feature = "memmap")]
+pub struct MappedStorage<D: Digest> {
+    len: u64,
+    entries: Option<Mmap>,
+    levels: Vec<Mmap>,
+    index: Mmap,
+    slots: u64,
+    _digest: PhantomData<D>,
+}
+
+/// A tree served from a memory-mapped `FileStorage` directory.
+#[cfg(feature = "memmap")]
+pub type MappedMerkleTree<D> = MerkleTree<D, MappedStorage<D>>;
+
+#[cfg(feature = "memmap")]
+impl<D: Digest> MappedStorage<D> {
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<MappedStorage<D>> {
+        let dir = dir.as_ref();
+        let entries = map(&dir.join(ENTRIES_FILE))?;
+        let size = entries.as_ref().map_or(0, |m| m.len() as u64);
+        if size % hash_len::<D>() != 0 {
+            return Err(unclean());
+        }
+        let len = size / hash_len::<D>();
+
+        let mut levels = Vec::new();
+        let mut level = 0;
+        while level < 64 && len >> level > 0 {
+            let path = dir.join(format!("nodes-{}", level));
+            let m = match map(&path)? {
+                Some(m) => m,
+                None => return Err(unclean()),
+            };
+            if m.len() as u64 != (len >> level) * hash_len::<D>() {
+                return Err(unclean());
+            }
+            levels.push(m);
+            level += 1;
+        }
+
+        let index = match map(&dir.join(INDEX_FILE))? {
+            Some(index) => index,
+            None => return Err(unclean()),
+        };
+        // Probing relies on the index never being more than half full.
+        let slots = (index.len() as u64).saturating_sub(8) / 8;
+        if !slots.is_power_of_two()
+            || len * 2 > slots
+            || mapped_u64(&index, 0) != len
+        {
+            return Err(unclean());
+        }
+
+        Ok(MappedStorage {
+            len,
+            entries,
+            levels,
+            index,
+            slots,
+            _digest: PhantomData,
+        })
+    }
+
+    fn entry(&self, index: u64) -> Hash<D> {
+        let entries = self.entries.as_ref().unwrap();
+        mapped_hash::<D>(entries, index)
+    }
+
+    fn probe<F: FnMut(u64) -> bool>(&self, entry: &Hash<D>, found: F) {
+        if self.len > 0 {
+            // Mapped reads cannot fail, the files were checked by `open`.
+            let _ = probe::<D, _, _, _>(
+                entry,
+                self.slots,
+                self.len,
+                |slot| Ok(mapped_u64(&self.index, 8 + 8 * slot)),
+                |i| Ok(self.entry(i)),
+                found,
+            );
+        }
+    }
+}
+
+#[cfg(feature = "memmap")]
+impl<D: Digest> Storage<D> for MappedStorage<D> {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn node(&self, level: u32, index: u64) -> io::Result<Hash<D>> {
+        Ok(mapped_hash::<D>(&self.levels[level as usize], index))
+    }
+
+    fn index_of(&self, entry: &Hash<D>) -> io::Result<Option<u64>> {
+        let mut index = None;
+        self.probe(entry, |i| {
+            index = Some(i);
+            true
+        });
+        Ok(index)
+    }
+
+    fn indices_of(&self, entry: &Hash<D>) -> io::Result<Vec<u64>> {
+        let mut indices = Vec::new();
+        self.probe(entry, |i| {
+            indices.push(i);
+            false
+        });
+        Ok(indices)
+    }
+
+    fn append(&mut self, _: Hash<D>, _: &[Hash<D>]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "mapped trees are read-only",
+        ))
+    }
+}
+
+#[cfg(feature = "memmap")]
+fn map(path: &Path) -> io::Result<Option<Mmap>> {
+    let f = File::open(path)?;
+    // Empty files cannot be mapped.
+    if f.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    unsafe { Mmap::map(&f) }.map(Some)
+}
+
+#[cfg(feature = "memmap")]
+fn unclean() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "tree files are inconsistent, open them with FileStorage to repair",
+    )
+}
+
+#[cfg(feature = "memmap")]
+fn mapped_hash<D: Digest>(m: &Mmap, index: u64) -> Hash<D> {
+    let start = (index * hash_len::<D>()) as usize;
+    let end = start + hash_len::<D>() as usize;
+    Hash::<D>::clone_from_slice(&m[start..end])
+}
+
+#[cfg(feature = "memmap")]
+fn mapped_u64(m: &Mmap, offset: u64) -> u64 {
+    let offset = offset as usize;
+    m[offset..offset + 8]
+        .iter()
+        .fold(0, |acc, &x| (acc << 8) | u64::from(x))
+}
+
+/// Walk the probe sequence of `entry`, passing every position of `entry`
+/// found on the way to `found` until it returns true. Returns the slot
+/// holding that position, or the empty slot ending the sequence, where
+/// another position of `entry` belongs.
+fn probe<D, S, E, F>(
+    entry: &Hash<D>,
+    slots: u64,
+    len: u64,
+    slot_value: S,
+    entry_at: E,
+    mut found: F,
+) -> io::Result<u64>
+where
+    D: Digest,
+    S: Fn(u64) -> io::Result<u64>,
+    E: Fn(u64) -> io::Result<Hash<D>>,
+    F: FnMut(u64) -> bool,
+{
+    let mask = slots - 1;
+    let mut slot = entry
+        .iter()
+        .take(8)
+        .fold(0u64, |acc, &b| (acc << 8) | u64::from(b))
+        & mask;
+    loop {
+        let value = slot_value(slot)?;
+        if value == 0 {
+            return Ok(slot);
+        }
+        // Slots can point past the end after an interrupted append.
+        let index = value - 1;
+        if index < len && entry_at(index)? == *entry && found(index) {
+            return Ok(slot);
+        }
+        slot = (slot + 1) & mask;
+    }
+}
+
+fn hash_len<D: Digest>() -> u64 {
+    mem::size_of::<Hash<D>>() as u64
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+}
+
+fn clear_index(f: &File, slots: u64) -> io::Result<()> {
+    f.set_len(0)?;
+    f.set_len(8 + 8 * slots)
+}
+
+fn read_hash<D: Digest>(f: &File, offset: u64) -> io::Result<Hash<D>> {
+    let mut h = Hash::<D>::default();
+    read_exact_at(f, &mut h, offset)?;
+    Ok(h)
+}
+
+fn read_u64(f: &File, offset: u64) -> io::Result<u64> {
+    let mut b = [0; 8];
+    read_exact_at(f, &mut b, offset)?;
+    Ok(b.iter().fold(0, |acc, &x| (acc << 8) | u64::from(x)))
+}
+
+fn write_u64(f: &File, offset: u64, x: u64) -> io::Result<()> {
+    let mut b = [0; 8];
+    for (i, byte) in b.iter_mut().enumerate() {
+        *byte = (x >> (56 - 8 * i)) as u8;
+    }
+    write_all_at(f, &b, offset)
+}
+
+#[cfg(unix)]
+fn read_exact_at(f: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    f.read_exact_at(buf, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(f: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    f.write_all_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(
+    f: &File,
+    mut buf: &mut [u8],
+    mut offset: u64,
+) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match f.seek_read(buf, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                let tmp = buf;
+                buf = &mut tmp[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn write_all_at(
+    f: &File,
+    mut buf: &[u8],
+    mut offset: u64,
+) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match f.seek_write(buf, offset)? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Elsewhere positional I/O is emulated by seeking. This moves the shared
+// file cursor, so a storage must not be read from several threads at once
+// on these platforms.
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(f: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut f = f;
+    f.seek(SeekFrom::Start(offset))?;
+    f.read_exact(buf)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn write_all_at(f: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom};
+    let mut f = f;
+    f.seek(SeekFrom::Start(offset))?;
+    f.write_all(buf)
+}
@@ -0,0 +1,331 @@
+//! Publishing trees as tiles, following the C2SP `tlog-tiles` layout.
+//!
+//! A tile at level `L` holds up to 256 consecutive hashes of level `8 * L`
+//! of the tree, that is the roots of perfect subtrees with `256^L` leaves.
+//! The hashes in between are recomputed from the tile below them. Tiles
+//! are full once they hold 256 hashes and never change after that; the last
+//! tile of every level is usually a partial one that grows with the tree.
+//!
+//! Logs serve the tiles returned by `MerkleTree::tile` as static files at
+//! `Tile::path`, and clients use a `TileReader` to build proofs from them.
+//! Entry bundles (`tile/entries/...`) are not covered, as trees only store
+//! the hashes of their entries.
+
+use digest::{Digest, Hash};
+use merkle::{MerkleTree, TreeHead};
+use proof::{ConsistencyProof, ConsistencyProofBase, InclusionProof,
+            InclusionProofBase};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::{cmp, io, mem};
+use storage::Storage;
+
+/// Number of tree levels covered by a tile.
+pub const TILE_HEIGHT: u32 = 8;
+/// Number of hashes in a full tile.
+pub const TILE_WIDTH: u64 = 1 << TILE_HEIGHT;
+
+/// Coordinates of a tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Tile {
+    level: u32,
+    index: u64,
+    width: u64,
+}
+
+impl Tile {
+    /// The tile at `level` and `index`, holding `width` hashes. Returns
+    /// `None` unless `0 < width <= TILE_WIDTH`.
+    pub fn new(level: u32, index: u64, width: u64) -> Option<Tile> {
+        if width == 0 || width > TILE_WIDTH {
+            return None;
+        }
+        Some(Tile {
+            level,
+            index,
+            width,
+        })
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn width(&self) -> u64 {
+        self.width
+    }
+
+    pub fn is_partial(&self) -> bool {
+        self.width < TILE_WIDTH
+    }
+
+    /// The path of the tile, like `tile/1/x001/234.p/17`.
+    pub fn path(&self) -> String {
+        let mut n = self.index;
+        let mut groups = vec![format!("{:03}", n % 1000)];
+        n /= 1000;
+        while n > 0 {
+            groups.push(format!("x{:03}", n % 1000));
+            n /= 1000;
+        }
+        groups.reverse();
+
+        let mut path = format!("tile/{}/{}", self.level, groups.join("/"));
+        if self.is_partial() {
+            path.push_str(&format!(".p/{}", self.width));
+        }
+        path
+    }
+
+    /// Parse a path produced by `path`.
+    // strip_prefix and strip_suffix need Rust 1.45.
+    #[allow(clippy::manual_strip)]
+    pub fn from_path(path: &str) -> Option<Tile> {
+        let mut parts = path.split('/');
+        if parts.next() != Some("tile") {
+            return None;
+        }
+        let level = parse_decimal(parts.next()?)?;
+        if level >= 64 / TILE_HEIGHT as u64 {
+            return None;
+        }
+
+        let mut index = 0u64;
+        let mut width = TILE_WIDTH;
+        loop {
+            let part = parts.next()?;
+            if part.starts_with('x') {
+                index = push_group(index, &part[1..])?;
+                continue;
+            }
+            if part.ends_with(".p") {
+                index = push_group(index, &part[..part.len() - 2])?;
+                width = parse_decimal(parts.next()?)?;
+                if width >= TILE_WIDTH {
+                    return None;
+                }
+            } else {
+                index = push_group(index, part)?;
+            }
+            break;
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Tile::new(level as u32, index, width)
+    }
+
+    /// The tile holding node `index` of level `level * TILE_HEIGHT` in a
+    /// tree of `tree_size` elements, as wide as that tree allows.
+    fn covering(level: u32, index: u64, tree_size: u64) -> Tile {
+        let nodes = tree_size >> (level * TILE_HEIGHT);
+        let tile = index / TILE_WIDTH;
+        Tile {
+            level,
+            index: tile,
+            width: cmp::min(TILE_WIDTH, nodes - tile * TILE_WIDTH),
+        }
+    }
+}
+
+fn parse_decimal(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+fn push_group(index: u64, group: &str) -> Option<u64> {
+    if group.len() != 3 {
+        return None;
+    }
+    index.checked_mul(1000)?.checked_add(parse_decimal(group)?)
+}
+
+/// All tiles of a tree with `tree_size` elements.
+pub fn tiles(tree_size: u64) -> Vec<Tile> {
+    new_tiles(0, tree_size)
+}
+
+/// The tiles that have to be published when a tree grows from `old_size`
+/// to `new_size` elements: new full tiles and the new partial tiles.
+pub fn new_tiles(old_size: u64, new_size: u64) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    let mut level = 0;
+    while level * TILE_HEIGHT < 64 {
+        let old_nodes = old_size >> (level * TILE_HEIGHT);
+        let new_nodes = new_size >> (level * TILE_HEIGHT);
+        if new_nodes <= old_nodes {
+            break;
+        }
+        let first = old_nodes / TILE_WIDTH;
+        let last = (new_nodes - 1) / TILE_WIDTH;
+        for index in first..last + 1 {
+            tiles.push(Tile::covering(level, index * TILE_WIDTH, new_size));
+        }
+        level += 1;
+    }
+    tiles
+}
+
+/// Where a `TileReader` gets its tiles from, for example a HTTP client or a
+/// local mirror.
+pub trait TileSource {
+    fn read_tile(&self, tile: &Tile) -> io::Result<Vec<u8>>;
+}
+
+impl<F: Fn(&Tile) -> io::Result<Vec<u8>>> TileSource for F {
+    fn read_tile(&self, tile: &Tile) -> io::Result<Vec<u8>> {
+        self(tile)
+    }
+}
+
+/// Builds proofs for a tree head from tiles alone.
+///
+/// The tiles are not trusted: proofs built from bad tiles simply fail to
+/// verify. Inclusion proofs carry the tree head the reader was created
+/// with, so `verify` checks them against it.
+pub struct TileReader<D: Digest, T: TileSource> {
+    head: TreeHead<D>,
+    source: T,
+    cache: RefCell<HashMap<Tile, Vec<u8>>>,
+}
+
+impl<D: Digest, T: TileSource> TileReader<D, T> {
+    /// A reader for the tree with the trusted head `head`.
+    pub fn new(head: TreeHead<D>, source: T) -> TileReader<D, T> {
+        TileReader {
+            head,
+            source,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn head(&self) -> &TreeHead<D> {
+        &self.head
+    }
+
+    /// The RFC 6962 leaf hash of the element at position `index`.
+    pub fn leaf_hash(&self, index: u64) -> io::Result<Option<Hash<D>>> {
+        self.with_tree(|mt| mt.try_leaf_hash(index))
+    }
+
+    /// Prove inclusion of the element at position `index`.
+    pub fn inclusion_proof(
+        &self,
+        index: u64,
+    ) -> io::Result<Option<InclusionProof<D>>> {
+        let size = self.head.size();
+        let base = self.with_tree(|mt| {
+            InclusionProofBase::from_index(index, size, mt)
+        })?;
+        Ok(base.map(|x| InclusionProof::new(x, self.head.clone())))
+    }
+
+    /// Prove that the tree head is an extension of the tree at `old_size`
+    /// elements. Check the proof with `verify_against` and the trusted old
+    /// tree head.
+    pub fn consistency_proof(
+        &self,
+        old_size: u64,
+    ) -> io::Result<Option<ConsistencyProof<D>>> {
+        let size = self.head.size();
+        let base = self.with_tree(|mt| {
+            ConsistencyProofBase::new(old_size, size, mt)
+        })?;
+        Ok(base.map(|x| ConsistencyProof::new(x, self.head.clone())))
+    }
+
+    /// Run `f` once to learn which tiles it needs, fetch them, and run it
+    /// again on the fetched tiles.
+    fn with_tree<F, R>(&self, f: F) -> io::Result<R>
+    where
+        F: Fn(&MerkleTree<D, TileView<D, T>>) -> io::Result<R>,
+    {
+        let needed = RefCell::new(Vec::new());
+        f(&MerkleTree::with_storage(TileView {
+            reader: self,
+            needed: Some(&needed),
+        }))?;
+        for tile in needed.into_inner() {
+            self.fetch(tile)?;
+        }
+        f(&MerkleTree::with_storage(TileView {
+            reader: self,
+            needed: None,
+        }))
+    }
+
+    fn fetch(&self, tile: Tile) -> io::Result<()> {
+        if self.cache.borrow().contains_key(&tile) {
+            return Ok(());
+        }
+        let data = self.source.read_tile(&tile)?;
+        if data.len() as u64 != tile.width * hash_len::<D>() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("tile {} has the wrong size", tile.path()),
+            ));
+        }
+        self.cache.borrow_mut().insert(tile, data);
+        Ok(())
+    }
+}
+
+/// Storage serving nodes from the tiles of a `TileReader`. While `needed`
+/// is set it only records the tiles it would read.
+struct TileView<'a, D: Digest + 'a, T: TileSource + 'a> {
+    reader: &'a TileReader<D, T>,
+    needed: Option<&'a RefCell<Vec<Tile>>>,
+}
+
+impl<'a, D: Digest, T: TileSource> Storage<D> for TileView<'a, D, T> {
+    fn len(&self) -> u64 {
+        self.reader.head.size()
+    }
+
+    fn node(&self, level: u32, index: u64) -> io::Result<Hash<D>> {
+        // The node covers 2^r hashes of the bottom level of its tile.
+        let r = level % TILE_HEIGHT;
+        let first = index << r;
+        let tile = Tile::covering(level / TILE_HEIGHT, first, self.len());
+        if let Some(needed) = self.needed {
+            needed.borrow_mut().push(tile);
+            return Ok(Hash::<D>::default());
+        }
+
+        let cache = self.reader.cache.borrow();
+        let data = &cache[&tile];
+        let offset = (first % TILE_WIDTH) as usize;
+        let n = hash_len::<D>() as usize;
+        let mut hashes: Vec<Hash<D>> = (offset..offset + (1 << r))
+            .map(|i| Hash::<D>::clone_from_slice(&data[i * n..(i + 1) * n]))
+            .collect();
+        while hashes.len() > 1 {
+            hashes = hashes
+                .chunks(2)
+                .map(|pair| D::hash_inner(&pair[0], &pair[1]))
+                .collect();
+        }
+        Ok(hashes.pop().unwrap())
+    }
+
+    fn index_of(&self, _: &Hash<D>) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    fn append(&mut self, _: Hash<D>, _: &[Hash<D>]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "tiles are read-only",
+        ))
+    }
+}
+
+fn hash_len<D: Digest>() -> u64 {
+    mem::size_of::<Hash<D>>() as u64
+}
@@ -0,0 +1,273 @@
+//! Witnesses cosigning tree heads, and policies requiring their
+//! cosignatures.
+//!
+//! A log can present different versions of its tree to different clients,
+//! a *split view*, and every client on its own will happily verify proofs
+//! against the head it was given. A witness remembers the latest head it
+//! cosigned for each log and only cosigns a new head after checking a
+//! consistency proof from that one, so it never cosigns two heads that
+//! contradict each other. Clients that insist on cosignatures by enough
+//! witnesses, as described by a `Policy`, can then only be shown a split
+//! view if these witnesses are shown it too, and they would refuse.
+//!
+//! Witnesses cosign both checkpoints, using C2SP `cosignature/v1` lines,
+//! and `SignedTreeHead`s. Heads of the same log share the same state, no
+//! matter which form they were submitted in. Tree heads may be signed and
+//! cosigned with any `Signer`, checkpoints only with Ed25519 keys as the
+//! signed note format has no other signature types.
+
+use checkpoint::{self, NoteError, SignedCheckpoint};
+use digest::Digest;
+use merkle::TreeHead;
+use proof::{ConsistencyProof, VerificationError};
+use signed_merkle::{self, KeyPair, PubKey, SignedTreeHead};
+use signer::{LogKey, Signer, Verifier};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// Reasons for a witness to refuse cosigning a head.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WitnessError {
+    /// The head is from a log the witness does not know.
+    UnknownLog,
+    /// The consistency proof does not start at the latest head the witness
+    /// cosigned. The client should retry with a proof from `latest`.
+    Conflict,
+    /// The log signature or the consistency proof does not verify.
+    Verification(VerificationError),
+    /// The witness key failed to make the cosignature.
+    Signing,
+}
+
+impl fmt::Display for WitnessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WitnessError::UnknownLog => f.write_str("unknown log"),
+            WitnessError::Conflict => {
+                f.write_str("proof does not start at the latest tree head")
+            }
+            WitnessError::Verification(ref e) => {
+                write!(f, "verification failed: {}", e)
+            }
+            WitnessError::Signing => f.write_str("signing failed"),
+        }
+    }
+}
+
+impl Error for WitnessError {}
+
+impl From<VerificationError> for WitnessError {
+    fn from(e: VerificationError) -> Self {
+        WitnessError::Verification(e)
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+struct WitnessedLog<D: Digest> {
+    key: LogKey,
+    #[cfg_attr(feature = "serde", serde(bound = ""))]
+    latest: Option<TreeHead<D>>,
+}
+
+/// Cosigns the heads of the logs it knows, as long as they are consistent
+/// with the heads it cosigned before.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Witness<D: Digest, S: Signer = KeyPair> {
+    name: String,
+    keypair: S,
+    #[cfg_attr(feature = "serde", serde(bound = ""))]
+    logs: HashMap<String, WitnessedLog<D>>,
+}
+
+impl<D: Digest, S: Signer> Witness<D, S> {
+    /// A witness cosigning with `keypair` under the key name `name`.
+    pub fn new(name: &str, keypair: S) -> Result<Self, NoteError> {
+        checkpoint::check_name(name)?;
+        Ok(Witness {
+            name: name.to_string(),
+            keypair,
+            logs: HashMap::new(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn pub_key(&self) -> S::PublicKey {
+        self.keypair.public_key()
+    }
+
+    /// Start witnessing the log `origin`, whose heads are signed by `key`.
+    /// Checkpoints of the log have to be signed under the key name
+    /// `origin`. Adding a known log again replaces its key but keeps the
+    /// latest head.
+    pub fn add_log<V: Verifier>(&mut self, origin: &str, key: &V) {
+        let key = LogKey::from_verifier(key);
+        let latest = self.logs.remove(origin).and_then(|log| log.latest);
+        self.logs
+            .insert(origin.to_string(), WitnessedLog { key, latest });
+    }
+
+    /// The latest head of the log `origin` cosigned by the witness.
+    pub fn latest(&self, origin: &str) -> Option<&TreeHead<D>> {
+        self.logs.get(origin).and_then(|log| log.latest.as_ref())
+    }
+
+    /// Cosign `sth`, a signed tree head of the log `origin`, if it is
+    /// signed by the log and `proof` shows it to be consistent with the
+    /// latest head cosigned so far. The first head of a log needs a proof
+    /// from the empty tree.
+    pub fn cosign_tree_head(
+        &mut self,
+        origin: &str,
+        sth: &mut SignedTreeHead<D>,
+        proof: &ConsistencyProof<D>,
+    ) -> Result<(), WitnessError> {
+        let head = sth.tree_head().clone();
+        self.advance(origin, head, proof, |key| sth.verify(key))?;
+        let cosig = sth
+            .cosign(&self.keypair)
+            .map_err(|_| WitnessError::Signing)?;
+        sth.add_cosignature(cosig);
+        Ok(())
+    }
+
+    /// Check the log signature with `verify` and the consistency of `head`
+    /// with the latest head of the log, and make it the latest head.
+    fn advance<F>(
+        &mut self,
+        origin: &str,
+        head: TreeHead<D>,
+        proof: &ConsistencyProof<D>,
+        verify: F,
+    ) -> Result<(), WitnessError>
+    where
+        F: FnOnce(&LogKey) -> Result<(), VerificationError>,
+    {
+        let log = match self.logs.get_mut(origin) {
+            Some(log) => log,
+            None => return Err(WitnessError::UnknownLog),
+        };
+        verify(&log.key)?;
+        let old_size = log.latest.as_ref().map_or(0, |th| th.size());
+        if proof.old_size() != old_size {
+            return Err(WitnessError::Conflict);
+        }
+        if let Some(ref old) = log.latest {
+            proof.verify_against(old, &head)?;
+        } else if !proof.consistency_path().is_empty() {
+            return Err(VerificationError::WrongPathLength.into());
+        }
+        log.latest = Some(head);
+        Ok(())
+    }
+}
+
+impl<D: Digest> Witness<D, KeyPair> {
+    /// Like `cosign_tree_head`, for a checkpoint of a log signing with an
+    /// Ed25519 key. `cosignature/v1` lines are only defined for Ed25519
+    /// witness keys.
+    pub fn cosign(
+        &mut self,
+        checkpoint: &mut SignedCheckpoint<D>,
+        proof: &ConsistencyProof<D>,
+    ) -> Result<(), WitnessError> {
+        let origin = checkpoint.checkpoint().origin().to_string();
+        let head = checkpoint.checkpoint().tree_head().clone();
+        self.advance(&origin, head, proof, |key| {
+            match PubKey::from_verifier(key) {
+                Some(key) => checkpoint.verify(&origin, &key),
+                None => Err(VerificationError::BadSignature),
+            }
+        })?;
+        let timestamp = signed_merkle::now_millis() / 1000;
+        checkpoint
+            .cosign(&self.name, &self.keypair, timestamp)
+            .expect("witness name was checked on creation");
+        Ok(())
+    }
+}
+
+/// What it takes for a client to accept a tree head: a signature by the
+/// log and cosignatures by at least `threshold` of the listed witnesses.
+/// Checkpoints are only accepted from logs and witnesses with Ed25519 keys.
+pub struct Policy<L: Verifier = PubKey, W: Verifier = PubKey> {
+    origin: String,
+    log_key: L,
+    witnesses: Vec<(String, W)>,
+    threshold: usize,
+}
+
+impl<L: Verifier, W: Verifier> Policy<L, W> {
+    /// A policy for the log `origin` signing with `log_key`, requiring
+    /// `threshold` witness cosignatures.
+    pub fn new(origin: &str, log_key: L, threshold: usize) -> Self {
+        Policy {
+            origin: origin.to_string(),
+            log_key,
+            witnesses: Vec::new(),
+            threshold,
+        }
+    }
+
+    /// Count cosignatures by the witness `key` named `name`.
+    pub fn add_witness(&mut self, name: &str, key: W) {
+        self.witnesses.push((name.to_string(), key));
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Check the log signature and the witness cosignatures of a
+    /// checkpoint.
+    pub fn verify_checkpoint<D: Digest>(
+        &self,
+        checkpoint: &SignedCheckpoint<D>,
+    ) -> Result<(), VerificationError> {
+        if checkpoint.checkpoint().origin() != self.origin {
+            return Err(VerificationError::BadSignature);
+        }
+        let log_key = match PubKey::from_verifier(&self.log_key) {
+            Some(key) => key,
+            None => return Err(VerificationError::BadSignature),
+        };
+        checkpoint.verify(&self.origin, &log_key)?;
+        self.check_quorum(|name, key| match PubKey::from_verifier(key) {
+            Some(key) => checkpoint.verify_cosignature(name, &key).is_ok(),
+            None => false,
+        })
+    }
+
+    /// Check the log signature and the witness cosignatures of a signed
+    /// tree head.
+    pub fn verify_tree_head<D: Digest>(
+        &self,
+        sth: &SignedTreeHead<D>,
+    ) -> Result<(), VerificationError> {
+        sth.verify(&self.log_key)?;
+        self.check_quorum(|_, key| sth.verify_cosignature(key).is_ok())
+    }
+
+    fn check_quorum<F>(&self, cosigned: F) -> Result<(), VerificationError>
+    where
+        F: Fn(&str, &W) -> bool,
+    {
+        let mut count = 0;
+        for (i, (name, key)) in self.witnesses.iter().enumerate() {
+            // A witness listed twice only counts once.
+            let seen = self.witnesses[..i]
+                .iter()
+                .any(|w| w.1.key_bytes() == key.key_bytes());
+            if !seen && cosigned(name, key) {
+                count += 1;
+            }
+        }
+        if count < self.threshold {
+            return Err(VerificationError::MissingCosignatures);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::{Sha256, entry};
+use merkle_rs::{DuplicatePolicy, InsertError, MerkleTree, OwningMerkleTree};
+
+#[test]
+fn append_returns_indices() {
+    let mut mt = MerkleTree::<Sha256>::new();
+    for i in 0..10 {
+        assert_eq!(mt.append(entry(i)).unwrap(), u64::from(i));
+    }
+    match mt.append(entry(4)) {
+        Err(InsertError::Duplicate(4)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(mt.len(), 10);
+
+    mt.set_duplicate_policy(DuplicatePolicy::ReturnExisting);
+    assert_eq!(mt.append(entry(4)).unwrap(), 4);
+    mt.set_duplicate_policy(DuplicatePolicy::Allow);
+    assert_eq!(mt.append(entry(4)).unwrap(), 10);
+
+    let p = mt.inclusion_proof_by_index(10).unwrap();
+    assert!(p.verify_against(&mt.head()).is_ok());
+    assert_eq!(p.leaf_hash(), &mt.leaf_hash(4).unwrap());
+}
+
+#[test]
+fn append_many_returns_ranges() {
+    let mut mt = MerkleTree::<Sha256>::new();
+    assert_eq!(mt.append_many((0..5).map(entry)).unwrap(), 0..5);
+    // Duplicates are skipped, so the appended entries stay consecutive.
+    assert_eq!(mt.append_many((3..8).map(entry)).unwrap(), 5..8);
+    assert_eq!(mt.index_of(entry(7)), Some(7));
+    assert_eq!(mt.append_many((0..3).map(entry)).unwrap(), 8..8);
+
+    let mut omt = OwningMerkleTree::<String, Sha256>::new();
+    assert_eq!(omt.append("a".to_string()).unwrap(), 0);
+    let range = omt
+        .append_many(vec!["a".to_string(), "b".to_string(), "c".to_string()])
+        .unwrap();
+    assert_eq!(range, 1..3);
+    assert_eq!(omt.get(2).map(String::as_str), Some("c"));
+}
+
+#[cfg(feature = "memmap")]
+#[test]
+fn append_reports_storage_errors() {
+    use merkle_rs::storage::MappedStorage;
+    use std::fs;
+
+    let dir = common::temp_dir("append");
+    let mt: MerkleTree<Sha256> = (0..3).map(entry).collect();
+    mt.save(&dir).unwrap();
+
+    let mut mapped = MerkleTree::<Sha256, _>::with_storage(
+        MappedStorage::open(&dir).unwrap(),
+    );
+    match mapped.append(entry(2)) {
+        Err(InsertError::Duplicate(2)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    match mapped.append(entry(3)) {
+        Err(InsertError::Io(_)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "ring")]
+#[test]
+fn signed_tree_append() {
+    use merkle_rs::{KeyPair, SignedMerkleTree, SignedOwningMerkleTree};
+
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(kp);
+    assert_eq!(smt.append(entry(0)).unwrap(), 0);
+    assert_eq!(smt.append_many((1..6).map(entry)).unwrap(), 1..6);
+    assert_eq!(smt.head().size(), 6);
+    let p = smt.inclusion_proof_by_index(5).unwrap();
+    assert!(p.verify(&pk).is_ok());
+    assert!(smt.append(entry(5)).is_err());
+    smt.set_duplicate_policy(DuplicatePolicy::ReturnExisting);
+    assert_eq!(smt.append(entry(5)).unwrap(), 5);
+    assert_eq!(smt.append_many((4..6).map(entry)).unwrap(), 6..6);
+
+    let mut somt =
+        SignedOwningMerkleTree::<String, Sha256>::new(KeyPair::new().unwrap());
+    assert_eq!(somt.append("a".to_string()).unwrap(), 0);
+    assert_eq!(somt.append_many(vec!["b".to_string()]).unwrap(), 1..2);
+    assert_eq!(somt.head().size(), 2);
+}
+
+#[cfg(feature = "ring")]
+#[test]
+fn failed_signing_removes_entries() {
+    use merkle_rs::signer::{PendingSignature, Signer, TreeHeadSigner};
+    use merkle_rs::{KeyPair, PubKey, SignedMerkleTree,
+                    SignedOwningMerkleTree};
+    use std::cell::Cell;
+    use std::io;
+    use std::rc::Rc;
+
+    struct FlakySigner(KeyPair, Rc<Cell<bool>>);
+
+    impl TreeHeadSigner for FlakySigner {
+        type Key = PubKey;
+
+        fn verifier(&self) -> PubKey {
+            self.0.pub_key()
+        }
+
+        fn sign_tree_head(&self, input: &[u8]) -> PendingSignature {
+            if self.1.get() {
+                let err = io::Error::new(io::ErrorKind::TimedOut, "offline");
+                return PendingSignature::ready(Err(err));
+            }
+            PendingSignature::ready(Ok(self.0.sign_message(input).unwrap()))
+        }
+    }
+
+    let offline = Rc::new(Cell::new(false));
+    let signer = FlakySigner(KeyPair::new().unwrap(), offline.clone());
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(signer);
+    smt.append_many((0..3).map(entry)).unwrap();
+    offline.set(true);
+    assert!(smt.append(entry(3)).is_err());
+    assert!(smt.try_insert(entry(3)).is_err());
+    assert!(smt.append_many((3..6).map(entry)).is_err());
+    assert_eq!(smt.len(), 3);
+    assert!(!smt.contains(entry(3)));
+    offline.set(false);
+    assert_eq!(smt.append(entry(3)).unwrap(), 3);
+    assert_eq!(smt.append_many((3..6).map(entry)).unwrap(), 4..6);
+    let unsigned: MerkleTree<Sha256> = (0..6).map(entry).collect();
+    assert_eq!(smt.head().root_hash(), unsigned.head().root_hash());
+    smt.set_duplicate_policy(DuplicatePolicy::Allow);
+    assert_eq!(smt.append(entry(1)).unwrap(), 6);
+    offline.set(true);
+    assert!(smt.append_many((0..3).map(entry)).is_err());
+    assert_eq!(smt.indices_of(entry(1)), vec![1, 6]);
+    assert_eq!(smt.indices_of(entry(0)), vec![0]);
+    offline.set(false);
+    smt.wait_for_head().unwrap();
+    let mut unsigned = MerkleTree::<Sha256>::new();
+    unsigned.set_duplicate_policy(DuplicatePolicy::Allow);
+    unsigned.extend((0..6).chain(Some(1)).map(entry));
+    assert_eq!(smt.head().root_hash(), unsigned.head().root_hash());
+
+    let signer = FlakySigner(KeyPair::new().unwrap(), offline.clone());
+    let mut somt = SignedOwningMerkleTree::<String, Sha256, _>::new(signer);
+    somt.insert("a".to_string());
+    offline.set(true);
+    assert!(somt.append("b".to_string()).is_err());
+    assert_eq!(somt.len(), 1);
+    assert!(somt.get(1).is_none());
+    offline.set(false);
+    assert_eq!(somt.append("b".to_string()).unwrap(), 1);
+    assert_eq!(somt.get(1).map(String::as_str), Some("b"));
+}
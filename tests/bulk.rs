@@ -0,0 +1,74 @@
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::{Sha256, entry};
+use merkle_rs::digest::Hash;
+use merkle_rs::MerkleTree;
+
+fn sequential(hashes: &[Hash<Sha256>]) -> MerkleTree<Sha256> {
+    let mut mt = MerkleTree::new();
+    for h in hashes {
+        mt.insert(*h);
+    }
+    mt
+}
+
+fn assert_same(
+    a: &MerkleTree<Sha256>,
+    b: &MerkleTree<Sha256>,
+    hashes: &[Hash<Sha256>],
+) {
+    assert_eq!(a.len(), b.len());
+    assert!(a.iter().eq(b.iter()));
+    for size in 0..=a.len() {
+        let (ha, hb) = (a.head_at(size).unwrap(), b.head_at(size).unwrap());
+        assert_eq!(ha.root_hash(), hb.root_hash());
+    }
+    for h in hashes {
+        let pa = a.inclusion_proof(*h).unwrap();
+        let pb = b.inclusion_proof(*h).unwrap();
+        assert_eq!(pa.index(), pb.index());
+        assert_eq!(pa.audit_path(), pb.audit_path());
+    }
+}
+
+#[test]
+fn bulk_trees_match_sequential_ones() {
+    for &n in &[0, 1, 2, 3, 7, 8, 9, 31, 32, 33, 100] {
+        let hashes: Vec<_> = (0..n).map(entry).collect();
+        let mt = MerkleTree::<Sha256>::from_hashes(hashes.clone());
+        assert_same(&mt, &sequential(&hashes), &hashes);
+        let collected: MerkleTree<Sha256> = hashes.iter().copied().collect();
+        assert_same(&collected, &mt, &hashes);
+    }
+}
+
+#[test]
+fn bulk_extension() {
+    let hashes: Vec<_> = (0..150).map(entry).collect();
+    let expected = sequential(&hashes);
+    for &split in &[0, 1, 5, 16, 63, 64, 149] {
+        let mut mt = sequential(&hashes[..split]);
+        let added = mt.extend_from_hashes(hashes[split..].to_vec());
+        assert_eq!(added, 150 - split);
+        assert_same(&mt, &expected, &hashes);
+    }
+}
+
+#[test]
+fn bulk_duplicates() {
+    // Repeated entries are skipped, whether they are part of the tree or
+    // of the batch.
+    let hashes: Vec<_> =
+        (0..40).chain(10..20).chain(35..45).map(entry).collect();
+    let mt = MerkleTree::<Sha256>::from_hashes(hashes.clone());
+    assert_eq!(mt.len(), 45);
+    assert_same(&mt, &sequential(&hashes), &hashes);
+
+    let mut mt = MerkleTree::<Sha256>::from_hashes(hashes[..20].to_vec());
+    assert_eq!(mt.extend_from_hashes(hashes.clone()), 25);
+    assert_same(&mt, &sequential(&hashes), &hashes);
+    assert_eq!(mt.index_of(entry(44)), Some(44));
+}
@@ -0,0 +1,192 @@
+#![cfg(feature = "ring")]
+
+extern crate base64;
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use common::{Sha256, entry};
+use merkle_rs::checkpoint::{self, Checkpoint, NoteError, SignedCheckpoint};
+use merkle_rs::proof::VerificationError;
+use merkle_rs::{KeyPair, MerkleTree, SignedMerkleTree, TreeHead};
+use std::io;
+
+#[test]
+fn checkpoint_text() {
+    let mt: MerkleTree<Sha256> = (0..5).map(entry).collect();
+    let mut cp = Checkpoint::new("example.com/log", mt.head()).unwrap();
+    let root = *mt.head().root_hash();
+    assert_eq!(
+        cp.to_text(),
+        format!("example.com/log\n5\n{}\n", BASE64.encode(root))
+    );
+
+    cp.add_extension("ext one").unwrap();
+    assert_eq!(cp.add_extension(""), Err(NoteError::InvalidLine));
+    assert_eq!(cp.add_extension("a\nb"), Err(NoteError::InvalidLine));
+    let text = cp.to_text();
+    assert!(text.ends_with("\next one\n"));
+
+    let parsed = Checkpoint::<Sha256>::from_text(&text).unwrap();
+    assert_eq!(parsed.origin(), "example.com/log");
+    assert_eq!(parsed.tree_head().size(), 5);
+    assert_eq!(parsed.tree_head().root_hash(), &root);
+    assert_eq!(parsed.extensions(), &["ext one".to_string()]);
+    assert_eq!(parsed.to_text(), text);
+
+    let th = TreeHead::<Sha256>::new(0, root);
+    assert!(Checkpoint::new("", th.clone()).is_err());
+    assert!(Checkpoint::new("a\nb", th).is_err());
+}
+
+#[test]
+fn malformed_checkpoints() {
+    let root = BASE64.encode([0; 32]);
+    for text in &[
+        format!("origin\n5\n{}", root),
+        format!("origin\n05\n{}\n", root),
+        format!("origin\n+5\n{}\n", root),
+        format!("origin\n-1\n{}\n", root),
+        format!("\n5\n{}\n", root),
+        format!("origin\n5\n{}\n", BASE64.encode([0; 31])),
+        // Missing padding, and unused bits that are not zero.
+        format!("origin\n5\n{}\n", root.trim_end_matches('=')),
+        format!("origin\n5\n{}B=\n", &root[..42]),
+        format!("origin\n5\n{}\n\n", root),
+        "origin\n5\nnot base64\n".to_string(),
+        "origin\n5\n".to_string(),
+    ] {
+        assert_eq!(
+            Checkpoint::<Sha256>::from_text(text).err(),
+            Some(NoteError::MalformedCheckpoint),
+            "{:?}",
+            text
+        );
+    }
+}
+
+#[test]
+fn signed_tree_checkpoint() {
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let mut smt = SignedMerkleTree::<Sha256>::new(kp);
+    smt.extend((0..9).map(entry));
+
+    let signed = smt.checkpoint("example.com/log").unwrap();
+    let note = signed.to_note();
+    let (text, sigs) = note.split_at(note.find("\n\n").unwrap() + 1);
+    assert_eq!(text, signed.checkpoint().to_text());
+    assert!(sigs.starts_with("\n\u{2014} example.com/log "));
+    assert_eq!(sigs.matches('\n').count(), 2);
+
+    let parsed = SignedCheckpoint::<Sha256>::from_note(&note).unwrap();
+    assert_eq!(parsed.verify("example.com/log", &pk), Ok(()));
+    assert_eq!(parsed.checkpoint().tree_head().size(), 9);
+    assert_eq!(
+        parsed.checkpoint().tree_head().root_hash(),
+        smt.head().root_hash()
+    );
+    assert_eq!(
+        parsed.signatures()[0].key_hash(),
+        checkpoint::key_hash("example.com/log", &pk)
+    );
+
+    let other = KeyPair::new().unwrap().pub_key();
+    assert_eq!(
+        parsed.verify("example.com/log", &other),
+        Err(VerificationError::BadSignature)
+    );
+    assert_eq!(
+        parsed.verify("other.example/log", &pk),
+        Err(VerificationError::BadSignature)
+    );
+
+    // Changing the tree size invalidates the signature.
+    let forged = note.replacen("\n9\n", "\n8\n", 1);
+    let forged = SignedCheckpoint::<Sha256>::from_note(&forged).unwrap();
+    assert_eq!(
+        forged.verify("example.com/log", &pk),
+        Err(VerificationError::BadSignature)
+    );
+
+    let err = smt.checkpoint("bad name").err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let err = err.into_inner().unwrap().downcast::<NoteError>().unwrap();
+    assert_eq!(*err, NoteError::InvalidName);
+}
+
+#[test]
+fn several_signers() {
+    let log = KeyPair::new().unwrap();
+    let witness = KeyPair::new().unwrap();
+    let mt: MerkleTree<Sha256> = (0..3).map(entry).collect();
+
+    let cp = Checkpoint::new("example.com/log", mt.head()).unwrap();
+    let mut signed = SignedCheckpoint::new(cp);
+    signed.sign("example.com/log", &log).unwrap();
+    signed.sign("witness.example", &witness).unwrap();
+
+    let parsed =
+        SignedCheckpoint::<Sha256>::from_note(&signed.to_note()).unwrap();
+    assert_eq!(parsed.signatures().len(), 2);
+    assert!(parsed.verify("example.com/log", &log.pub_key()).is_ok());
+    assert!(parsed.verify("witness.example", &witness.pub_key()).is_ok());
+    assert!(parsed.verify("witness.example", &log.pub_key()).is_err());
+    assert_eq!(parsed.to_note(), signed.to_note());
+}
+
+#[test]
+fn malformed_notes() {
+    let kp = KeyPair::new().unwrap();
+    let smt = SignedMerkleTree::<Sha256>::new(kp);
+    let note = smt.checkpoint("example.com/log").unwrap().to_note();
+    let text = note[..note.find("\n\n").unwrap() + 1].to_string();
+
+    for bad in &[
+        text.clone(),
+        format!("{}\n", text),
+        note[..note.len() - 1].to_string(),
+        note.replace("\u{2014}", "-"),
+        note.replace(" example.com/log ", " example.com/log  "),
+        format!("{}\u{2014} name AAAA\n", note),
+        format!("{}\u{2014} name\n", note),
+    ] {
+        assert!(
+            SignedCheckpoint::<Sha256>::from_note(bad).is_err(),
+            "{:?}",
+            bad
+        );
+    }
+}
+
+#[test]
+fn verifier_keys() {
+    // Example key from the documentation of the Go note package.
+    let vkey =
+        "PeterNeumann+c74f20a3+ARpc2QcUPDhMQegwxbzhKqiBfsVkmqq/LDE4izWy10TW";
+    let (name, pk) = checkpoint::parse_verifier_key(vkey).unwrap();
+    assert_eq!(name, "PeterNeumann");
+    assert_eq!(checkpoint::key_hash(&name, &pk), 0xc74f_20a3);
+    assert_eq!(checkpoint::verifier_key(&name, &pk).unwrap(), vkey);
+
+    let pk = KeyPair::new().unwrap().pub_key();
+    let vkey = checkpoint::verifier_key("example.com/log", &pk).unwrap();
+    let (name, parsed) = checkpoint::parse_verifier_key(&vkey).unwrap();
+    assert_eq!(name, "example.com/log");
+    assert!(parsed == pk);
+
+    for bad in &[
+        "PeterNeumann+c74f20a4+ARpc2QcUPDhMQegwxbzhKqiBfsVkmqq/LDE4izWy10TW",
+        "PeterNeumann+c74f20a3+Apc2QcUPDhMQegwxbzhKqiBfsVkmqq/LDE4izWy10TW",
+        "PeterNeumann+c74f20a3",
+        "+c74f20a3+ARpc2QcUPDhMQegwxbzhKqiBfsVkmqq/LDE4izWy10TW",
+    ] {
+        assert_eq!(
+            checkpoint::parse_verifier_key(bad).err(),
+            Some(NoteError::MalformedKey)
+        );
+    }
+}
@@ -0,0 +1,31 @@
+//! Fixtures shared by the integration tests. Every test crate only uses
+//! some of them.
+#![allow(dead_code)]
+
+use merkle_rs::MerkleTree;
+use merkle_rs::digest::{self, Hash};
+use merkle_rs::storage::FileStorage;
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+pub type Sha256 = ::sha2::Sha256;
+
+/// The `i`th test entry. Distinct `i` give distinct entries.
+pub fn entry(i: u32) -> Hash<Sha256> {
+    let b = [i as u8, (i >> 8) as u8, (i >> 16) as u8, (i >> 24) as u8];
+    <Sha256 as digest::Digest>::hash_elem(&b)
+}
+
+/// An empty directory for the test `name`, removing what earlier runs
+/// left behind.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir()
+        .join(format!("merkle-rs-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// The tree persisted in `dir`, created if needed.
+pub fn open(dir: &Path) -> MerkleTree<Sha256, FileStorage<Sha256>> {
+    MerkleTree::with_storage(FileStorage::open(dir).unwrap())
+}
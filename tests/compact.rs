@@ -0,0 +1,30 @@
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::{Sha256, entry};
+use merkle_rs::{CompactMerkleTree, MerkleTree};
+
+#[test]
+fn compact_tree_heads() {
+    let mut mt = MerkleTree::<Sha256>::new();
+    let mut cmt = CompactMerkleTree::<Sha256>::new();
+    assert!(cmt.is_empty());
+
+    for i in 0..300u32 {
+        assert_eq!(cmt.head().size(), mt.head().size());
+        assert_eq!(cmt.head().root_hash(), mt.head().root_hash());
+        assert_eq!(cmt.frontier().len() as u32, i.count_ones());
+
+        let converted = CompactMerkleTree::from(&mt);
+        assert_eq!(converted.len(), cmt.len());
+        assert_eq!(converted.frontier(), cmt.frontier());
+
+        mt.insert(entry(i));
+        cmt.insert(entry(i));
+    }
+
+    let bulk: CompactMerkleTree<Sha256> = (0..300).map(entry).collect();
+    assert_eq!(bulk.head().root_hash(), mt.head().root_hash());
+}
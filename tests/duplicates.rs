@@ -0,0 +1,151 @@
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::{Sha256, entry, open, temp_dir};
+use merkle_rs::digest::Hash;
+use merkle_rs::{CompactMerkleTree, DuplicatePolicy, MerkleTree,
+                OwningMerkleTree};
+use std::fs;
+
+// The first 50 entries appear twice, the second time 100 positions later.
+fn log() -> Vec<Hash<Sha256>> {
+    (0..100).chain(0..50).map(entry).collect()
+}
+
+#[test]
+fn duplicate_policies() {
+    let mut mt = MerkleTree::<Sha256>::new();
+    assert_eq!(mt.duplicate_policy(), DuplicatePolicy::Reject);
+    assert!(mt.insert(entry(0)));
+    assert!(!mt.insert(entry(0)));
+    assert_eq!(mt.len(), 1);
+
+    mt.set_duplicate_policy(DuplicatePolicy::ReturnExisting);
+    assert!(mt.insert(entry(0)));
+    assert!(mt.insert(entry(1)));
+    assert_eq!(mt.len(), 2);
+    assert_eq!(mt.indices_of(entry(0)), vec![0]);
+
+    mt.set_duplicate_policy(DuplicatePolicy::Allow);
+    assert!(mt.insert(entry(0)));
+    assert_eq!(mt.len(), 3);
+    assert_eq!(mt.index_of(entry(0)), Some(0));
+    assert_eq!(mt.indices_of(entry(0)), vec![0, 2]);
+    assert_eq!(mt.indices_of(entry(1)), vec![1]);
+    assert!(mt.indices_of(entry(2)).is_empty());
+}
+
+#[test]
+fn logs_with_duplicates() {
+    let mut mt = MerkleTree::<Sha256>::new();
+    mt.set_duplicate_policy(DuplicatePolicy::Allow);
+    let mut cmt = CompactMerkleTree::<Sha256>::new();
+    for h in log() {
+        assert!(mt.insert(h));
+        cmt.insert(h);
+    }
+    assert_eq!(mt.len(), 150);
+    assert_eq!(mt.head().root_hash(), cmt.head().root_hash());
+    assert_eq!(mt.indices_of(entry(7)), vec![7, 107]);
+    assert_eq!(mt.indices_of(entry(57)), vec![57]);
+
+    // Both copies can be proven, and the tree before the second one
+    // already contains the first.
+    for &i in &[7, 107] {
+        let p = mt.inclusion_proof_by_index(i).unwrap();
+        assert!(p.verify_against(&mt.head()).is_ok());
+    }
+    assert!(mt.inclusion_proof_at(entry(7), 100).is_some());
+
+    let mut bulk = MerkleTree::<Sha256>::new();
+    bulk.set_duplicate_policy(DuplicatePolicy::Allow);
+    assert_eq!(bulk.extend_from_hashes(log()), 150);
+    assert_eq!(bulk.head().root_hash(), mt.head().root_hash());
+    assert_eq!(bulk.indices_of(entry(7)), vec![7, 107]);
+
+    let mut omt = OwningMerkleTree::<String, Sha256>::new();
+    omt.set_duplicate_policy(DuplicatePolicy::Allow);
+    for s in &["a", "b", "a"] {
+        assert!(omt.insert(s.to_string()));
+    }
+    assert_eq!(omt.len(), 3);
+    assert_eq!(omt.get(2).map(String::as_str), Some("a"));
+    assert_eq!(omt.indices_of(&"a".to_string()), vec![0, 2]);
+}
+
+#[test]
+fn file_storage_duplicates() {
+    let dir = temp_dir("duplicates");
+    let mut mt = MerkleTree::<Sha256>::new();
+    mt.set_duplicate_policy(DuplicatePolicy::Allow);
+    mt.extend(log());
+
+    let mut ft = open(&dir);
+    ft.set_duplicate_policy(DuplicatePolicy::Allow);
+    ft.extend(log()[..120].iter().copied());
+    drop(ft);
+
+    // Duplicates survive reopening and growing the index.
+    let mut ft = open(&dir);
+    assert_eq!(ft.duplicate_policy(), DuplicatePolicy::Reject);
+    assert!(!ft.insert(entry(3)));
+    ft.set_duplicate_policy(DuplicatePolicy::Allow);
+    ft.extend(log()[120..].iter().copied());
+    for i in 0..1200 {
+        ft.insert(entry(1000 + i));
+        mt.insert(entry(1000 + i));
+    }
+    assert_eq!(ft.head().root_hash(), mt.head().root_hash());
+    for i in 0..100 {
+        assert_eq!(ft.indices_of(entry(i)), mt.indices_of(entry(i)));
+    }
+    assert_eq!(ft.index_of(entry(42)), Some(42));
+    fs::remove_dir_all(&dir).unwrap();
+
+    mt.save(&dir).unwrap();
+    let ft = open(&dir);
+    assert_eq!(ft.head().root_hash(), mt.head().root_hash());
+    assert_eq!(ft.indices_of(entry(42)), vec![42, 142]);
+
+    #[cfg(feature = "memmap")]
+    {
+        use merkle_rs::storage::MappedStorage;
+
+        let mapped = MerkleTree::<Sha256, _>::with_storage(
+            MappedStorage::open(&dir).unwrap(),
+        );
+        assert_eq!(mapped.indices_of(entry(42)), vec![42, 142]);
+        assert_eq!(mapped.indices_of(entry(92)), vec![92]);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "ring")]
+#[test]
+fn signed_tree_duplicates() {
+    use merkle_rs::{KeyPair, SignedMerkleTree};
+
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(kp);
+    assert!(smt.insert(entry(0)));
+    assert!(!smt.stage(entry(0)));
+
+    smt.set_duplicate_policy(DuplicatePolicy::ReturnExisting);
+    assert!(smt.insert(entry(0)));
+    assert!(smt.stage(entry(0)));
+    assert_eq!(smt.queued(), 0);
+    assert_eq!(smt.head().size(), 1);
+
+    smt.set_duplicate_policy(DuplicatePolicy::Allow);
+    assert_eq!(smt.stage_many(vec![entry(0), entry(1), entry(1)]), 3);
+    assert_eq!(smt.commit().unwrap(), 3);
+    assert_eq!(smt.head().size(), 4);
+    assert!(smt.head().verify(&pk).is_ok());
+    assert_eq!(smt.indices_of(entry(1)), vec![2, 3]);
+    let p = smt.inclusion_proof_by_index(3).unwrap();
+    assert!(p.verify(&pk).is_ok());
+}
@@ -0,0 +1,95 @@
+#![cfg(feature = "ring")]
+
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::{Sha256, entry};
+use merkle_rs::proof::VerificationError;
+use merkle_rs::signer::Verifier;
+use merkle_rs::{EcdsaKeyPair, EcdsaPubKey, KeyPair, KeyRing, LogId, PubKey,
+                SignedMerkleTree};
+
+// The public key of RFC 8410, section 10.1.
+const PUBLIC_PEM: &str = "\
+-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEAGb9ECWmEzf6FQbrBZ9w7lshQhqowtrbLDFw4rXAxZuE=
+-----END PUBLIC KEY-----
+";
+// A P-256 point generated by OpenSSL, and the base64 SHA-256 of its
+// SubjectPublicKeyInfo as written by `openssl ec -pubout`.
+const P256_POINT: [u8; 65] = [
+    0x04, 0x5e, 0x70, 0x8f, 0xe6, 0x6c, 0xde, 0x37, 0x76, 0x9b, 0x4b, 0x65,
+    0xa6, 0x7e, 0xfe, 0x9d, 0x6c, 0x87, 0x0a, 0xea, 0xf6, 0x11, 0xc8, 0x3f,
+    0x59, 0x1b, 0x72, 0x08, 0x37, 0xe5, 0x56, 0xdc, 0x6e, 0x12, 0x45, 0xc8,
+    0x52, 0xf1, 0x98, 0x25, 0x9c, 0xaf, 0x2c, 0xe4, 0x99, 0x59, 0xb2, 0xe4,
+    0x35, 0x58, 0x13, 0xf2, 0x9d, 0x03, 0xb4, 0x72, 0x82, 0xb8, 0x4f, 0xcd,
+    0xd5, 0xd5, 0x42, 0xa0, 0x49,
+];
+const P256_LOG_ID: &str = "NNKp3J2gYzEkXdTR3qHYVs69k/5BtzAo2od2dtBIHGE=";
+
+#[test]
+fn log_ids() {
+    let pk = PubKey::from_pem(PUBLIC_PEM).unwrap();
+    assert_eq!(
+        pk.log_id().to_string(),
+        "oekVYFTgT6yJmunydRMs3Ael28TqLCrTof/G4NJTaB8="
+    );
+    assert_eq!(Verifier::log_id(&pk), pk.log_id());
+    let ec = EcdsaPubKey::from_point(&P256_POINT).unwrap();
+    assert_eq!(ec.log_id().to_string(), P256_LOG_ID);
+    assert_eq!(ec.to_spki_der().len(), 91);
+
+    let id = pk.log_id();
+    assert_eq!(LogId::from_bytes(id.as_bytes()), Some(id));
+    assert_eq!(LogId::from_bytes(&[0; 31]), None);
+}
+
+#[test]
+fn signed_heads_carry_log_ids() {
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let mut smt = SignedMerkleTree::<Sha256>::new(kp);
+    smt.extend((0..6).map(entry));
+    assert_eq!(smt.head().log_id(), pk.log_id());
+    let p = smt.inclusion_proof(entry(4)).unwrap();
+    assert_eq!(p.log_id(), pk.log_id());
+    let p = smt.consistency_proof(2).unwrap();
+    assert_eq!(p.log_id(), pk.log_id());
+}
+
+#[test]
+fn key_ring_picks_the_key() {
+    let (a, b) = (KeyPair::new().unwrap(), KeyPair::new().unwrap());
+    let mut ring = KeyRing::new();
+    let a_id = ring.add(a.pub_key());
+    ring.add(b.pub_key());
+    assert_eq!(ring.len(), 2);
+    assert!(ring.contains(&a_id));
+
+    let mut smt_a = SignedMerkleTree::<Sha256>::new(a);
+    let mut smt_b = SignedMerkleTree::<Sha256>::new(b);
+    smt_a.extend((0..5).map(entry));
+    smt_b.extend((3..9).map(entry));
+    let pa = smt_a.inclusion_proof(entry(1)).unwrap();
+    let pb = smt_b.inclusion_proof(entry(7)).unwrap();
+    assert!(pa.verify(&ring).is_ok());
+    assert!(pb.verify(&ring).is_ok());
+    let old = smt_a.consistency_proof_between(0, 2).unwrap();
+    let pc = smt_a.consistency_proof(2).unwrap();
+    assert!(pc.verify(old.tree_head().root_hash(), &ring).is_ok());
+    assert!(ring.iter().all(|(id, key)| *id == key.log_id()));
+
+    assert!(ring.remove(&a_id).is_some());
+    assert_eq!(pa.verify(&ring), Err(VerificationError::BadSignature));
+    assert!(pb.verify(&ring).is_ok());
+
+    // Rings hold keys of any one algorithm.
+    let ec = EcdsaKeyPair::new().unwrap();
+    let mut ring = KeyRing::new();
+    ring.add(ec.pub_key());
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(ec);
+    smt.insert(entry(0));
+    assert!(smt.head().verify(&ring).is_ok());
+}
@@ -51,11 +51,11 @@ fn large_tree() {
         sheads.push(smt.head());
         soheads.push(somt.head());
 
-        assert!(sheads[i].verify(&pubk));
-        assert!(soheads[i].verify(&opubk));
+        assert!(sheads[i].verify(&pubk).is_ok());
+        assert!(soheads[i].verify(&opubk).is_ok());
 
-        assert!(!sheads[i].verify(&opubk));
-        assert!(!soheads[i].verify(&pubk));
+        assert!(sheads[i].verify(&opubk).is_err());
+        assert!(soheads[i].verify(&pubk).is_err());
 
         assert!(heads[i].root_hash() == oheads[i].root_hash());
         assert!(heads[i].root_hash() == sheads[i].root_hash());
@@ -67,37 +67,89 @@ fn large_tree() {
             assert!(!smt.insert(hashes[j]));
             assert!(!somt.insert(A(j)));
 
-            assert!(mt.inclusion_proof(hashes[j]).unwrap().verify());
-            assert!(omt.inclusion_proof(hashes[j]).unwrap().verify());
-            assert!(omt.inclusion_proof(&A(j)).unwrap().verify());
+            assert!(mt.inclusion_proof(hashes[j]).unwrap().verify().is_ok());
+            assert!(omt.inclusion_proof(hashes[j]).unwrap().verify().is_ok());
+            assert!(omt.inclusion_proof(&A(j)).unwrap().verify().is_ok());
 
-            assert!(smt.inclusion_proof(hashes[j]).unwrap().verify(&pubk));
-            assert!(somt.inclusion_proof(hashes[j]).unwrap().verify(&opubk));
-            assert!(somt.inclusion_proof(&A(j)).unwrap().verify(&opubk));
+            assert!(
+                smt.inclusion_proof(hashes[j])
+                    .unwrap()
+                    .verify(&pubk)
+                    .is_ok()
+            );
+            assert!(
+                somt.inclusion_proof(hashes[j])
+                    .unwrap()
+                    .verify(&opubk)
+                    .is_ok()
+            );
+            assert!(
+                somt.inclusion_proof(&A(j))
+                    .unwrap()
+                    .verify(&opubk)
+                    .is_ok()
+            );
+
+            assert!(
+                smt.inclusion_proof_by_index(j as u64)
+                    .unwrap()
+                    .verify(&pubk)
+                    .is_ok()
+            );
+            assert!(
+                somt.inclusion_proof_by_index(j as u64)
+                    .unwrap()
+                    .verify(&opubk)
+                    .is_ok()
+            );
+
+            let old_size = j as u64 + 1;
+            assert!(
+                omt.inclusion_proof_at(&A(j), old_size)
+                    .unwrap()
+                    .verify_against(&heads[j])
+                    .is_ok()
+            );
+            assert!(
+                smt.inclusion_proof_at(hashes[j], old_size)
+                    .unwrap()
+                    .verify_against(&heads[j])
+                    .is_ok()
+            );
+            assert!(
+                somt.inclusion_proof_at(&A(j), old_size)
+                    .unwrap()
+                    .verify_against(&heads[j])
+                    .is_ok()
+            );
 
             assert!(
                 mt.consistency_proof(j as u64 + 1,)
                     .unwrap()
                     .verify(heads[j].root_hash(),)
+                    .is_ok()
             );
             assert!(
                 omt.consistency_proof(j as u64 + 1,)
                     .unwrap()
                     .verify(heads[j].root_hash(),)
+                    .is_ok()
             );
             assert!(
                 smt.consistency_proof(j as u64 + 1,)
                     .unwrap()
                     .verify(heads[j].root_hash(), &pubk,)
+                    .is_ok()
             );
             assert!(
                 somt.consistency_proof(j as u64 + 1,)
                     .unwrap()
                     .verify(heads[j].root_hash(), &opubk,)
+                    .is_ok()
             );
         }
 
-        #[cfg_attr(feature = "cargo-clippy", allow(needless_range_loop))]
+        #[allow(clippy::needless_range_loop)]
         for j in i + 1..max_size {
             assert!(mt.inclusion_proof(hashes[j]).is_none());
             assert!(omt.inclusion_proof(hashes[j]).is_none());
@@ -135,17 +187,17 @@ fn large_tree() {
 
     bulksmt.extend(hashes.iter().cloned());
     assert!(bulksmt.head().root_hash() == sheads[max_size - 1].root_hash());
-    assert!(bulksmt.head().verify(&pubk));
+    assert!(bulksmt.head().verify(&pubk).is_ok());
 
     bulksomt.extend((0..max_size).map(A));
     assert!(bulksomt.head().root_hash() == heads[max_size - 1].root_hash());
-    assert!(bulksomt.head().verify(&opubk));
+    assert!(bulksomt.head().verify(&opubk).is_ok());
 }
 
 #[derive(Hash, Eq, PartialEq)]
 struct A(usize);
 impl digest::Digestible for A {
-    fn hash_bytes(&self, digest: &mut digest::Input) {
+    fn hash_bytes(&self, digest: &mut dyn digest::Input) {
         let mut b = [0; 8];
         BigEndian::write_u64(&mut b, self.0 as u64);
         digest.process(&b)
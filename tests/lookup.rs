@@ -0,0 +1,88 @@
+extern crate byteorder;
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::Sha256;
+use byteorder::{BigEndian, ByteOrder};
+use merkle_rs::{MerkleTree, OwningMerkleTree};
+use merkle_rs::digest;
+
+#[test]
+fn merkle_tree_lookup() {
+    let hashes: Vec<_> = (0..10)
+        .map(|i| <Sha256 as digest::Digest>::hash_elem(&A(i)))
+        .collect();
+    let mut mt = MerkleTree::<Sha256>::new();
+    assert!(mt.is_empty());
+    assert_eq!(mt.iter().count(), 0);
+    assert!(mt.entries(0..10).is_empty());
+
+    mt.extend(hashes.iter().cloned());
+    assert_eq!(mt.len(), 10);
+    assert!(!mt.is_empty());
+
+    for (i, h) in hashes.iter().enumerate() {
+        assert!(mt.contains(*h));
+        assert_eq!(mt.index_of(*h), Some(i as u64));
+        assert_eq!(mt.get(i as u64), Some(h));
+    }
+    let missing = <Sha256 as digest::Digest>::hash_elem(&A(10));
+    assert!(!mt.contains(missing));
+    assert_eq!(mt.index_of(missing), None);
+    assert_eq!(mt.get(10), None);
+
+    assert!(mt.iter().eq(hashes.iter()));
+    assert_eq!(mt.entries(0..10), &hashes[..]);
+    assert_eq!(mt.entries(3..5), &hashes[3..5]);
+    assert_eq!(mt.entries(8..20), &hashes[8..]);
+    assert!(mt.entries(12..20).is_empty());
+    let (start, end) = (5, 3);
+    assert!(mt.entries(start..end).is_empty());
+}
+
+#[test]
+fn owning_merkle_tree_lookup() {
+    let omt: OwningMerkleTree<A, Sha256> = (0..10).map(A).collect();
+    assert_eq!(omt.len(), 10);
+
+    for i in 0..10 {
+        assert!(omt.contains(&A(i)));
+        assert_eq!(omt.index_of(&A(i)), Some(i as u64));
+        assert_eq!(omt.get(i as u64), Some(&A(i)));
+    }
+    assert!(!omt.contains(&A(10)));
+    assert_eq!(omt.get(10), None);
+
+    assert!(omt.iter().cloned().eq((0..10).map(A)));
+    assert_eq!(omt.entries(2..4), &[A(2), A(3)]);
+    assert_eq!(omt.entries(9..12), &[A(9)]);
+}
+
+#[cfg(feature = "ring")]
+#[test]
+fn signed_tree_lookup() {
+    use merkle_rs::{KeyPair, SignedOwningMerkleTree};
+
+    let mut somt =
+        SignedOwningMerkleTree::<A, Sha256>::new(KeyPair::new().unwrap());
+    somt.extend((0..5).map(A));
+
+    assert_eq!(somt.len(), 5);
+    assert_eq!(somt.index_of(&A(3)), Some(3));
+    assert_eq!(somt.get(3), Some(&A(3)));
+    assert_eq!(somt.entries(3..10), &[A(3), A(4)]);
+    assert_eq!(somt.iter().count(), 5);
+}
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+struct A(usize);
+
+impl digest::Digestible for A {
+    fn hash_bytes(&self, digest: &mut dyn digest::Input) {
+        let mut b = [0; 8];
+        BigEndian::write_u64(&mut b, self.0 as u64);
+        digest.process(&b)
+    }
+}
@@ -0,0 +1,187 @@
+#![cfg(feature = "ring")]
+
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::{Sha256, entry};
+use merkle_rs::promise::DEFAULT_MERGE_DELAY;
+use merkle_rs::proof::VerificationError;
+use merkle_rs::{DuplicatePolicy, EcdsaKeyPair, KeyPair, KeyRing,
+                SignedMerkleTree, SignedOwningMerkleTree};
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn promises_are_kept() {
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let mut smt = SignedMerkleTree::<Sha256>::new(kp);
+    smt.extend((0..3).map(entry));
+    assert_eq!(smt.merge_delay(), DEFAULT_MERGE_DELAY);
+    smt.set_merge_delay(60_000);
+
+    let promises: Vec<_> =
+        (3..8).map(|i| smt.submit(entry(i)).unwrap()).collect();
+    assert_eq!(smt.queued(), 5);
+    assert_eq!(smt.len(), 3);
+    assert!(smt.inclusion_proof(entry(3)).is_none());
+    for (i, p) in (3..8).zip(&promises) {
+        assert!(p.verify(&pk).is_ok());
+        assert_eq!(p.log_id(), pk.log_id());
+        assert_eq!(*p.entry_hash(), entry(i));
+        assert_eq!(p.merge_delay(), 60_000);
+        assert_eq!(p.deadline(), p.timestamp() + 60_000);
+    }
+
+    assert_eq!(smt.commit().unwrap(), 5);
+    assert_eq!(smt.queued(), 0);
+    assert_eq!(smt.head().size(), 8);
+    for (i, p) in (3..8).zip(&promises) {
+        let proof = smt.inclusion_proof(entry(i)).unwrap();
+        assert!(p.verify_honored(&proof, &pk).is_ok());
+    }
+
+    // A proof for another entry does not keep the promise.
+    let other = smt.inclusion_proof(entry(1)).unwrap();
+    assert_eq!(
+        promises[0].verify_honored(&other, &pk),
+        Err(VerificationError::MalformedProof)
+    );
+    // Neither does a proof by another log.
+    let mut ring = KeyRing::new();
+    ring.add(pk);
+    let mut forger = SignedMerkleTree::<Sha256>::new(KeyPair::new().unwrap());
+    forger.extend((3..5).map(entry));
+    let forged = forger.inclusion_proof(entry(3)).unwrap();
+    assert!(promises[0].verify_honored(&forged, &ring).is_err());
+
+    // Entries already in the tree are rejected by default. If the duplicate
+    // policy keeps them where they are, they are promised but not queued
+    // again.
+    let err = smt.submit(entry(4)).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    smt.set_duplicate_policy(DuplicatePolicy::ReturnExisting);
+    let again = smt.submit(entry(4)).unwrap();
+    assert_eq!(smt.queued(), 0);
+    let proof = smt.inclusion_proof(entry(4)).unwrap();
+    assert!(again.verify_honored(&proof, &ring).is_ok());
+    assert_eq!(smt.commit().unwrap(), 0);
+}
+
+#[test]
+fn heads_after_the_deadline_need_the_entry() {
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let mut smt = SignedMerkleTree::<Sha256>::new(kp);
+    smt.set_merge_delay(0);
+    let p = smt.submit(entry(0)).unwrap();
+    thread::sleep(Duration::from_millis(5));
+
+    // The log signs a head without merging the entry.
+    smt.insert(entry(1));
+    let sth = smt.head();
+    assert!(sth.timestamp() >= p.deadline());
+    assert!(smt.inclusion_proof(entry(0)).is_none());
+    let early = smt.inclusion_proof(entry(1)).unwrap();
+    assert_eq!(
+        p.verify_head(&sth, None, &pk),
+        Err(VerificationError::DeadlineMissed)
+    );
+
+    // Merging the entry late still includes it, and later heads prove it.
+    smt.commit().unwrap();
+    let proof = smt.inclusion_proof(entry(0)).unwrap();
+    assert!(p.verify_honored(&proof, &pk).is_ok());
+    let sth = smt.head();
+    assert!(p.verify_head(&sth, Some(&proof), &pk).is_ok());
+    // The proof has to be for the head it is checked with.
+    assert_eq!(
+        p.verify_head(&sth, Some(&early), &pk),
+        Err(VerificationError::MalformedProof)
+    );
+
+    // Heads before the deadline need not include the entry yet.
+    smt.set_merge_delay(60_000);
+    let p = smt.submit(entry(2)).unwrap();
+    assert!(p.verify_head(&smt.head(), None, &pk).is_ok());
+    let other = SignedMerkleTree::<Sha256>::new(KeyPair::new().unwrap());
+    assert!(p.verify_head(&other.head(), None, &pk).is_err());
+}
+
+#[test]
+fn promises_hold_after_later_commits() {
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let mut smt = SignedMerkleTree::<Sha256>::new(kp);
+    let p = smt.submit(entry(0)).unwrap();
+    smt.commit().unwrap();
+    for i in 1..4 {
+        smt.stage_many((i * 10..i * 10 + 5).map(entry));
+        smt.commit().unwrap();
+    }
+    assert_eq!(smt.head().size(), 16);
+    let proof = smt.inclusion_proof(entry(0)).unwrap();
+    assert_eq!(proof.tree_size(), 16);
+    assert!(p.verify_honored(&proof, &pk).is_ok());
+    assert!(p.verify_head(&smt.head(), Some(&proof), &pk).is_ok());
+}
+
+#[test]
+fn staged_entries_are_promised_once() {
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let mut smt = SignedMerkleTree::<Sha256>::new(kp);
+    smt.submit(entry(0)).unwrap();
+    let err = smt.submit(entry(0)).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert!(!smt.stage(entry(0)));
+    assert_eq!(smt.queued(), 1);
+
+    // Entries kept at their existing position are promised again, but
+    // staged once.
+    smt.set_duplicate_policy(DuplicatePolicy::ReturnExisting);
+    let p = smt.submit(entry(0)).unwrap();
+    assert_eq!(smt.queued(), 1);
+    assert_eq!(smt.commit().unwrap(), 1);
+    let proof = smt.inclusion_proof(entry(0)).unwrap();
+    assert!(p.verify_honored(&proof, &pk).is_ok());
+
+    smt.set_duplicate_policy(DuplicatePolicy::Allow);
+    smt.submit(entry(0)).unwrap();
+    smt.submit(entry(0)).unwrap();
+    assert_eq!(smt.queued(), 2);
+    assert_eq!(smt.commit().unwrap(), 2);
+}
+
+#[test]
+fn owning_tree_promises() {
+    let kp = EcdsaKeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let mut somt = SignedOwningMerkleTree::<Vec<u8>, Sha256, _>::new(kp);
+    let p = somt.submit(b"first".to_vec()).unwrap();
+    somt.submit(b"second".to_vec()).unwrap();
+    assert!(somt.submit(b"first".to_vec()).is_err());
+    assert_eq!(somt.queued(), 2);
+    assert_eq!(somt.commit().unwrap(), 2);
+    assert_eq!(somt.get(1).unwrap(), b"second");
+
+    let proof = somt.inclusion_proof(&b"first".to_vec()).unwrap();
+    assert!(p.verify_honored(&proof, &pk).is_ok());
+    let other = EcdsaKeyPair::new().unwrap().pub_key();
+    assert_eq!(p.verify(&other), Err(VerificationError::BadSignature));
+}
+
+#[test]
+fn rotation_commits_staged_entries() {
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let mut smt = SignedMerkleTree::<Sha256>::new(kp);
+    let p = smt.submit(entry(0)).unwrap();
+    let t = smt.rotate_key(KeyPair::new().unwrap()).unwrap().clone();
+    assert_eq!(t.tree_size(), 1);
+    assert_eq!(smt.queued(), 0);
+    assert!(p.verify(&pk).is_ok());
+}
@@ -0,0 +1,200 @@
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::Sha256;
+use merkle_rs::{MerkleTree, TreeHead};
+use merkle_rs::digest::{self, Hash};
+use merkle_rs::proof::{self, VerificationError};
+use merkle_rs::rfc6962;
+
+fn entries(tag: u8, n: u8) -> Vec<Hash<Sha256>> {
+    (0..n)
+        .map(|i| <Sha256 as digest::Digest>::hash_elem(&[tag, i]))
+        .collect()
+}
+
+#[test]
+fn verify_against_trusted_heads() {
+    let entries = entries(0, 13);
+    let leaves: Vec<_> = entries
+        .iter()
+        .map(|e| rfc6962::leaf_hash::<Sha256>(e))
+        .collect();
+
+    let mut mt = MerkleTree::<Sha256>::new();
+    let mut heads = vec![mt.head()];
+    for e in &entries {
+        mt.insert(*e);
+        heads.push(mt.head());
+    }
+    let trusted = mt.head();
+    let other: MerkleTree<Sha256> = self::entries(1, 13).into_iter().collect();
+    let forged = other.head();
+
+    for (i, e) in entries.iter().enumerate() {
+        let p = mt.inclusion_proof(*e).unwrap();
+        assert_eq!(p.index(), i as u64);
+        assert_eq!(p.tree_size(), 13);
+        assert_eq!(*p.leaf_hash(), leaves[i]);
+        assert_eq!(
+            p.audit_path(),
+            &rfc6962::inclusion_path::<Sha256>(i as u64, &leaves).unwrap()[..]
+        );
+
+        assert_eq!(p.verify_against(&trusted), Ok(()));
+        assert_eq!(
+            p.verify_against(&forged),
+            Err(VerificationError::RootMismatch)
+        );
+        assert_eq!(
+            proof::verify_inclusion(
+                p.leaf_hash(),
+                p.index(),
+                p.audit_path(),
+                &trusted
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            proof::verify_inclusion(
+                p.leaf_hash(),
+                p.index(),
+                p.audit_path(),
+                &forged
+            ),
+            Err(VerificationError::RootMismatch)
+        );
+    }
+
+    for m in 0..heads.len() {
+        let p = mt.consistency_proof(m as u64).unwrap();
+        assert_eq!(p.old_size(), m as u64);
+        assert_eq!(p.new_size(), 13);
+        let expected =
+            rfc6962::consistency_proof::<Sha256>(m as u64, &leaves).unwrap();
+        assert_eq!(p.consistency_path(), &expected[..]);
+
+        assert_eq!(p.verify_against(&heads[m], &trusted), Ok(()));
+        assert_eq!(
+            proof::verify_consistency(
+                &heads[m],
+                &trusted,
+                p.consistency_path()
+            ),
+            Ok(())
+        );
+        // Every tree is consistent with the empty tree.
+        if m > 0 {
+            assert!(p.verify_against(&heads[m], &forged).is_err());
+            let bad_old = TreeHead::new(m as u64, *forged.root_hash());
+            assert!(p.verify_against(&bad_old, &trusted).is_err());
+            assert_eq!(
+                p.verify_against(&heads[m - 1], &trusted),
+                Err(VerificationError::MalformedProof)
+            );
+        }
+    }
+}
+
+#[test]
+fn historical_inclusion_proofs() {
+    let entries = entries(2, 37);
+    let leaves: Vec<_> = entries
+        .iter()
+        .map(|e| rfc6962::leaf_hash::<Sha256>(e))
+        .collect();
+
+    let mut mt = MerkleTree::<Sha256>::new();
+    let mut heads = vec![mt.head()];
+    for e in &entries {
+        mt.insert(*e);
+        heads.push(mt.head());
+    }
+
+    for (size, head) in heads.iter().enumerate() {
+        let at = mt.head_at(size as u64).unwrap();
+        assert_eq!(at.size(), head.size());
+        assert_eq!(at.root_hash(), head.root_hash());
+
+        for (i, e) in entries.iter().enumerate() {
+            let p = mt.inclusion_proof_at(*e, size as u64);
+            if i >= size {
+                assert!(p.is_none());
+                continue;
+            }
+            let p = p.unwrap();
+            let expected =
+                rfc6962::inclusion_path::<Sha256>(i as u64, &leaves[..size])
+                    .unwrap();
+            assert_eq!(p.audit_path(), &expected[..]);
+            assert_eq!(p.tree_size(), size as u64);
+            assert_eq!(p.verify(), Ok(()));
+            assert_eq!(p.verify_against(head), Ok(()));
+        }
+    }
+    assert!(mt.head_at(38).is_none());
+    assert!(mt.inclusion_proof_at(entries[0], 38).is_none());
+}
+
+#[test]
+fn historical_consistency_proofs() {
+    let entries = entries(3, 37);
+    let leaves: Vec<_> = entries
+        .iter()
+        .map(|e| rfc6962::leaf_hash::<Sha256>(e))
+        .collect();
+
+    let mut mt = MerkleTree::<Sha256>::new();
+    let mut heads = vec![mt.head()];
+    for e in &entries {
+        mt.insert(*e);
+        heads.push(mt.head());
+    }
+
+    for new_size in 0..heads.len() {
+        for old_size in 0..heads.len() {
+            let p = mt
+                .consistency_proof_between(old_size as u64, new_size as u64);
+            if old_size > new_size {
+                assert!(p.is_none());
+                continue;
+            }
+            let p = p.unwrap();
+            let expected = rfc6962::consistency_proof::<Sha256>(
+                old_size as u64,
+                &leaves[..new_size],
+            ).unwrap();
+            assert_eq!(p.consistency_path(), &expected[..]);
+            assert_eq!(p.new_size(), new_size as u64);
+            assert_eq!(p.verify(heads[old_size].root_hash()), Ok(()));
+            assert_eq!(
+                p.verify_against(&heads[old_size], &heads[new_size]),
+                Ok(())
+            );
+        }
+    }
+    assert!(mt.consistency_proof_between(0, 38).is_none());
+}
+
+#[test]
+fn inclusion_proofs_by_index() {
+    let entries = entries(4, 21);
+    let mt: MerkleTree<Sha256> = entries.iter().cloned().collect();
+
+    for (i, e) in entries.iter().enumerate() {
+        let by_hash = mt.inclusion_proof(*e).unwrap();
+        let by_index = mt.inclusion_proof_by_index(i as u64).unwrap();
+        assert_eq!(
+            mt.leaf_hash(i as u64),
+            Some(rfc6962::leaf_hash::<Sha256>(e))
+        );
+        assert_eq!(by_index.leaf_hash(), by_hash.leaf_hash());
+        assert_eq!(by_index.index(), i as u64);
+        assert_eq!(by_index.audit_path(), by_hash.audit_path());
+        assert_eq!(by_index.verify_against(&mt.head()), Ok(()));
+    }
+    assert!(mt.leaf_hash(21).is_none());
+    assert!(mt.inclusion_proof_by_index(21).is_none());
+}
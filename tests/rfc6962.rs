@@ -0,0 +1,296 @@
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::Sha256;
+use merkle_rs::MerkleTree;
+use merkle_rs::digest::{self, Hash};
+use merkle_rs::proof::VerificationError;
+use merkle_rs::rfc6962;
+
+// Test vectors from the Certificate Transparency reference implementation.
+const LEAVES: &[&str] = &[
+    "",
+    "00",
+    "10",
+    "2021",
+    "3031",
+    "40414243",
+    "5051525354555657",
+    "606162636465666768696a6b6c6d6e6f",
+];
+
+const ROOTS: &[&str] = &[
+    "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+    "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+    "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+    "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+    "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+    "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+    "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+];
+
+// (leaf index, tree size, audit path)
+const INCLUSION_PROOFS: &[(u64, u64, &[&str])] = &[
+    (0, 1, &[]),
+    (
+        0,
+        8,
+        &[
+            "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+            "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+            "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+        ],
+    ),
+    (
+        5,
+        8,
+        &[
+            "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        ],
+    ),
+    (
+        2,
+        3,
+        &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"],
+    ),
+    (
+        1,
+        5,
+        &[
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+            "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+        ],
+    ),
+];
+
+// (old size, new size, consistency proof)
+const CONSISTENCY_PROOFS: &[(u64, u64, &[&str])] = &[
+    (1, 1, &[]),
+    (
+        1,
+        8,
+        &[
+            "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+            "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+            "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+        ],
+    ),
+    (
+        6,
+        8,
+        &[
+            "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+            "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        ],
+    ),
+    (
+        2,
+        5,
+        &[
+            "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+            "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+        ],
+    ),
+];
+
+fn unhex(s: &str) -> Vec<u8> {
+    (0..s.len() / 2)
+        .map(|i| u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap())
+        .collect()
+}
+
+fn hash(s: &str) -> Hash<Sha256> {
+    Hash::<Sha256>::clone_from_slice(&unhex(s))
+}
+
+fn hashes(v: &[&str]) -> Vec<Hash<Sha256>> {
+    v.iter().map(|s| hash(s)).collect()
+}
+
+fn leaves() -> Vec<Hash<Sha256>> {
+    LEAVES
+        .iter()
+        .map(|l| rfc6962::leaf_hash::<Sha256>(&unhex(l)))
+        .collect()
+}
+
+#[test]
+fn known_roots() {
+    let leaves = leaves();
+    assert_eq!(
+        rfc6962::root::<Sha256>(&[]),
+        hash("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+    );
+    for (i, r) in ROOTS.iter().enumerate() {
+        assert_eq!(rfc6962::root::<Sha256>(&leaves[..i + 1]), hash(r));
+    }
+}
+
+#[test]
+fn known_inclusion_proofs() {
+    let leaves = leaves();
+    for &(index, size, path) in INCLUSION_PROOFS {
+        let path = hashes(path);
+        let leaves = &leaves[..size as usize];
+        let root = hash(ROOTS[size as usize - 1]);
+        let leaf = &leaves[index as usize];
+        let verify = |index, path: &[Hash<Sha256>]| {
+            rfc6962::verify_inclusion::<Sha256>(leaf, index, size, path, &root)
+        };
+
+        assert_eq!(
+            rfc6962::inclusion_path::<Sha256>(index, leaves),
+            Some(path.clone())
+        );
+        assert_eq!(verify(index, &path), Ok(()));
+
+        assert!(verify(index + 1, &path).is_err());
+        assert_eq!(
+            verify(size, &path),
+            Err(VerificationError::IndexOutOfRange)
+        );
+        if !path.is_empty() {
+            assert_eq!(
+                verify(index, &path[..path.len() - 1]),
+                Err(VerificationError::WrongPathLength)
+            );
+            let mut wrong = path.clone();
+            wrong[0] = *leaf;
+            assert_eq!(
+                verify(index, &wrong),
+                Err(VerificationError::RootMismatch)
+            );
+        }
+        let mut extended = path.clone();
+        extended.push(root);
+        assert_eq!(
+            verify(index, &extended),
+            Err(VerificationError::WrongPathLength)
+        );
+    }
+    assert!(rfc6962::inclusion_path::<Sha256>(8, &leaves).is_none());
+}
+
+#[test]
+fn known_consistency_proofs() {
+    let leaves = leaves();
+    for &(old_size, new_size, proof) in CONSISTENCY_PROOFS {
+        let proof = hashes(proof);
+        let old_root = hash(ROOTS[old_size as usize - 1]);
+        let new_root = hash(ROOTS[new_size as usize - 1]);
+        let verify = |old_size, old_root, new_root, proof: &[Hash<Sha256>]| {
+            rfc6962::verify_consistency::<Sha256>(
+                old_size, new_size, old_root, new_root, proof,
+            )
+        };
+
+        assert_eq!(
+            rfc6962::consistency_proof::<Sha256>(
+                old_size,
+                &leaves[..new_size as usize]
+            ),
+            Some(proof.clone())
+        );
+        assert_eq!(verify(old_size, &old_root, &new_root, &proof), Ok(()));
+
+        if old_size != new_size {
+            // For power of two sizes the old root is part of the path.
+            let wrong_old = verify(old_size, &new_root, &new_root, &proof);
+            if old_size.is_power_of_two() {
+                assert!(wrong_old.is_err());
+            } else {
+                assert_eq!(wrong_old, Err(VerificationError::OldRootMismatch));
+            }
+            assert_eq!(
+                verify(old_size, &old_root, &old_root, &proof),
+                Err(VerificationError::RootMismatch)
+            );
+            assert_eq!(
+                verify(old_size, &old_root, &new_root, &proof[1..]),
+                Err(VerificationError::WrongPathLength)
+            );
+            assert!(
+                verify(old_size + 1, &old_root, &new_root, &proof).is_err()
+            );
+        }
+        assert_eq!(
+            verify(new_size + 1, &old_root, &new_root, &proof),
+            Err(VerificationError::IndexOutOfRange)
+        );
+    }
+}
+
+#[test]
+fn garbage_proofs_do_not_panic() {
+    let leaves = leaves();
+    let sizes = [0, 1, 2, 3, 7, 8, 9, u64::MAX - 1, u64::MAX];
+    for len in 0..70 {
+        let path: Vec<_> = leaves.iter().cloned().cycle().take(len).collect();
+        for &a in &sizes {
+            for &b in &sizes {
+                let _ = rfc6962::verify_inclusion::<Sha256>(
+                    &leaves[0], a, b, &path, &leaves[1],
+                );
+                let _ = rfc6962::verify_consistency::<Sha256>(
+                    a, b, &leaves[0], &leaves[1], &path,
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn merkle_tree_interop() {
+    let entries: Vec<Hash<Sha256>> = (0u8..20)
+        .map(|i| <Sha256 as digest::Digest>::hash_elem(&[i]))
+        .collect();
+    let leaves: Vec<Hash<Sha256>> =
+        entries.iter().map(|e| rfc6962::leaf_hash::<Sha256>(e)).collect();
+
+    let mut mt = MerkleTree::<Sha256>::new();
+    let mut roots = vec![*mt.head().root_hash()];
+    for (n, e) in entries.iter().enumerate() {
+        mt.insert(*e);
+        roots.push(*mt.head().root_hash());
+        assert_eq!(roots[n + 1], rfc6962::root::<Sha256>(&leaves[..n + 1]));
+    }
+
+    let size = leaves.len() as u64;
+    for i in 0..size {
+        let path = rfc6962::inclusion_path::<Sha256>(i, &leaves).unwrap();
+        assert_eq!(
+            rfc6962::verify_inclusion::<Sha256>(
+                &leaves[i as usize],
+                i,
+                size,
+                &path,
+                &roots[size as usize]
+            ),
+            Ok(())
+        );
+    }
+    for m in 0..size + 1 {
+        let proof = rfc6962::consistency_proof::<Sha256>(m, &leaves).unwrap();
+        assert_eq!(
+            rfc6962::verify_consistency::<Sha256>(
+                m,
+                size,
+                &roots[m as usize],
+                &roots[size as usize],
+                &proof
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            mt.consistency_proof(m).unwrap().verify(&roots[m as usize]),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,134 @@
+#![cfg(feature = "ring")]
+
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::{Sha256, entry};
+use merkle_rs::proof::VerificationError;
+use merkle_rs::rotation::KeyHistory;
+use merkle_rs::signer::{LogKey, Verifier};
+use merkle_rs::{EcdsaKeyPair, KeyPair, SignedMerkleTree};
+
+#[test]
+fn rotated_keys_are_followed() {
+    let first = KeyPair::new().unwrap();
+    let second = KeyPair::new().unwrap();
+    let third = KeyPair::new().unwrap();
+    let (pk1, pk2, pk3) = (first.pub_key(), second.pub_key(), third.pub_key());
+    let mut history = KeyHistory::new(&pk1);
+
+    let mut smt = SignedMerkleTree::<Sha256>::new(first);
+    smt.extend((0..4).map(entry));
+    let old = smt.head();
+    let t = smt.rotate_key(second).unwrap().clone();
+    assert_eq!(t.tree_size(), 4);
+    assert!(t.verify(&pk1).is_ok());
+    assert_eq!(t.verify(&pk2), Err(VerificationError::BadSignature));
+    let at_rotation = smt.head();
+    assert_eq!(at_rotation.size(), 4);
+    assert!(at_rotation.verify(&pk2).is_ok());
+
+    smt.extend((4..9).map(entry));
+    let mid = smt.head();
+    smt.rotate_key(third).unwrap();
+    smt.insert(entry(9));
+    let new = smt.head();
+    assert!(new.verify(&pk3).is_ok());
+    assert_eq!(smt.key_transitions().len(), 2);
+
+    // Heads after the rotation are only accepted once the client follows
+    // the transitions.
+    assert!(history.verify(&old).is_ok());
+    assert_eq!(history.verify(&mid), Err(VerificationError::BadSignature));
+    for t in smt.key_transitions() {
+        history.add_transition(t.clone()).unwrap();
+    }
+    assert!(history.current() == &LogKey::from_verifier(&pk3));
+    for sth in &[&old, &at_rotation, &mid, &new] {
+        assert!(history.verify(sth).is_ok());
+    }
+    let p = smt.inclusion_proof(entry(2)).unwrap();
+    let sth = p.signed_tree_head();
+    let key = history.key_for(&sth.log_id(), sth.size()).unwrap();
+    assert!(p.verify(key).is_ok());
+
+    // Retired keys do not sign heads beyond their transition.
+    let mut stale = SignedMerkleTree::<Sha256>::new(KeyPair::new().unwrap());
+    stale.extend((0..6).map(entry));
+    let mut history = KeyHistory::new(&pk1);
+    history.add_transition(t.clone()).unwrap();
+    assert!(history.key_for(&pk1.log_id(), 4).is_some());
+    assert!(history.key_for(&pk1.log_id(), 5).is_none());
+    assert!(history.key_for(&pk2.log_id(), 3).is_none());
+    assert!(history.key_for(&pk2.log_id(), 100).is_some());
+    assert!(history.verify(&stale.head()).is_err());
+}
+
+#[test]
+fn transitions_must_chain() {
+    let first = KeyPair::new().unwrap();
+    let pk1 = first.pub_key();
+    let mut smt = SignedMerkleTree::<Sha256>::new(first);
+    smt.extend((0..3).map(entry));
+    smt.rotate_key(KeyPair::new().unwrap()).unwrap();
+    smt.insert(entry(3));
+    smt.rotate_key(KeyPair::new().unwrap()).unwrap();
+    let ts = smt.key_transitions().to_vec();
+
+    // Skipping a transition leaves the history at the wrong key.
+    let mut history = KeyHistory::new(&pk1);
+    assert_eq!(
+        history.add_transition(ts[1].clone()),
+        Err(VerificationError::BadSignature)
+    );
+    assert!(history.transitions().is_empty());
+    history.add_transition(ts[0].clone()).unwrap();
+    history.add_transition(ts[1].clone()).unwrap();
+    assert_eq!(history.transitions().len(), 2);
+}
+
+#[test]
+fn transitions_must_not_go_back() {
+    let first = KeyPair::new().unwrap();
+    let pk1 = first.pub_key();
+    let second = KeyPair::new().unwrap();
+    let seed = second.to_seed();
+    let mut smt = SignedMerkleTree::<Sha256>::new(first);
+    smt.extend((0..5).map(entry));
+    smt.rotate_key(second).unwrap();
+
+    // The second key also signs a transition at a smaller tree size.
+    let mut other =
+        SignedMerkleTree::<Sha256>::new(KeyPair::from_seed(&seed).unwrap());
+    other.extend((0..2).map(entry));
+    other.rotate_key(KeyPair::new().unwrap()).unwrap();
+
+    let mut history = KeyHistory::new(&pk1);
+    history.add_transition(smt.key_transitions()[0].clone()).unwrap();
+    assert_eq!(
+        history.add_transition(other.key_transitions()[0].clone()),
+        Err(VerificationError::TransitionOutOfOrder)
+    );
+    assert_eq!(history.transitions().len(), 1);
+}
+
+#[test]
+fn rotation_across_algorithms() {
+    let ec = EcdsaKeyPair::new().unwrap();
+    let ec_pk = ec.pub_key();
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(ec);
+    smt.extend((0..5).map(entry));
+    let old = smt.head();
+    let next = EcdsaKeyPair::new().unwrap();
+    let next_pk = next.pub_key();
+    smt.rotate_key(next).unwrap();
+    smt.insert(entry(5));
+
+    let mut history = KeyHistory::new(&ec_pk);
+    history.add_transition(smt.key_transitions()[0].clone()).unwrap();
+    assert!(history.verify(&old).is_ok());
+    assert!(history.verify(&smt.head()).is_ok());
+    assert_eq!(history.current().key_bytes(), next_pk.key_bytes());
+}
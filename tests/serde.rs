@@ -4,12 +4,20 @@
 extern crate byteorder;
 extern crate merkle_rs;
 extern crate rmp_serde;
+#[macro_use]
 extern crate serde;
 extern crate sha2;
 
 use byteorder::{BigEndian, ByteOrder};
-use merkle_rs::{digest, KeyPair, MerkleTree};
+use merkle_rs::{digest, KeyPair, MerkleTree, SignedMerkleTree};
+use merkle_rs::{CompactMerkleTree, Cosignature, DuplicatePolicy};
+use merkle_rs::{EcdsaKeyPair, EncryptedKeyPair};
+use merkle_rs::SignedTreeHead;
+use merkle_rs::keyring::LogId;
+use merkle_rs::proof::VerificationError;
+use merkle_rs::signer::SignatureAlgorithm;
 use serde::Serialize;
+use std::collections::HashMap;
 
 #[test]
 fn keypair_serde() {
@@ -27,11 +35,26 @@ fn keypair_serde() {
     assert!(kp.pub_key() == x.pub_key());
 }
 
+#[test]
+fn ecdsa_keypair_serde() {
+    let kp = EcdsaKeyPair::new().unwrap();
+    let mut buf = Vec::new();
+    kp.serialize(&mut rmp_serde::Serializer::new(&mut buf))
+        .unwrap();
+    let mut de = rmp_serde::Deserializer::new(&buf[..]);
+    let x: EcdsaKeyPair = serde::Deserialize::deserialize(&mut de).unwrap();
+    assert!(kp.pub_key() == x.pub_key());
+
+    let mut smt = SignedMerkleTree::<sha2::Sha256, _>::new(x);
+    smt.insert(<sha2::Sha256 as digest::Digest>::hash_elem(&A(1)));
+    assert!(smt.head().verify(&kp.pub_key()).is_ok());
+}
+
 #[test]
 fn tree_serde() {
     let mut mt = MerkleTree::<sha2::Sha256>::new();
     let hash = <sha2::Sha256 as digest::Digest>::hash_elem(&A(1));
-    mt.insert(hash.clone());
+    mt.insert(hash);
     let mut buf = Vec::new();
     mt.serialize(&mut rmp_serde::Serializer::new(&mut buf))
         .unwrap();
@@ -44,13 +67,204 @@ fn tree_serde() {
         .unwrap();
     assert!(buf == buf2);
     assert!(x.inclusion_proof(hash).is_some());
+
+    mt.set_duplicate_policy(DuplicatePolicy::Allow);
+    mt.insert(hash);
+    let mut buf = Vec::new();
+    mt.serialize(&mut rmp_serde::Serializer::new(&mut buf))
+        .unwrap();
+    let mut de = rmp_serde::Deserializer::new(&buf[..]);
+    let x: MerkleTree<sha2::Sha256> =
+        serde::Deserialize::deserialize(&mut de).unwrap();
+    assert_eq!(x.duplicate_policy(), DuplicatePolicy::Allow);
+    assert_eq!(x.indices_of(hash), vec![0, 1]);
+}
+
+// The serialized form of a `MerkleTree`, with its parts open to tampering.
+#[derive(Clone, Serialize, Deserialize)]
+struct RawTree {
+    storage: RawStorage,
+    policy: DuplicatePolicy,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct RawStorage {
+    entries: Vec<[u8; 32]>,
+    map: HashMap<[u8; 32], u64>,
+    levels: Vec<Vec<[u8; 32]>>,
+    duplicates: HashMap<[u8; 32], Vec<u64>>,
+}
+
+#[test]
+fn tree_serde_checks_storage() {
+    let load = |raw: &RawTree| {
+        let mut buf = Vec::new();
+        raw.serialize(&mut rmp_serde::Serializer::new(&mut buf))
+            .unwrap();
+        let mut de = rmp_serde::Deserializer::new(&buf[..]);
+        let x: Result<MerkleTree<sha2::Sha256>, _> =
+            serde::Deserialize::deserialize(&mut de);
+        x
+    };
+    let mut mt: MerkleTree<sha2::Sha256> = (0..6)
+        .map(|i| <sha2::Sha256 as digest::Digest>::hash_elem(&A(i)))
+        .collect();
+    mt.set_duplicate_policy(DuplicatePolicy::Allow);
+    mt.insert(<sha2::Sha256 as digest::Digest>::hash_elem(&A(2)));
+    let mut buf = Vec::new();
+    mt.serialize(&mut rmp_serde::Serializer::new(&mut buf))
+        .unwrap();
+    let mut de = rmp_serde::Deserializer::new(&buf[..]);
+    let raw: RawTree = serde::Deserialize::deserialize(&mut de).unwrap();
+    let x = load(&raw).unwrap();
+    assert_eq!(x.head().root_hash(), mt.head().root_hash());
+    assert_eq!(x.indices_of(*mt.get(2).unwrap()), vec![2, 6]);
+
+    let mut bad = raw.clone();
+    bad.storage.entries.pop();
+    assert!(load(&bad).is_err());
+    let mut bad = raw.clone();
+    bad.storage.levels[1].pop();
+    assert!(load(&bad).is_err());
+    let mut bad = raw.clone();
+    bad.storage.levels.push(vec![[0; 32]]);
+    assert!(load(&bad).is_err());
+    let mut bad = raw.clone();
+    *bad.storage.map.values_mut().next().unwrap() = 9;
+    assert!(load(&bad).is_err());
+    let mut bad = raw.clone();
+    bad.storage.map.clear();
+    assert!(load(&bad).is_err());
+    let mut bad = raw.clone();
+    bad.storage.duplicates.values_mut().next().unwrap()[0] = 4;
+    assert!(load(&bad).is_err());
+    let mut bad = raw;
+    bad.storage.duplicates.clear();
+    assert!(load(&bad).is_err());
+}
+
+#[test]
+fn compact_tree_serde() {
+    let cmt: CompactMerkleTree<sha2::Sha256> = (0..11)
+        .map(|i| <sha2::Sha256 as digest::Digest>::hash_elem(&A(i)))
+        .collect();
+    let mut buf = Vec::new();
+    cmt.serialize(&mut rmp_serde::Serializer::new(&mut buf))
+        .unwrap();
+    let mut de = rmp_serde::Deserializer::new(&buf[..]);
+    let x: CompactMerkleTree<sha2::Sha256> =
+        serde::Deserialize::deserialize(&mut de).unwrap();
+    assert_eq!(x.len(), 11);
+    assert_eq!(x.head().root_hash(), cmt.head().root_hash());
+
+    // A size of 12 needs two frontier hashes, not three.
+    let mut buf = Vec::new();
+    (12u64, cmt.frontier().to_vec())
+        .serialize(&mut rmp_serde::Serializer::new(&mut buf))
+        .unwrap();
+    let mut de = rmp_serde::Deserializer::new(&buf[..]);
+    let x: Result<CompactMerkleTree<sha2::Sha256>, _> =
+        serde::Deserialize::deserialize(&mut de);
+    assert!(x.is_err());
+}
+
+#[test]
+fn signed_tree_head_covers_size() {
+    #[derive(Serialize, Deserialize)]
+    struct RawTreeHead {
+        count: u64,
+        hash: Vec<u8>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct RawSignedTreeHead {
+        th: RawTreeHead,
+        version: u8,
+        timestamp: u64,
+        log_id: LogId,
+        sig: Vec<u8>,
+        sig_alg: SignatureAlgorithm,
+        cosigs: Vec<Cosignature>,
+    }
+
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let mut smt = SignedMerkleTree::<sha2::Sha256>::new(kp);
+    smt.extend(
+        (0..3).map(|i| <sha2::Sha256 as digest::Digest>::hash_elem(&A(i))),
+    );
+    let sth = smt.head();
+    assert!(sth.verify(&pk).is_ok());
+
+    let mut buf = Vec::new();
+    sth.serialize(&mut rmp_serde::Serializer::new(&mut buf))
+        .unwrap();
+    let mut de = rmp_serde::Deserializer::new(&buf[..]);
+    let mut raw: RawSignedTreeHead =
+        serde::Deserialize::deserialize(&mut de).unwrap();
+
+    raw.th.count = 2;
+    let mut buf = Vec::new();
+    raw.serialize(&mut rmp_serde::Serializer::new(&mut buf))
+        .unwrap();
+    let mut de = rmp_serde::Deserializer::new(&buf[..]);
+    let forged: SignedTreeHead<sha2::Sha256> =
+        serde::Deserialize::deserialize(&mut de).unwrap();
+    assert_eq!(forged.size(), 2);
+    assert_eq!(forged.verify(&pk), Err(VerificationError::BadSignature));
+}
+
+#[test]
+fn signed_tree_without_signer() {
+    let kp = KeyPair::new().unwrap();
+    let enc = kp.encrypt(b"password").unwrap();
+    let mut smt = SignedMerkleTree::<sha2::Sha256>::new(kp);
+    smt.extend(
+        (0..5).map(|i| <sha2::Sha256 as digest::Digest>::hash_elem(&A(i))),
+    );
+    smt.submit(<sha2::Sha256 as digest::Digest>::hash_elem(&A(7)))
+        .unwrap();
+    let mut buf = Vec::new();
+    smt.serialize_without_signer(&mut rmp_serde::Serializer::new(&mut buf))
+        .unwrap();
+    let seed = enc.decrypt(b"password").unwrap().to_seed();
+    assert!(!buf.windows(32).any(|w| w == seed));
+
+    // The key comes back from its encrypted container.
+    let mut buf2 = Vec::new();
+    enc.serialize(&mut rmp_serde::Serializer::new(&mut buf2))
+        .unwrap();
+    let mut de = rmp_serde::Deserializer::new(&buf2[..]);
+    let enc: EncryptedKeyPair =
+        serde::Deserialize::deserialize(&mut de).unwrap();
+    let kp = enc.decrypt(b"password").unwrap();
+    let pk = kp.pub_key();
+    let mut de = rmp_serde::Deserializer::new(&buf[..]);
+    let mut x =
+        SignedMerkleTree::<sha2::Sha256>::deserialize_with_signer(&mut de, kp)
+            .unwrap();
+    assert_eq!(x.len(), 5);
+    assert_eq!(x.head().root_hash(), smt.head().root_hash());
+    // Promised entries are not lost, nor staged twice.
+    assert_eq!(x.queued(), 1);
+    assert!(!x.stage(<sha2::Sha256 as digest::Digest>::hash_elem(&A(7))));
+    x.insert(<sha2::Sha256 as digest::Digest>::hash_elem(&A(5)));
+    assert_eq!(x.head().size(), 6);
+    assert!(x.head().verify(&pk).is_ok());
+
+    // Trees only accept the key that signed them.
+    let mut de = rmp_serde::Deserializer::new(&buf[..]);
+    assert!(SignedMerkleTree::<sha2::Sha256>::deserialize_with_signer(
+        &mut de,
+        KeyPair::new().unwrap(),
+    ).is_err());
 }
 
 #[derive(Hash, Eq, PartialEq)]
 struct A(usize);
 
 impl digest::Digestible for A {
-    fn hash_bytes(&self, digest: &mut digest::Input) {
+    fn hash_bytes(&self, digest: &mut dyn digest::Input) {
         let mut b = [0; 8];
         BigEndian::write_u64(&mut b, self.0 as u64);
         digest.process(&b)
@@ -0,0 +1,350 @@
+#![cfg(feature = "ring")]
+
+extern crate base64;
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use common::{Sha256, entry};
+use merkle_rs::digest::Hash;
+use merkle_rs::proof::VerificationError;
+use merkle_rs::signer::{LocalSigner, LogKey, PendingSignature,
+                        SignatureAlgorithm, Signer, TreeHeadSigner, Verifier};
+use merkle_rs::{EcdsaKeyPair, EcdsaPubKey, KeyPair, PubKey, RsaPssPubKey,
+                SignedMerkleTree};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{io, thread};
+
+// A P-256 key written by `openssl pkcs8 -topk8`, and its public key.
+const P256_PKCS8: &str = "\
+MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQg5MT7f9OqU5Esx+hp8T9hoMjW\
+e4fBnuZDwEzT3sTjI5ihRANCAAQc0Mf+YSXsN9BZpCSNeeQaXt49UuU3wliIEcAY6TYINqgC\
+KpMnS2X0U9S0qFio918YgbAJmHaKCSPgX7hR0YEo";
+const P256_POINT: &str = "\
+BBzQx/5hJew30FmkJI155Bpe3j1S5TfCWIgRwBjpNgg2qAIqkydLZfRT1LSoWKj3XxiBsAmY\
+dooJI+BfuFHRgSg=";
+// A 2048 bit RSA key, the base64 SHA-256 of its SubjectPublicKeyInfo and
+// its RSA-PSS signature of `merkle-rs`, made with Python's cryptography.
+const RSA_SPKI: &str = "\
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAmFQ9y2n6Kfl8MhubEcQwlyl9W+xn\
+SYEFrUxomElwHOvIucumkIbELtfHIDhNSjuryqiW6qQr+xsP/O64W9FOdCJPZqvXcyhSFtq+\
+B9l5oJrXlLhlU/rxQewvf2+9P5Yb7BO51EE+za9rpEkzeE4UQO5rC39ByjXj9uVjmA3xGGZQ\
+H4k42OccQbAo+SpvhF9SvzJK8dhNt8SR03WSxL0KFhZJId1cCfKako2YWGLHMUKfoOC05o6p\
+1RaJTLcNm9MjRh7XXXk8BtP1l54Arhpdl8S3pkrKch/AC7A2up59Bha1luWlO86WnSCZ4oay\
+yBMyY7PTPwf6UoXeKjCnvwSw/QIDAQAB";
+const RSA_LOG_ID: &str = "9Ym8SMwdZrvH4l8Ac8LsK7ypXAgGkPMgGOolqOcA49c=";
+const RSA_PSS_SIG: &str = "\
+hzjikw3ewnpe/bdlK9/Z7IXoe1jW5WFR5O3XJmL/DocKSMKqJJG+JNUhwoverDvkXP11LidC\
+TRN8kH5tQJg+lpHKUcmuE9hWF7CxMypaEW55CfzuP/TtzMBT4M24HupJvOr8Qp60dtTfOCsn\
+Kj5z/3pmE8tzN6+vo3vVt/f34cnRl70StG7mNxINTgu9OZ00cyy5xsQHlkF9U2jAEhwDujMM\
+/DWXy8+pAfqcxKg6l5eWQnzEygDArM7meO57lS6QxzxqcIuHk86VfSSrjaTV0XhI9VfXGuvh\
+JdIfctwxuXnuEbLxu8URy6F5p+MOQE3n+XAgsB3FGhTT/+Hxpybc3A==";
+
+#[test]
+fn ecdsa_signed_tree() {
+    let kp = EcdsaKeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    assert_eq!(pk.algorithm(), SignatureAlgorithm::ECDSA_P256_SHA256);
+    assert_eq!(EcdsaPubKey::from_point(pk.key_bytes()), Some(pk.clone()));
+
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(kp);
+    smt.extend((0..7).map(entry));
+    let sth = smt.head();
+    assert_eq!(sth.algorithm(), SignatureAlgorithm::ECDSA_P256_SHA256);
+    assert_eq!(sth.log_id(), pk.log_id());
+    assert!(sth.verify(&pk).is_ok());
+
+    let p = smt.inclusion_proof(entry(3)).unwrap();
+    assert!(p.verify(&pk).is_ok());
+    let old = smt.consistency_proof_between(0, 4).unwrap();
+    let p = smt.consistency_proof(4).unwrap();
+    assert!(p.verify(old.tree_head().root_hash(), &pk).is_ok());
+    let p = smt.consistency_proof(0).unwrap();
+    let empty = smt.consistency_proof_between(0, 0).unwrap();
+    assert!(p.verify(empty.tree_head().root_hash(), &pk).is_ok());
+
+    let other = EcdsaKeyPair::new().unwrap().pub_key();
+    assert_eq!(sth.verify(&other), Err(VerificationError::BadSignature));
+}
+
+#[test]
+fn algorithms_are_not_mixed_up() {
+    let ed = KeyPair::new().unwrap();
+    let ec = EcdsaKeyPair::new().unwrap();
+    assert_eq!(ed.public_key().algorithm(), SignatureAlgorithm::ED25519);
+
+    let msg = b"tree head";
+    let sig = ec.sign_message(msg).unwrap();
+    assert!(ec.public_key().verify_message(msg, &sig));
+    assert!(!ec.public_key().verify_message(b"other", &sig));
+    assert!(!ed.public_key().verify_message(msg, &sig));
+
+    let (ed_pk, ec_pk) = (ed.pub_key(), ec.pub_key());
+    let ed_sth = SignedMerkleTree::<Sha256>::new(ed).head();
+    let ec_sth = SignedMerkleTree::<Sha256, _>::new(ec).head();
+    assert!(ed_sth.verify(&ed_pk).is_ok());
+    assert_eq!(ed_sth.verify(&ec_pk), Err(VerificationError::BadSignature));
+    assert_eq!(ec_sth.verify(&ed_pk), Err(VerificationError::BadSignature));
+}
+
+#[test]
+fn bad_ecdsa_keys() {
+    let kp = EcdsaKeyPair::new().unwrap();
+    assert!(EcdsaPubKey::from_point(&kp.pub_key().key_bytes()[1..]).is_none());
+    let mut point = kp.pub_key().key_bytes().to_vec();
+    point[0] = 2;
+    assert!(EcdsaPubKey::from_point(&point).is_none());
+
+    assert!(EcdsaKeyPair::from_pkcs8(&[]).is_err());
+    assert!(EcdsaKeyPair::from_pkcs8(&[0x30; 138]).is_err());
+}
+
+#[test]
+fn ecdsa_keys_from_pkcs8() {
+    let pkcs8 = BASE64.decode(P256_PKCS8).unwrap();
+    let kp = EcdsaKeyPair::from_pkcs8(&pkcs8).unwrap();
+    let point = BASE64.decode(P256_POINT).unwrap();
+    assert_eq!(kp.pub_key().key_bytes(), &point[..]);
+    let sig = kp.sign_message(b"tree head").unwrap();
+    assert!(kp.pub_key().verify_message(b"tree head", &sig));
+    assert!(EcdsaKeyPair::from_pkcs8(&pkcs8[..pkcs8.len() - 1]).is_err());
+}
+
+#[test]
+fn rsa_pss_signatures() {
+    let spki = BASE64.decode(RSA_SPKI).unwrap();
+    let sig = BASE64.decode(RSA_PSS_SIG).unwrap();
+    let pk = RsaPssPubKey::from_spki_der(&spki).unwrap();
+    assert_eq!(pk.algorithm(), SignatureAlgorithm::RSA_PSS_RSAE_SHA256);
+    assert_eq!(pk.to_spki_der(), spki);
+    assert_eq!(pk.log_id().to_string(), RSA_LOG_ID);
+    assert_eq!(RsaPssPubKey::from_pkcs1_der(pk.key_bytes()), Some(pk.clone()));
+    assert!(pk.verify_message(b"merkle-rs", &sig));
+    assert!(!pk.verify_message(b"merkle-rs!", &sig));
+
+    // Log keys of the algorithm verify the same way.
+    let key = LogKey::from_verifier(&pk);
+    assert_eq!(key.to_spki_der(), spki);
+    assert!(key.verify_message(b"merkle-rs", &sig));
+    let key = LogKey::new(SignatureAlgorithm::ED25519, pk.key_bytes());
+    assert!(!key.verify_message(b"merkle-rs", &sig));
+
+    assert!(RsaPssPubKey::from_spki_der(&spki[1..]).is_none());
+    let ec = EcdsaKeyPair::new().unwrap().pub_key();
+    assert!(RsaPssPubKey::from_spki_der(&ec.to_spki_der()).is_none());
+    assert!(RsaPssPubKey::from_pkcs1_der(&spki).is_none());
+}
+
+#[test]
+fn deferred_signing() {
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let signer = LocalSigner::new(kp);
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(signer.clone());
+    assert!(smt.head().verify(&pk).is_ok());
+
+    signer.hold(true);
+    assert!(smt.insert(entry(0)));
+    assert!(smt.insert(entry(1)));
+    assert_eq!(signer.queued(), 2);
+    assert_eq!(smt.len(), 2);
+    assert_eq!(smt.head().size(), 0);
+    assert!(smt.inclusion_proof(entry(0)).is_none());
+    assert!(!smt.poll_head().unwrap());
+
+    // Only the latest request matters.
+    assert_eq!(signer.sign_queued(), 2);
+    assert!(smt.poll_head().unwrap());
+    assert_eq!(smt.head().size(), 2);
+    assert!(smt.head().verify(&pk).is_ok());
+    assert!(smt.inclusion_proof(entry(1)).unwrap().verify(&pk).is_ok());
+    assert!(smt.poll_head().unwrap());
+}
+
+#[test]
+fn failed_signing() {
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let signer = LocalSigner::new(kp);
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(signer.clone());
+    smt.insert(entry(0));
+
+    signer.hold(true);
+    assert!(smt.try_insert(entry(1)).unwrap());
+    signer.fail_queued(io::ErrorKind::TimedOut);
+    let err = smt.poll_head().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(smt.head().size(), 1);
+    assert!(!smt.poll_head().unwrap());
+
+    // Nothing answers a held signer while the tree waits for it.
+    let err = smt.wait_for_head().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    assert_eq!(smt.head().size(), 1);
+    signer.hold(false);
+    smt.wait_for_head().unwrap();
+    assert_eq!(smt.head().size(), 2);
+    assert!(smt.head().verify(&pk).is_ok());
+}
+
+// Signs every request on a thread of its own, like a remote signer.
+#[derive(Clone)]
+struct RemoteSigner(Arc<KeyPair>);
+
+impl TreeHeadSigner for RemoteSigner {
+    type Key = PubKey;
+
+    fn verifier(&self) -> PubKey {
+        self.0.pub_key()
+    }
+
+    fn sign_tree_head(&self, input: &[u8]) -> PendingSignature {
+        let (tx, pending) = PendingSignature::channel();
+        let (key, input) = (self.0.clone(), input.to_vec());
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(5));
+            tx.send(key.sign_message(&input).map_err(io::Error::from));
+        });
+        pending
+    }
+}
+
+#[test]
+fn waiting_for_signers() {
+    let signer = RemoteSigner(Arc::new(KeyPair::new().unwrap()));
+    let pk = signer.verifier();
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(signer);
+    smt.extend((0..3).map(entry));
+    assert_eq!(smt.head().size(), 0);
+    smt.wait_for_head().unwrap();
+    assert_eq!(smt.head().size(), 3);
+    assert!(smt.head().verify(&pk).is_ok());
+    let promise = smt.submit(entry(3)).unwrap();
+    assert!(promise.verify(&pk).is_ok());
+    let cp = smt.checkpoint("example.com/log").unwrap();
+    assert!(cp.verify("example.com/log", &pk).is_ok());
+    let new = RemoteSigner(Arc::new(KeyPair::new().unwrap()));
+    let new_pk = new.verifier();
+    smt.rotate_key(new).unwrap();
+    assert_eq!(smt.head().size(), 4);
+    assert!(smt.head().verify(&new_pk).is_ok());
+
+    // A held signer on the waiting thread fails fast instead.
+    let signer = LocalSigner::new(KeyPair::new().unwrap());
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(signer.clone());
+    smt.extend((0..3).map(entry));
+    signer.hold(true);
+    let would_block = |r: io::Result<()>| {
+        r.unwrap_err().kind() == io::ErrorKind::WouldBlock
+    };
+    assert!(would_block(smt.submit(entry(3)).map(|_| ())));
+    assert_eq!(smt.queued(), 0);
+    assert!(would_block(smt.checkpoint("example.com/log").map(|_| ())));
+    let new = LocalSigner::new(KeyPair::new().unwrap());
+    assert!(would_block(smt.rotate_key(new).map(|_| ())));
+    assert!(smt.key_transitions().is_empty());
+    assert_eq!(signer.sign_queued(), 3);
+}
+
+struct BrokenSigner(KeyPair);
+
+impl TreeHeadSigner for BrokenSigner {
+    type Key = PubKey;
+
+    fn verifier(&self) -> PubKey {
+        self.0.pub_key()
+    }
+
+    fn sign_tree_head(&self, input: &[u8]) -> PendingSignature {
+        let mut sig = self.0.sign_message(input).unwrap();
+        sig[0] ^= 1;
+        PendingSignature::ready(Ok(sig))
+    }
+}
+
+#[test]
+fn invalid_signatures_are_rejected() {
+    let signer = BrokenSigner(KeyPair::new().unwrap());
+    let err = SignedMerkleTree::<Sha256, _>::try_new(signer).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let (tx, mut pending) = PendingSignature::channel();
+    assert!(pending.try_take().is_none());
+    drop(tx);
+    assert!(pending.try_take().unwrap().is_err());
+}
+
+#[test]
+fn checkpoints_from_deferred_signers() {
+    let kp = KeyPair::new().unwrap();
+    let pk = kp.pub_key();
+    let signer = LocalSigner::new(kp);
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(signer.clone());
+    smt.extend((0..3).map(entry));
+    let cp = smt.checkpoint("example.com/log").unwrap();
+    assert_eq!(cp.checkpoint().tree_head().size(), 3);
+    assert!(cp.verify("example.com/log", &pk).is_ok());
+
+    signer.hold(true);
+    let err = smt.checkpoint("example.com/log").err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    signer.hold(false);
+    assert_eq!(signer.sign_queued(), 1);
+    let cp = smt.checkpoint("example.com/log").unwrap();
+    assert!(cp.verify("example.com/log", &pk).is_ok());
+
+    // Signed notes only know Ed25519 keys.
+    let smt = SignedMerkleTree::<Sha256, _>::new(EcdsaKeyPair::new().unwrap());
+    let err = smt.checkpoint("example.com/log").err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+struct FlakySigner(KeyPair, Rc<Cell<bool>>);
+
+impl TreeHeadSigner for FlakySigner {
+    type Key = PubKey;
+
+    fn verifier(&self) -> PubKey {
+        self.0.pub_key()
+    }
+
+    fn sign_tree_head(&self, input: &[u8]) -> PendingSignature {
+        if self.1.get() {
+            let err = io::Error::new(io::ErrorKind::TimedOut, "offline");
+            return PendingSignature::ready(Err(err));
+        }
+        PendingSignature::ready(Ok(self.0.sign_message(input).unwrap()))
+    }
+}
+
+#[test]
+fn extend_signs_only_new_entries() {
+    let signer = LocalSigner::new(KeyPair::new().unwrap());
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(signer.clone());
+    smt.extend((0..3).map(entry));
+    signer.hold(true);
+    smt.extend((0..3).map(entry));
+    smt.extend(Vec::<Hash<Sha256>>::new());
+    assert_eq!(signer.queued(), 0);
+    assert_eq!(smt.head().size(), 3);
+
+    // Failing signers do not make `extend` panic, the head just lags.
+    let offline = Rc::new(Cell::new(false));
+    let signer = FlakySigner(KeyPair::new().unwrap(), offline.clone());
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(signer);
+    smt.extend((0..3).map(entry));
+    offline.set(true);
+    smt.extend((3..5).map(entry));
+    assert_eq!(smt.len(), 5);
+    assert_eq!(smt.head().size(), 3);
+    assert!(smt.wait_for_head().is_err());
+    offline.set(false);
+    smt.wait_for_head().unwrap();
+    assert_eq!(smt.head().size(), 5);
+}
@@ -0,0 +1,117 @@
+#![cfg(feature = "ring")]
+
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::{Sha256, entry};
+use merkle_rs::signer::{PendingSignature, Signer, TreeHeadSigner};
+use merkle_rs::{DuplicatePolicy, KeyPair, MerkleTree, PubKey,
+                SignedMerkleTree, SignedOwningMerkleTree};
+use std::cell::Cell;
+use std::rc::Rc;
+
+struct CountingSigner {
+    key: KeyPair,
+    count: Rc<Cell<usize>>,
+}
+
+impl TreeHeadSigner for CountingSigner {
+    type Key = PubKey;
+
+    fn verifier(&self) -> PubKey {
+        self.key.pub_key()
+    }
+
+    fn sign_tree_head(&self, input: &[u8]) -> PendingSignature {
+        self.count.set(self.count.get() + 1);
+        PendingSignature::ready(Ok(self.key.sign_message(input).unwrap()))
+    }
+}
+
+#[test]
+fn one_signature_per_commit() {
+    let count = Rc::new(Cell::new(0));
+    let signer = CountingSigner {
+        key: KeyPair::new().unwrap(),
+        count: count.clone(),
+    };
+    let pk = signer.verifier();
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(signer);
+    assert_eq!(count.get(), 1);
+    assert_eq!(smt.stage_many((0..500).map(entry)), 500);
+    assert!(smt.stage(entry(500)));
+    assert_eq!(smt.queued(), 501);
+    assert_eq!(smt.len(), 0);
+    assert_eq!(count.get(), 1);
+    assert_eq!(smt.commit().unwrap(), 501);
+    assert_eq!(count.get(), 2);
+    assert_eq!(smt.head().size(), 501);
+    assert!(smt.head().verify(&pk).is_ok());
+
+    let unsigned: MerkleTree<Sha256> = (0..501).map(entry).collect();
+    assert_eq!(smt.head().root_hash(), unsigned.head().root_hash());
+    assert!(smt.inclusion_proof(entry(250)).unwrap().verify(&pk).is_ok());
+
+    // Entries in the tree are not staged again, and empty commits do not
+    // sign anything.
+    assert!(!smt.stage(entry(3)));
+    assert_eq!(smt.stage_many((499..503).map(entry)), 2);
+    // Neither are staged ones, or repeated ones.
+    assert_eq!(smt.stage_many((500..503).chain(501..503).map(entry)), 0);
+    assert_eq!(smt.queued(), 2);
+    assert_eq!(smt.commit().unwrap(), 2);
+    assert_eq!(smt.commit().unwrap(), 0);
+    assert_eq!(count.get(), 3);
+    assert_eq!(smt.head().size(), 503);
+}
+
+#[test]
+fn owning_tree_staging() {
+    let mut somt =
+        SignedOwningMerkleTree::<String, Sha256>::new(KeyPair::new().unwrap());
+    somt.insert("a".to_string());
+    assert!(!somt.stage("a".to_string()));
+    let n = somt.stage_many(vec!["b".to_string(), "c".to_string()]);
+    assert_eq!(n, 2);
+    let n = somt.stage_many(vec!["c".to_string(), "d".to_string()]);
+    assert_eq!(n, 1);
+    assert_eq!(somt.queued(), 3);
+    assert_eq!(somt.len(), 1);
+    assert_eq!(somt.commit().unwrap(), 3);
+    assert_eq!(somt.get(2).map(String::as_str), Some("c"));
+    assert_eq!(somt.head().size(), 4);
+}
+
+#[test]
+fn commits_match_single_inserts() {
+    let mut smt = SignedMerkleTree::<Sha256>::new(KeyPair::new().unwrap());
+    let mut mt = MerkleTree::<Sha256>::new();
+    for i in 0..7 {
+        smt.insert(entry(i));
+        mt.insert(entry(i));
+    }
+    for batch in &[5..25, 25..26, 20..64] {
+        smt.stage_many(batch.clone().map(entry));
+        smt.stage_many(batch.clone().map(entry));
+        for i in batch.clone() {
+            mt.insert(entry(i));
+        }
+        smt.commit().unwrap();
+        assert_eq!(smt.head().root_hash(), mt.head().root_hash());
+        let size = mt.len();
+        let p = smt.consistency_proof_between(7, size).unwrap();
+        let q = mt.consistency_proof_between(7, size).unwrap();
+        assert_eq!(p.consistency_path(), q.consistency_path());
+    }
+
+    let mut somt =
+        SignedOwningMerkleTree::<String, Sha256>::new(KeyPair::new().unwrap());
+    somt.insert("a".to_string());
+    somt.set_duplicate_policy(DuplicatePolicy::Allow);
+    somt.stage_many(vec!["a".to_string(), "b".to_string(), "a".to_string()]);
+    assert_eq!(somt.commit().unwrap(), 3);
+    assert_eq!(somt.indices_of(&"a".to_string()), vec![0, 1, 3]);
+    assert_eq!(somt.get(2).map(String::as_str), Some("b"));
+}
@@ -0,0 +1,237 @@
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::{Sha256, entry, open, temp_dir};
+use merkle_rs::MerkleTree;
+use merkle_rs::storage::Storage;
+use std::fs::{self, OpenOptions};
+
+#[test]
+fn file_storage_matches_memory() {
+    let dir = temp_dir("matches");
+    let mut mt = MerkleTree::<Sha256>::new();
+    let mut ft = open(&dir);
+    assert!(ft.is_empty());
+    assert_eq!(ft.head().root_hash(), mt.head().root_hash());
+
+    for i in 0..1500 {
+        assert!(mt.insert(entry(i)));
+        assert!(ft.insert(entry(i)));
+        assert!(!ft.insert(entry(i)));
+        if i % 97 == 0 {
+            assert_eq!(ft.head().root_hash(), mt.head().root_hash());
+        }
+    }
+    assert_eq!(ft.len(), 1500);
+    assert_eq!(ft.head().root_hash(), mt.head().root_hash());
+
+    for i in (0..1500).step_by(13) {
+        assert_eq!(ft.index_of(entry(i)), Some(u64::from(i)));
+        let p = ft.inclusion_proof(entry(i)).unwrap();
+        assert_eq!(
+            p.audit_path(),
+            mt.inclusion_proof(entry(i)).unwrap().audit_path()
+        );
+        assert!(p.verify_against(&mt.head()).is_ok());
+        assert_eq!(ft.leaf_hash(u64::from(i)), mt.leaf_hash(u64::from(i)));
+    }
+    assert!(!ft.contains(entry(1500)));
+
+    let new = mt.head_at(1300).unwrap();
+    for old in (0..1300).step_by(111) {
+        let p = ft.consistency_proof_between(old, 1300).unwrap();
+        let old = mt.head_at(old).unwrap();
+        assert!(p.verify_against(&old, &new).is_ok());
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn file_storage_reopen() {
+    let dir = temp_dir("reopen");
+    let mut mt = MerkleTree::<Sha256>::new();
+    {
+        let mut ft = open(&dir);
+        for i in 0..700 {
+            mt.insert(entry(i));
+            ft.insert(entry(i));
+        }
+        ft.storage().sync().unwrap();
+    }
+
+    let mut ft = open(&dir);
+    assert_eq!(ft.len(), 700);
+    assert_eq!(ft.head().root_hash(), mt.head().root_hash());
+    assert_eq!(ft.index_of(entry(345)), Some(345));
+    assert!(!ft.insert(entry(5)));
+    for i in 700..900 {
+        mt.insert(entry(i));
+        ft.insert(entry(i));
+    }
+    assert_eq!(ft.head().root_hash(), mt.head().root_hash());
+    assert_eq!(ft.append_many((890..950).map(entry)).unwrap(), 900..950);
+    mt.extend((900..950).map(entry));
+    assert_eq!(ft.head().root_hash(), mt.head().root_hash());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn file_storage_repairs_interrupted_appends() {
+    let dir = temp_dir("repair");
+    let mut mt = MerkleTree::<Sha256>::new();
+    {
+        let mut ft = open(&dir);
+        for i in 0..300 {
+            mt.insert(entry(i));
+            ft.insert(entry(i));
+        }
+    }
+
+    // Lose the tail of the node files and the index, and leave half an
+    // entry behind.
+    for name in &["nodes-0", "nodes-3", "index"] {
+        let f = OpenOptions::new().write(true).open(dir.join(name)).unwrap();
+        let len = f.metadata().unwrap().len();
+        f.set_len(len / 2).unwrap();
+    }
+    let f = OpenOptions::new()
+        .append(true)
+        .open(dir.join("entries"))
+        .unwrap();
+    f.set_len(300 * 32 + 7).unwrap();
+
+    let ft = open(&dir);
+    assert_eq!(ft.storage().len(), 300);
+    assert_eq!(ft.head().root_hash(), mt.head().root_hash());
+    for i in 0..300 {
+        assert_eq!(ft.index_of(entry(i)), Some(u64::from(i)));
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn file_storage_rolls_back_failed_appends() {
+    let dir = temp_dir("rollback");
+    let mt: MerkleTree<Sha256> = (0..20).map(entry).collect();
+    let mut ft = open(&dir);
+    ft.insert(entry(0));
+
+    // The second entry completes the first node of level 1, whose file
+    // cannot be created while a directory takes its place. The entry has
+    // been written by then and has to be removed again.
+    fs::create_dir(dir.join("nodes-1")).unwrap();
+    for _ in 0..2 {
+        assert!(ft.try_insert(entry(1)).is_err());
+        assert_eq!(ft.len(), 1);
+        let entries = fs::metadata(dir.join("entries")).unwrap().len();
+        assert_eq!(entries, 32);
+        let nodes = fs::metadata(dir.join("nodes-0")).unwrap().len();
+        assert_eq!(nodes, 32);
+    }
+    fs::remove_dir(dir.join("nodes-1")).unwrap();
+
+    for i in 1..20 {
+        assert!(ft.insert(entry(i)));
+    }
+    assert_eq!(ft.head().root_hash(), mt.head().root_hash());
+    assert_eq!(ft.indices_of(entry(1)), vec![1]);
+    drop(ft);
+    let ft = open(&dir);
+    assert_eq!(ft.len(), 20);
+    assert_eq!(ft.head().root_hash(), mt.head().root_hash());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn file_storage_reports_failed_reads() {
+    let dir = temp_dir("reads");
+    let mut ft = open(&dir);
+    for i in 0..10 {
+        ft.insert(entry(i));
+    }
+
+    // Cut the files short behind the back of the open storage.
+    for name in &["entries", "nodes-0", "nodes-1"] {
+        let f = OpenOptions::new().write(true).open(dir.join(name)).unwrap();
+        f.set_len(0).unwrap();
+    }
+    assert!(ft.try_head().is_err());
+    assert!(ft.try_head_at(3).is_err());
+    assert!(ft.try_leaf_hash(4).is_err());
+    assert!(ft.try_index_of(entry(7)).is_err());
+    assert!(ft.try_inclusion_proof_by_index(2).is_err());
+    assert!(ft.try_consistency_proof(5).is_err());
+    assert!(ft.try_insert(entry(7)).is_err());
+
+    // Requests the tree cannot answer do not read anything.
+    assert!(ft.try_head_at(11).unwrap().is_none());
+    assert_eq!(ft.try_head_at(0).unwrap().unwrap().size(), 0);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "memmap")]
+#[test]
+fn mapped_tree() {
+    use merkle_rs::MappedMerkleTree;
+    use merkle_rs::storage::MappedStorage;
+
+    let dir = temp_dir("mapped");
+    let mt: MerkleTree<Sha256> = (0..1100).map(entry).collect();
+    mt.save(&dir).unwrap();
+    assert!(mt.save(&dir).is_err());
+
+    let mut mapped: MappedMerkleTree<Sha256> =
+        MerkleTree::with_storage(MappedStorage::open(&dir).unwrap());
+    assert_eq!(mapped.len(), 1100);
+    assert_eq!(mapped.head().root_hash(), mt.head().root_hash());
+    for i in (0..1100).step_by(7) {
+        assert_eq!(mapped.index_of(entry(i)), Some(u64::from(i)));
+        let p = mapped.inclusion_proof(entry(i)).unwrap();
+        assert!(p.verify_against(&mt.head()).is_ok());
+    }
+    assert!(!mapped.contains(entry(1100)));
+    let p = mapped.consistency_proof(517).unwrap();
+    assert!(p.verify_against(&mt.head_at(517).unwrap(), &mt.head()).is_ok());
+    assert!(mapped.try_insert(entry(1100)).is_err());
+
+    // Appending through a FileStorage does not affect the mapped view.
+    let mut ft = open(&dir);
+    assert_eq!(ft.head().root_hash(), mt.head().root_hash());
+    ft.insert(entry(1100));
+    assert_eq!(mapped.len(), 1100);
+    assert_eq!(mapped.head().root_hash(), mt.head().root_hash());
+    let remapped = MappedStorage::<Sha256>::open(&dir).unwrap();
+    assert_eq!(remapped.len(), 1101);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "memmap")]
+#[test]
+fn mapped_tree_rejects_unclean_files() {
+    use merkle_rs::storage::MappedStorage;
+
+    let dir = temp_dir("unclean");
+    let mt: MerkleTree<Sha256> = (0..10).map(entry).collect();
+    mt.save(&dir).unwrap();
+    let f = OpenOptions::new()
+        .write(true)
+        .open(dir.join("nodes-1"))
+        .unwrap();
+    f.set_len(32).unwrap();
+    assert!(MappedStorage::<Sha256>::open(&dir).is_err());
+
+    // FileStorage repairs the tree, after which it can be mapped again.
+    assert_eq!(open(&dir).head().root_hash(), mt.head().root_hash());
+    assert!(MappedStorage::<Sha256>::open(&dir).is_ok());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
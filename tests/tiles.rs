@@ -0,0 +1,175 @@
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::{Sha256, entry};
+use merkle_rs::MerkleTree;
+use merkle_rs::tiles::{self, Tile, TileReader};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io;
+
+#[test]
+fn tile_paths() {
+    let cases = [
+        ((0, 0, 256), "tile/0/000"),
+        ((0, 1234067, 256), "tile/0/x001/x234/067"),
+        ((1, 5, 17), "tile/1/005.p/17"),
+        ((3, 1000, 1), "tile/3/x001/000.p/1"),
+    ];
+    for &((level, index, width), path) in &cases {
+        let tile = Tile::new(level, index, width).unwrap();
+        assert_eq!(tile.path(), path);
+        assert_eq!(Tile::from_path(path), Some(tile));
+    }
+
+    assert!(Tile::new(0, 0, 0).is_none());
+    assert!(Tile::new(0, 0, 257).is_none());
+    for path in &[
+        "tile/0/1234",
+        "tile/0/x001",
+        "tile/0/01",
+        "tile/0/001.p/256",
+        "tile/0/001.p/0",
+        "tile/0/001/002",
+        "tile/0/000.p/5/6",
+        "tile/8/000",
+        "tile/entries/000",
+        "tiles/0/000",
+    ] {
+        assert_eq!(Tile::from_path(path), None, "{}", path);
+    }
+}
+
+#[test]
+fn tiles_of_a_tree() {
+    assert!(tiles::tiles(0).is_empty());
+    assert_eq!(tiles::tiles(1), vec![Tile::new(0, 0, 1).unwrap()]);
+    assert_eq!(
+        tiles::tiles(256),
+        vec![Tile::new(0, 0, 256).unwrap(), Tile::new(1, 0, 1).unwrap()]
+    );
+
+    // 70000 = 273 * 256 + 112, and 273 = 256 + 17.
+    let all = tiles::tiles(70000);
+    assert_eq!(all.len(), 274 + 2 + 1);
+    assert_eq!(all[272], Tile::new(0, 272, 256).unwrap());
+    assert_eq!(all[273], Tile::new(0, 273, 112).unwrap());
+    assert_eq!(all[274], Tile::new(1, 0, 256).unwrap());
+    assert_eq!(all[275], Tile::new(1, 1, 17).unwrap());
+    assert_eq!(all[276], Tile::new(2, 0, 1).unwrap());
+
+    // Publishing the new tiles after every step ends up with all tiles.
+    let mut published = HashMap::new();
+    let mut size = 0;
+    for &next in &[1, 2, 255, 256, 300, 511, 513, 70000] {
+        for t in tiles::new_tiles(size, next) {
+            published.insert((t.level(), t.index()), t);
+        }
+        size = next;
+    }
+    let mut published: Vec<_> = published.into_iter().collect();
+    published.sort_by_key(|&(k, _)| k);
+    let published: Vec<_> = published.into_iter().map(|(_, t)| t).collect();
+    assert_eq!(published, all);
+}
+
+#[test]
+fn tile_contents() {
+    let mt: MerkleTree<Sha256> = (0..600).map(entry).collect();
+    let leaves: Vec<u8> = (0..600)
+        .flat_map(|i| mt.leaf_hash(i).unwrap().to_vec())
+        .collect();
+
+    let t = Tile::new(0, 1, 256).unwrap();
+    assert_eq!(mt.tile(&t).unwrap(), &leaves[256 * 32..512 * 32]);
+    let t = Tile::new(0, 2, 88).unwrap();
+    assert_eq!(mt.tile(&t).unwrap(), &leaves[512 * 32..]);
+    assert!(mt.tile(&Tile::new(0, 2, 89).unwrap()).is_none());
+
+    let t = Tile::new(1, 0, 2).unwrap();
+    let data = mt.tile(&t).unwrap();
+    assert_eq!(&data[..32], &mt.head_at(256).unwrap().root_hash()[..]);
+    assert!(mt.tile(&Tile::new(1, 0, 3).unwrap()).is_none());
+}
+
+#[test]
+fn proofs_from_tiles() {
+    let mt: MerkleTree<Sha256> = (0..66000).map(entry).collect();
+    let fetches = Cell::new(0);
+    let source = |t: &Tile| {
+        fetches.set(fetches.get() + 1);
+        mt.tile(t)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no tile"))
+    };
+
+    for &size in &[1, 255, 256, 600, 65536, 66000] {
+        let head = mt.head_at(size).unwrap();
+        let reader = TileReader::new(head.clone(), &source);
+        for &i in &[0, size / 3, (size - 1) / 2, size - 1] {
+            let p = reader.inclusion_proof(i).unwrap().unwrap();
+            let expected = mt.inclusion_proof_at(entry(i as u32), size);
+            assert_eq!(p.audit_path(), expected.unwrap().audit_path());
+            assert_eq!(reader.leaf_hash(i).unwrap(), mt.leaf_hash(i));
+            assert!(p.verify().is_ok());
+        }
+        assert!(reader.inclusion_proof(size).unwrap().is_none());
+
+        for &old in &[0, 1, size / 2, size - 1, size] {
+            let p = reader.consistency_proof(old).unwrap().unwrap();
+            let old = mt.head_at(old).unwrap();
+            assert!(p.verify_against(&old, &head).is_ok());
+        }
+        assert!(reader.consistency_proof(size + 1).unwrap().is_none());
+    }
+
+    // Tiles are cached by the reader.
+    let reader = TileReader::new(mt.head(), &source);
+    reader.inclusion_proof(1000).unwrap();
+    let before = fetches.get();
+    reader.inclusion_proof(1001).unwrap();
+    assert_eq!(fetches.get(), before);
+}
+
+#[test]
+fn bad_tiles() {
+    let mt: MerkleTree<Sha256> = (0..300).map(entry).collect();
+
+    let short = |t: &Tile| Ok(mt.tile(t).unwrap()[1..].to_vec());
+    let reader = TileReader::new(mt.head(), short);
+    assert!(reader.inclusion_proof(3).is_err());
+
+    let failing =
+        |_: &Tile| Err(io::Error::new(io::ErrorKind::TimedOut, "offline"));
+    let reader = TileReader::new(mt.head(), failing);
+    assert!(reader.consistency_proof(10).is_err());
+
+    let tampered = |t: &Tile| {
+        let mut data = mt.tile(t).unwrap();
+        data[0] ^= 1;
+        Ok(data)
+    };
+    let reader = TileReader::new(mt.head(), tampered);
+    let p = reader.inclusion_proof(299).unwrap().unwrap();
+    assert!(p.verify().is_err());
+}
+
+#[test]
+fn out_of_range_tiles() {
+    let mt: MerkleTree<Sha256> = (0..300).map(entry).collect();
+    let tile = |level, index, width| {
+        mt.tile(&Tile::new(level, index, width).unwrap())
+    };
+    let full = tiles::TILE_WIDTH;
+    assert!(tile(0, 0, full).is_some());
+    // Levels past the top of a 64 bit tree, including ones whose height
+    // overflows or would wrap around to level 0.
+    assert!(tile(8, 0, full).is_none());
+    assert!(tile(0x2000_0000, 0, full).is_none());
+    assert!(tile(u32::MAX, 0, 1).is_none());
+    // Indices whose first or last node overflows.
+    let last = u64::MAX / full;
+    assert!(tile(0, last, full).is_none());
+    assert!(tile(0, last + 1, 1).is_none());
+}
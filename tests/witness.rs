@@ -0,0 +1,253 @@
+#![cfg(feature = "ring")]
+
+extern crate merkle_rs;
+extern crate sha2;
+
+mod common;
+
+use common::{Sha256, entry};
+use merkle_rs::checkpoint::{Checkpoint, SignedCheckpoint};
+use merkle_rs::proof::VerificationError;
+use merkle_rs::witness::{Policy, Witness, WitnessError};
+use merkle_rs::{EcdsaKeyPair, KeyPair, MerkleTree, SignedMerkleTree,
+                TreeHead};
+
+const ORIGIN: &str = "example.com/log";
+
+fn signed(kp: &KeyPair, th: TreeHead<Sha256>) -> SignedCheckpoint<Sha256> {
+    let mut cp = SignedCheckpoint::new(Checkpoint::new(ORIGIN, th).unwrap());
+    cp.sign(ORIGIN, kp).unwrap();
+    cp
+}
+
+#[test]
+fn witness_cosigns_consistent_checkpoints() {
+    let log = KeyPair::new().unwrap();
+    let mut witness = Witness::new("witness", KeyPair::new().unwrap()).unwrap();
+    witness.add_log(ORIGIN, &log.pub_key());
+    let mut mt: MerkleTree<Sha256> = (0..5).map(entry).collect();
+
+    let mut cp = signed(&log, mt.head());
+    let proof = mt.consistency_proof_between(0, 5).unwrap();
+    witness.cosign(&mut cp, &proof).unwrap();
+    assert_eq!(witness.latest(ORIGIN).unwrap().size(), 5);
+
+    // The cosignature survives the note encoding and does not disturb the
+    // log signature.
+    let cp = SignedCheckpoint::<Sha256>::from_note(&cp.to_note()).unwrap();
+    assert!(cp.verify(ORIGIN, &log.pub_key()).is_ok());
+    assert!(cp.verify_cosignature("witness", &witness.pub_key()).is_ok());
+    assert!(cp.verify_cosignature("other", &witness.pub_key()).is_err());
+    assert!(cp.verify("witness", &witness.pub_key()).is_err());
+
+    mt.extend((5..12).map(entry));
+    let mut cp = signed(&log, mt.head());
+    let stale = mt.consistency_proof_between(3, 12).unwrap();
+    assert_eq!(witness.cosign(&mut cp, &stale), Err(WitnessError::Conflict));
+    let proof = mt.consistency_proof_between(5, 12).unwrap();
+    witness.cosign(&mut cp, &proof).unwrap();
+    assert_eq!(witness.latest(ORIGIN).unwrap().size(), 12);
+    assert_eq!(cp.signatures().len(), 2);
+
+    // Heads are only cosigned if the log signed them.
+    let mut forged = signed(&KeyPair::new().unwrap(), mt.head());
+    let proof = mt.consistency_proof_between(12, 12).unwrap();
+    assert_eq!(
+        witness.cosign(&mut forged, &proof),
+        Err(WitnessError::Verification(VerificationError::BadSignature))
+    );
+    let mut unknown = SignedCheckpoint::new(
+        Checkpoint::new("example.com/other", mt.head()).unwrap(),
+    );
+    unknown.sign("example.com/other", &log).unwrap();
+    assert_eq!(
+        witness.cosign(&mut unknown, &proof),
+        Err(WitnessError::UnknownLog)
+    );
+    assert!(Witness::<Sha256>::new("a b", KeyPair::new().unwrap()).is_err());
+}
+
+#[test]
+fn witness_refuses_split_views() {
+    let log = KeyPair::new().unwrap();
+    let mut witness = Witness::new("witness", KeyPair::new().unwrap()).unwrap();
+    witness.add_log(ORIGIN, &log.pub_key());
+
+    let honest: MerkleTree<Sha256> = (0..8).map(entry).collect();
+    let mut cp = signed(&log, honest.head_at(6).unwrap());
+    let proof = honest.consistency_proof_between(0, 6).unwrap();
+    witness.cosign(&mut cp, &proof).unwrap();
+
+    // A view sharing the first four entries only.
+    let fork: MerkleTree<Sha256> =
+        (0..4).chain(100..104).map(entry).collect();
+    let mut cp = signed(&log, fork.head());
+    let proof = fork.consistency_proof_between(6, 8).unwrap();
+    assert!(witness.cosign(&mut cp, &proof).is_err());
+    let mut cp = signed(&log, fork.head_at(6).unwrap());
+    let proof = fork.consistency_proof_between(6, 6).unwrap();
+    assert_eq!(
+        witness.cosign(&mut cp, &proof),
+        Err(WitnessError::Verification(VerificationError::RootMismatch))
+    );
+    assert_eq!(cp.signatures().len(), 1);
+
+    // Rejected heads leave the witness where it was.
+    let latest = witness.latest(ORIGIN).unwrap();
+    assert_eq!(latest.root_hash(), honest.head_at(6).unwrap().root_hash());
+    let mut cp = signed(&log, honest.head());
+    let proof = honest.consistency_proof_between(6, 8).unwrap();
+    witness.cosign(&mut cp, &proof).unwrap();
+
+    // So do attempts to roll the log back.
+    let mut cp = signed(&log, honest.head_at(7).unwrap());
+    let proof = honest.consistency_proof_between(8, 8).unwrap();
+    assert!(witness.cosign(&mut cp, &proof).is_err());
+    assert_eq!(witness.latest(ORIGIN).unwrap().size(), 8);
+}
+
+#[test]
+fn checkpoint_policy() {
+    let log = KeyPair::new().unwrap();
+    let mut witnesses: Vec<Witness<Sha256>> = (0..5)
+        .map(|i| {
+            let name = format!("witness{}", i);
+            Witness::new(&name, KeyPair::new().unwrap()).unwrap()
+        })
+        .collect();
+    let mut policy = Policy::new(ORIGIN, log.pub_key(), 2);
+    for w in &mut witnesses {
+        w.add_log(ORIGIN, &log.pub_key());
+        policy.add_witness(w.name(), w.pub_key());
+    }
+
+    let mt: MerkleTree<Sha256> = (0..3).map(entry).collect();
+    let proof = mt.consistency_proof_between(0, 3).unwrap();
+    let mut cp = signed(&log, mt.head());
+    assert_eq!(
+        policy.verify_checkpoint(&cp),
+        Err(VerificationError::MissingCosignatures)
+    );
+    witnesses[1].cosign(&mut cp, &proof).unwrap();
+    assert_eq!(
+        policy.verify_checkpoint(&cp),
+        Err(VerificationError::MissingCosignatures)
+    );
+
+    // A witness outside of the policy does not count.
+    let mut outsider = Witness::new("witness3", KeyPair::new().unwrap())
+        .unwrap();
+    outsider.add_log(ORIGIN, &log.pub_key());
+    outsider.cosign(&mut cp, &proof).unwrap();
+    assert!(policy.verify_checkpoint(&cp).is_err());
+
+    witnesses[4].cosign(&mut cp, &proof).unwrap();
+    assert!(policy.verify_checkpoint(&cp).is_ok());
+
+    // Neither does a witness listed twice.
+    let mut policy = Policy::new(ORIGIN, log.pub_key(), 2);
+    policy.add_witness("witness1", witnesses[1].pub_key());
+    policy.add_witness("witness1", witnesses[1].pub_key());
+    assert!(policy.verify_checkpoint(&cp).is_err());
+
+    let policy: Policy = Policy::new("example.com/other", log.pub_key(), 0);
+    assert_eq!(
+        policy.verify_checkpoint(&cp),
+        Err(VerificationError::BadSignature)
+    );
+}
+
+#[test]
+fn cosigned_tree_heads() {
+    let log = KeyPair::new().unwrap();
+    let log_key = log.pub_key();
+    let mut a = Witness::new("a", KeyPair::new().unwrap()).unwrap();
+    let mut b = Witness::new("b", KeyPair::new().unwrap()).unwrap();
+    a.add_log(ORIGIN, &log.pub_key());
+    b.add_log(ORIGIN, &log.pub_key());
+    let mut policy = Policy::new(ORIGIN, log.pub_key(), 2);
+    let mut smt = SignedMerkleTree::<Sha256>::new(log);
+    policy.add_witness("a", a.pub_key());
+    policy.add_witness("b", b.pub_key());
+
+    smt.extend((0..9).map(entry));
+    let mut sth = smt.head();
+    let proof = smt.consistency_proof_between(0, 9).unwrap();
+    a.cosign_tree_head(ORIGIN, &mut sth, &proof).unwrap();
+    assert!(sth.verify(&log_key).is_ok());
+    assert!(sth.verify_cosignature(&a.pub_key()).is_ok());
+    assert!(sth.verify_cosignature(&b.pub_key()).is_err());
+    assert_eq!(
+        policy.verify_tree_head(&sth),
+        Err(VerificationError::MissingCosignatures)
+    );
+
+    b.cosign_tree_head(ORIGIN, &mut sth, &proof).unwrap();
+    assert_eq!(sth.cosignatures().len(), 2);
+    assert!(policy.verify_tree_head(&sth).is_ok());
+
+    // Cosignatures do not carry over to other heads.
+    smt.insert(entry(9));
+    let mut next = smt.head();
+    for c in sth.cosignatures() {
+        next.add_cosignature(c.clone());
+    }
+    assert!(policy.verify_tree_head(&next).is_err());
+
+    // Witnesses share their state between checkpoints and tree heads.
+    let mut cp = smt.checkpoint(ORIGIN).unwrap();
+    let proof = smt.consistency_proof_between(0, 10).unwrap();
+    assert_eq!(a.cosign(&mut cp, &proof), Err(WitnessError::Conflict));
+    let proof = smt.consistency_proof_between(9, 10).unwrap();
+    a.cosign(&mut cp, &proof).unwrap();
+    assert_eq!(
+        a.cosign_tree_head(ORIGIN, &mut next, &proof),
+        Err(WitnessError::Conflict)
+    );
+    let proof = smt.consistency_proof_between(10, 10).unwrap();
+    assert!(a.cosign_tree_head(ORIGIN, &mut next, &proof).is_ok());
+}
+
+#[test]
+fn ecdsa_logs_and_witnesses() {
+    let log = EcdsaKeyPair::new().unwrap();
+    let log_key = log.pub_key();
+    let mut ecdsa = Witness::new("ecdsa", EcdsaKeyPair::new().unwrap())
+        .unwrap();
+    let mut ed25519 = Witness::new("ed25519", KeyPair::new().unwrap())
+        .unwrap();
+    ecdsa.add_log(ORIGIN, &log_key);
+    ed25519.add_log(ORIGIN, &log_key);
+    let mut smt = SignedMerkleTree::<Sha256, _>::new(log);
+    smt.extend((0..4).map(entry));
+
+    let mut sth = smt.head();
+    let proof = smt.consistency_proof_between(0, 4).unwrap();
+    ecdsa.cosign_tree_head(ORIGIN, &mut sth, &proof).unwrap();
+    ed25519.cosign_tree_head(ORIGIN, &mut sth, &proof).unwrap();
+    assert!(sth.verify_cosignature(&ecdsa.pub_key()).is_ok());
+    assert!(sth.verify_cosignature(&ed25519.pub_key()).is_ok());
+
+    let mut policy = Policy::new(ORIGIN, log_key.clone(), 1);
+    policy.add_witness("ecdsa", ecdsa.pub_key());
+    assert!(policy.verify_tree_head(&sth).is_ok());
+    let other = EcdsaKeyPair::new().unwrap().pub_key();
+    let mut policy = Policy::new(ORIGIN, log_key, 1);
+    policy.add_witness("other", other);
+    assert_eq!(
+        policy.verify_tree_head(&sth),
+        Err(VerificationError::MissingCosignatures)
+    );
+
+    // Heads of an ECDSA log are checked with its key.
+    smt.insert(entry(4));
+    let mut next = smt.head();
+    let forged = KeyPair::new().unwrap().pub_key();
+    let mut fooled = Witness::new("fooled", KeyPair::new().unwrap()).unwrap();
+    fooled.add_log(ORIGIN, &forged);
+    let proof = smt.consistency_proof_between(0, 5).unwrap();
+    assert_eq!(
+        fooled.cosign_tree_head(ORIGIN, &mut next, &proof),
+        Err(WitnessError::Verification(VerificationError::BadSignature))
+    );
+}